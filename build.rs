@@ -0,0 +1,462 @@
+//! Build script generating the radiation counter command set
+//!
+//! The Interface Control Document in `icd/commands.toml` is the source of truth
+//! for the command set. This script generates from it:
+//!
+//! - `opcodes.rs` - Opcode and channel constants for every command and telemetry item
+//! - `commands/<module>.rs` - `command` and `parse` functions, included by `src/commands`
+//! - `reset_telemetry.rs` - The `make_reset_telemetry!` invocation for the reset `Type` enum,
+//!   `command` and `parse` functions
+//! - `ICD.md` - Markdown rendering of the ICD
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+const ICD_PATH: &str = "icd/commands.toml";
+
+#[derive(Deserialize)]
+struct Icd {
+    device: Device,
+    #[serde(default)]
+    command: Vec<CommandDef>,
+    reset_reply: ResetReply,
+    #[serde(default)]
+    reset_telemetry: Vec<ResetDef>,
+    #[serde(default)]
+    housekeeping: Vec<HousekeepingDef>,
+}
+
+#[derive(Deserialize)]
+struct Device {
+    name: String,
+    inter_command_delay_ms: u64,
+}
+
+#[derive(Deserialize)]
+struct CommandDef {
+    name: String,
+    module: Option<String>,
+    opcode: u8,
+    data: Option<Vec<u8>>,
+    argument: Option<String>,
+    rx_len: Option<usize>,
+    delay_ms: Option<u64>,
+    response: String,
+    label: Option<String>,
+    summary: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Deserialize)]
+struct ResetReply {
+    rx_len: usize,
+    delay_ms: u64,
+}
+
+#[derive(Deserialize)]
+struct ResetDef {
+    name: String,
+    opcode: u8,
+    summary: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Deserialize)]
+struct HousekeepingDef {
+    name: String,
+    channel: u8,
+    unit: String,
+    summary: String,
+}
+
+impl CommandDef {
+    fn data(&self) -> Vec<u8> {
+        self.data.clone().unwrap_or_else(|| vec![0x00])
+    }
+
+    fn label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.summary)
+    }
+
+    /// Rust return type, reply length and value expression of the generated `parse` function
+    fn response(&self) -> Option<(&'static str, usize, &'static str)> {
+        match self.response.as_str() {
+            "none" => None,
+            "u8" => Some(("u8", 2, "data[1]")),
+            "error_code" => Some(("ErrorCode", 2, "ErrorCode::from_u8(data[1])")),
+            // Raw ADC readings are converted by the telemetry sets in `src/telemetry`
+            "adc" if self.module.is_none() => None,
+            "counts" => Some((
+                "RCHk",
+                6,
+                "RCHk {
+            rc1_reading: i16::from_be_bytes([data[0], data[1]]),
+            rc2_reading: i16::from_be_bytes([data[2], data[3]]),
+            rc3_reading: i16::from_be_bytes([data[4], data[5]]),
+        }",
+            )),
+            "board_info" => Some((
+                "BoardInfo",
+                9,
+                "BoardInfo {
+            board_revision: data[1],
+            firmware_major: data[2],
+            firmware_minor: data[3],
+            firmware_patch: data[4],
+            serial: u32::from_be_bytes([data[5], data[6], data[7], data[8]]),
+        }",
+            )),
+            other => panic!("{}: unknown response layout '{}'", self.name, other),
+        }
+    }
+
+    /// Whether byte 0 of the reply is reserved, so a reply starting with the
+    /// 0xFFFF error value cannot be valid
+    fn reserved_first_byte(&self) -> bool {
+        matches!(self.response.as_str(), "u8" | "error_code" | "board_info")
+    }
+
+    fn validate(&self) {
+        match (self.response(), self.rx_len) {
+            (None, Some(2)) if self.response == "adc" => {}
+            (None, _) if self.response == "adc" => {
+                panic!("{}: `rx_len` must be 2 for the 'adc' response", self.name)
+            }
+            (Some(_), None) => panic!("{}: commands with a response require `rx_len`", self.name),
+            (Some((_, len, _)), Some(rx_len)) if len != rx_len => panic!(
+                "{}: `rx_len` {} does not match the {} byte '{}' response",
+                self.name, rx_len, len, self.response
+            ),
+            (None, Some(_)) => panic!("{}: write-only commands take no `rx_len`", self.name),
+            _ => {}
+        }
+        if self.rx_len.is_some() != self.delay_ms.is_some() {
+            panic!("{}: `delay_ms` is required with `rx_len`", self.name);
+        }
+        if self.argument.is_some() && self.data.is_some() {
+            panic!("{}: `argument` and `data` are mutually exclusive", self.name);
+        }
+    }
+}
+
+fn const_name(name: &str) -> String {
+    name.to_uppercase()
+}
+
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.push(c.to_ascii_lowercase());
+    }
+    out
+}
+
+fn doc_lines(out: &mut String, indent: &str, summary: &str, description: &str) {
+    writeln!(out, "{}/// {}", indent, summary).unwrap();
+    if !description.trim().is_empty() {
+        writeln!(out, "{}///", indent).unwrap();
+        for line in description.trim().lines() {
+            if line.is_empty() {
+                writeln!(out, "{}///", indent).unwrap();
+            } else {
+                writeln!(out, "{}/// {}", indent, line).unwrap();
+            }
+        }
+    }
+}
+
+fn hex_bytes(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("0x{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn gen_opcodes(icd: &Icd) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "/// Required delay between two commands sent to the {}, in milliseconds",
+        icd.device.name
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "pub const INTER_COMMAND_DELAY_MS: u64 = {};\n",
+        icd.device.inter_command_delay_ms
+    )
+    .unwrap();
+    for cmd in &icd.command {
+        writeln!(out, "/// {}", cmd.summary).unwrap();
+        writeln!(
+            out,
+            "pub const {}: u8 = 0x{:02X};",
+            const_name(&cmd.name),
+            cmd.opcode
+        )
+        .unwrap();
+    }
+    for item in &icd.reset_telemetry {
+        writeln!(out, "/// {}", item.summary).unwrap();
+        writeln!(
+            out,
+            "pub const RESET_{}: u8 = 0x{:02X};",
+            const_name(&snake_case(&item.name)),
+            item.opcode
+        )
+        .unwrap();
+    }
+    for item in &icd.housekeeping {
+        writeln!(out, "/// {} channel ({})", item.summary, item.unit).unwrap();
+        writeln!(
+            out,
+            "pub const HK_{}: u8 = 0x{:02X};",
+            const_name(&snake_case(&item.name)),
+            item.channel
+        )
+        .unwrap();
+    }
+    out
+}
+
+fn gen_functions(out: &mut String, indent: &str, cmd: &CommandDef) {
+    let (arg, data) = match &cmd.argument {
+        Some(arg) => (format!("{}: u8", arg), arg.clone()),
+        None => (String::new(), hex_bytes(&cmd.data())),
+    };
+
+    if let (Some((ty, _, value)), Some(rx_len), Some(delay_ms)) =
+        (cmd.response(), cmd.rx_len, cmd.delay_ms)
+    {
+        let value = value.replace('\n', &format!("\n{}", indent));
+        let check = if cmd.reserved_first_byte() {
+            " && data[..2] != [0xFF, 0xFF]"
+        } else {
+            ""
+        };
+        write!(
+            out,
+            "{i}/// Parses the {summary} reply\n\
+             {i}pub fn parse(data: &[u8]) -> CounterResult<{ty}> {{\n\
+             {i}    if data.len() == {rx_len}{check} {{\n\
+             {i}        Ok({value})\n\
+             {i}    }} else {{\n\
+             {i}        Err(CounterError::parsing_failure(\"{label}\"))\n\
+             {i}    }}\n\
+             {i}}}\n\n",
+            i = indent,
+            summary = cmd.summary,
+            ty = ty,
+            rx_len = rx_len,
+            check = check,
+            value = value,
+            label = cmd.label(),
+        )
+        .unwrap();
+        write!(
+            out,
+            "{i}/// Builds the {summary} command, expected reply length and the delay\n\
+             {i}/// before reading back the reply\n\
+             {i}pub fn command({arg}) -> (Command, usize, Duration) {{\n\
+             {i}    (\n\
+             {i}        Command {{\n\
+             {i}            cmd: 0x{opcode:02X},\n\
+             {i}            data: vec![{data}],\n\
+             {i}        }},\n\
+             {i}        {rx_len},\n\
+             {i}        Duration::from_millis({delay_ms}),\n\
+             {i}    )\n\
+             {i}}}\n",
+            i = indent,
+            summary = cmd.summary,
+            arg = arg,
+            opcode = cmd.opcode,
+            data = data,
+            rx_len = rx_len,
+            delay_ms = delay_ms,
+        )
+        .unwrap();
+    } else {
+        write!(
+            out,
+            "{i}/// Builds the {summary} command\n\
+             {i}pub fn command({arg}) -> Command {{\n\
+             {i}    Command {{\n\
+             {i}        cmd: 0x{opcode:02X},\n\
+             {i}        data: vec![{data}],\n\
+             {i}    }}\n\
+             {i}}}\n",
+            i = indent,
+            summary = cmd.summary,
+            arg = arg,
+            opcode = cmd.opcode,
+            data = data,
+        )
+        .unwrap();
+    }
+}
+
+fn gen_modules(icd: &Icd) -> BTreeMap<String, String> {
+    let mut modules: BTreeMap<String, String> = BTreeMap::new();
+    for cmd in &icd.command {
+        let module = match &cmd.module {
+            Some(module) => module,
+            None => continue,
+        };
+        let out = modules.entry(module.clone()).or_default();
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        if &cmd.name == module {
+            gen_functions(out, "", cmd);
+        } else {
+            doc_lines(out, "", &cmd.summary, &cmd.description);
+            writeln!(out, "pub mod {} {{", cmd.name).unwrap();
+            writeln!(out, "    use super::*;\n").unwrap();
+            gen_functions(out, "    ", cmd);
+            writeln!(out, "}}").unwrap();
+        }
+    }
+    modules
+}
+
+fn gen_reset_telemetry(icd: &Icd) -> String {
+    let mut out = String::from("make_reset_telemetry!(\n");
+    writeln!(
+        out,
+        "    {}, {};",
+        icd.reset_reply.rx_len, icd.reset_reply.delay_ms
+    )
+    .unwrap();
+    for item in &icd.reset_telemetry {
+        doc_lines(&mut out, "    ", &item.summary, &item.description);
+        writeln!(out, "    {} => 0x{:02X},", item.name, item.opcode).unwrap();
+    }
+    out.push_str(");\n");
+    out
+}
+
+fn gen_markdown(icd: &Icd) -> String {
+    let mut out = String::new();
+    writeln!(out, "# {} Interface Control Document\n", icd.device.name).unwrap();
+    writeln!(
+        out,
+        "Generated from `{}`. Do not edit by hand.\n",
+        ICD_PATH
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "A delay of {} ms is required between two consecutive commands.\n",
+        icd.device.inter_command_delay_ms
+    )
+    .unwrap();
+
+    writeln!(out, "## Command Summary\n").unwrap();
+    writeln!(out, "| Opcode | Command | Data | Reply bytes | Reply |").unwrap();
+    writeln!(out, "|--------|---------|------|-------------|-------|").unwrap();
+    for cmd in &icd.command {
+        let data = match &cmd.argument {
+            Some(arg) => format!("`{}`", arg),
+            None => hex_bytes(&cmd.data()),
+        };
+        writeln!(
+            out,
+            "| 0x{:02X} | {} | {} | {} | {} |",
+            cmd.opcode,
+            cmd.summary,
+            if data.is_empty() { "-".to_string() } else { data },
+            cmd.rx_len.map_or("-".to_string(), |len| len.to_string()),
+            cmd.response
+        )
+        .unwrap();
+    }
+    for item in &icd.reset_telemetry {
+        writeln!(
+            out,
+            "| 0x{:02X} | {} | 0x00 | {} | u8 |",
+            item.opcode, item.summary, icd.reset_reply.rx_len
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "\n## Commands").unwrap();
+    for cmd in &icd.command {
+        writeln!(out, "\n### 0x{:02X} - {}\n", cmd.opcode, cmd.summary).unwrap();
+        if !cmd.description.trim().is_empty() {
+            writeln!(out, "{}\n", cmd.description.trim()).unwrap();
+        }
+        if let Some(delay) = cmd.delay_ms {
+            writeln!(out, "Read back after a delay of {} ms.\n", delay).unwrap();
+        }
+    }
+
+    if !icd.housekeeping.is_empty() {
+        writeln!(out, "\n## Board Housekeeping Channels\n").unwrap();
+        writeln!(out, "| Channel | Item | Unit |").unwrap();
+        writeln!(out, "|---------|------|------|").unwrap();
+        for item in &icd.housekeeping {
+            writeln!(
+                out,
+                "| 0x{:02X} | {} | {} |",
+                item.channel, item.summary, item.unit
+            )
+            .unwrap();
+        }
+    }
+
+    writeln!(out, "\n## Reset Telemetry\n").unwrap();
+    writeln!(
+        out,
+        "Each reset telemetry command is sent with the data byte 0x00 and read back \
+         after a delay of {} ms. The reply is {} bytes long, with the counter in byte 1. \
+         All counters roll over at 255 to 0.",
+        icd.reset_reply.delay_ms, icd.reset_reply.rx_len
+    )
+    .unwrap();
+    for item in &icd.reset_telemetry {
+        writeln!(out, "\n### 0x{:02X} - {}\n", item.opcode, item.summary).unwrap();
+        if !item.description.trim().is_empty() {
+            writeln!(out, "{}", item.description.trim()).unwrap();
+        }
+    }
+    out
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", ICD_PATH);
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let source = fs::read_to_string(ICD_PATH).expect("Failed to read ICD");
+    let icd: Icd = toml::from_str(&source).expect("Failed to parse ICD");
+    for cmd in &icd.command {
+        cmd.validate();
+    }
+    // Reset telemetry replies carry the counter in byte 1
+    if icd.reset_reply.rx_len != 2 {
+        panic!(
+            "reset_reply: `rx_len` {} does not match the 2 byte 'u8' response",
+            icd.reset_reply.rx_len
+        );
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let out_dir = Path::new(&out_dir);
+    fs::create_dir_all(out_dir.join("commands")).unwrap();
+
+    fs::write(out_dir.join("opcodes.rs"), gen_opcodes(&icd)).unwrap();
+    for (module, source) in gen_modules(&icd) {
+        fs::write(out_dir.join("commands").join(format!("{}.rs", module)), source).unwrap();
+    }
+    fs::write(out_dir.join("reset_telemetry.rs"), gen_reset_telemetry(&icd)).unwrap();
+    fs::write(out_dir.join("ICD.md"), gen_markdown(&icd)).unwrap();
+}
@@ -0,0 +1,515 @@
+//! Rate Alarms
+//!
+//! Evaluates configurable [`AlarmRule`]s against calibrated samples. A rule
+//! raises once its condition holds for a number of consecutive samples and
+//! clears once the rate has fallen back below a separate clear level for as
+//! many samples, so a rate hovering around the threshold does not toggle the
+//! alarm.
+//!
+//! Suspect samples are skipped and hold every alarm in its current state. A
+//! rate with an unreliable dead-time correction, e.g. from a saturating tube,
+//! is only a lower bound of the true rate: it still raises an `Above` alarm
+//! once it exceeds the threshold, but never clears an alarm.
+//!
+//! Raised and cleared alarms are sent as [`AlarmEvent`]s to every subscribed
+//! channel and callback, and are latched in an alarm table until they are
+//! acknowledged.
+
+use crate::analysis::calibration::CalibratedSample;
+use crate::{CounterError, CounterResult};
+use std::sync::mpsc;
+
+/// Rate an alarm rule is evaluated on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateSource {
+    /// Dead-time corrected rate of a single tube, 0 to 2
+    Tube(usize),
+    /// Mean dead-time corrected rate of the three tubes
+    Combined,
+}
+
+impl RateSource {
+    // Rate of a usable sample, and whether it is only a lower bound because
+    // of an unreliable dead-time correction
+    fn rate(self, sample: &CalibratedSample) -> Option<(f64, bool)> {
+        if !sample.sample.quality.is_usable() {
+            return None;
+        }
+        match self {
+            RateSource::Tube(tube) => sample
+                .tubes
+                .get(tube)
+                .map(|tube| (tube.corrected_rate.value, !tube.reliable)),
+            RateSource::Combined => Some((
+                sample
+                    .tubes
+                    .iter()
+                    .map(|tube| tube.corrected_rate.value)
+                    .sum::<f64>()
+                    / 3.0,
+                !sample.is_reliable(),
+            )),
+        }
+    }
+}
+
+/// Condition of an alarm rule
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlarmCondition {
+    /// Rate above `threshold` (cps), cleared at or below `clear` (cps)
+    Above {
+        /// Raise level
+        threshold: f64,
+        /// Clear level, at or below the raise level
+        clear: f64,
+    },
+    /// Rate changing faster than `limit` (cps per second) in either
+    /// direction, cleared at or below `clear` (cps per second)
+    RateOfChange {
+        /// Raise level
+        limit: f64,
+        /// Clear level, at or below the raise level
+        clear: f64,
+    },
+}
+
+/// Alarm rule
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlarmRule {
+    /// Unique name of the rule
+    pub name: String,
+    /// Rate the rule is evaluated on
+    pub source: RateSource,
+    /// Raise and clear condition
+    pub condition: AlarmCondition,
+    /// Consecutive samples needed to raise or clear the alarm
+    pub consecutive: u32,
+}
+
+/// Kind of alarm event
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AlarmEventKind {
+    /// Alarm condition started
+    Raised,
+    /// Alarm condition ended
+    Cleared,
+}
+
+/// Alarm event sent to subscribers
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlarmEvent {
+    /// Name of the rule
+    pub rule: String,
+    /// Kind of event
+    pub kind: AlarmEventKind,
+    /// Value which raised or cleared the alarm (cps, or cps per second)
+    pub value: f64,
+    /// UTC time of the sample (ms)
+    pub utc_ms: u64,
+}
+
+/// Entry of the latched alarm table
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AlarmEntry {
+    /// Name of the rule
+    pub rule: String,
+    /// Whether the alarm condition currently holds
+    pub active: bool,
+    /// Whether the alarm was raised since it was last acknowledged
+    pub latched: bool,
+    /// Number of times the alarm was raised
+    pub raised_count: u32,
+    /// UTC time the alarm was last raised (ms)
+    pub raised_utc_ms: Option<u64>,
+    /// UTC time the alarm was last cleared (ms)
+    pub cleared_utc_ms: Option<u64>,
+    /// Largest value seen while the alarm was active
+    pub peak: f64,
+}
+
+struct RuleState {
+    rule: AlarmRule,
+    entry: AlarmEntry,
+    streak: u32,
+    previous: Option<(f64, u64)>,
+}
+
+impl RuleState {
+    // Value of the condition and whether it is above the raise and clear levels
+    fn evaluate(&mut self, sample: &CalibratedSample) -> Option<(f64, bool, bool)> {
+        let (rate, lower_bound) = self.rule.source.rate(sample)?;
+        let utc_ms = sample.sample.utc_ms;
+
+        match self.rule.condition {
+            // A lower bound above the threshold raises, and holds an active
+            // alarm, but cannot show that the rate has dropped
+            AlarmCondition::Above { threshold, .. }
+                if lower_bound && !self.entry.active && rate <= threshold =>
+            {
+                None
+            }
+            AlarmCondition::Above { threshold, .. } if lower_bound => {
+                Some((rate, rate > threshold, true))
+            }
+            AlarmCondition::Above { threshold, clear } => {
+                Some((rate, rate > threshold, rate > clear))
+            }
+            // The change of a lower bound means nothing either way
+            AlarmCondition::RateOfChange { .. } if lower_bound => None,
+            AlarmCondition::RateOfChange { limit, clear } => {
+                let previous = self.previous.replace((rate, utc_ms));
+                let (last, last_ms) = previous?;
+                if utc_ms <= last_ms {
+                    return None;
+                }
+                let change = (rate - last).abs() / ((utc_ms - last_ms) as f64 / 1000.0);
+                Some((change, change > limit, change > clear))
+            }
+        }
+    }
+
+    fn update(&mut self, sample: &CalibratedSample) -> Option<AlarmEvent> {
+        let (value, above_raise, above_clear) = self.evaluate(sample)?;
+
+        let kind = if self.entry.active {
+            self.entry.peak = self.entry.peak.max(value);
+            self.streak = if above_clear { 0 } else { self.streak + 1 };
+            if self.streak < self.rule.consecutive.max(1) {
+                return None;
+            }
+            self.entry.active = false;
+            self.entry.cleared_utc_ms = Some(sample.sample.utc_ms);
+            AlarmEventKind::Cleared
+        } else {
+            self.streak = if above_raise { self.streak + 1 } else { 0 };
+            if self.streak < self.rule.consecutive.max(1) {
+                return None;
+            }
+            self.entry.active = true;
+            self.entry.latched = true;
+            self.entry.raised_count += 1;
+            self.entry.raised_utc_ms = Some(sample.sample.utc_ms);
+            self.entry.peak = value;
+            AlarmEventKind::Raised
+        };
+
+        self.streak = 0;
+        Some(AlarmEvent {
+            rule: self.rule.name.clone(),
+            kind,
+            value,
+            utc_ms: sample.sample.utc_ms,
+        })
+    }
+}
+
+type AlarmCallback = Box<dyn FnMut(&AlarmEvent) + Send>;
+
+/// Evaluates alarm rules and notifies subscribers
+#[derive(Default)]
+pub struct AlarmMonitor {
+    rules: Vec<RuleState>,
+    subscribers: Vec<mpsc::Sender<AlarmEvent>>,
+    callbacks: Vec<AlarmCallback>,
+}
+
+impl AlarmMonitor {
+    /// Constructor
+    ///
+    /// Creates a monitor without rules or subscribers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add Rule
+    ///
+    /// Adds a rule to the monitor. A rule with the same name is replaced,
+    /// together with its alarm table entry. Fails for a rule on a tube which
+    /// does not exist.
+    ///
+    /// # Arguments
+    /// `rule` - Rule to add
+    pub fn add_rule(&mut self, rule: AlarmRule) -> CounterResult<()> {
+        if let RateSource::Tube(tube) = rule.source {
+            if tube >= 3 {
+                return Err(CounterError::GenericError);
+            }
+        }
+        self.remove_rule(&rule.name);
+        self.rules.push(RuleState {
+            entry: AlarmEntry {
+                rule: rule.name.clone(),
+                ..AlarmEntry::default()
+            },
+            rule,
+            streak: 0,
+            previous: None,
+        });
+        Ok(())
+    }
+
+    /// Remove Rule
+    ///
+    /// # Arguments
+    /// `name` - Name of the rule
+    pub fn remove_rule(&mut self, name: &str) -> Option<AlarmRule> {
+        let index = self
+            .rules
+            .iter()
+            .position(|state| state.rule.name == name)?;
+        Some(self.rules.remove(index).rule)
+    }
+
+    /// Subscribe
+    ///
+    /// Returns a channel receiving every alarm event. The subscription ends
+    /// when the receiver is dropped.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<AlarmEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Register Callback
+    ///
+    /// # Arguments
+    /// `callback` - Function called with every alarm event
+    pub fn on_event<F: FnMut(&AlarmEvent) + Send + 'static>(&mut self, callback: F) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Update
+    ///
+    /// Evaluates every rule against a calibrated sample, notifies the
+    /// subscribers and returns the resulting events.
+    ///
+    /// # Arguments
+    /// `sample` - Calibrated sample
+    pub fn update(&mut self, sample: &CalibratedSample) -> Vec<AlarmEvent> {
+        let events: Vec<AlarmEvent> = self
+            .rules
+            .iter_mut()
+            .filter_map(|state| state.update(sample))
+            .collect();
+
+        for event in &events {
+            self.subscribers
+                .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+            for callback in self.callbacks.iter_mut() {
+                callback(event);
+            }
+        }
+        events
+    }
+
+    /// Alarm table, in the order the rules were added
+    pub fn table(&self) -> Vec<&AlarmEntry> {
+        self.rules.iter().map(|state| &state.entry).collect()
+    }
+
+    /// Alarm table entry of a rule
+    ///
+    /// # Arguments
+    /// `name` - Name of the rule
+    pub fn entry(&self, name: &str) -> Option<&AlarmEntry> {
+        self.rules
+            .iter()
+            .find(|state| state.rule.name == name)
+            .map(|state| &state.entry)
+    }
+
+    /// Entries which are active or latched
+    pub fn latched(&self) -> Vec<&AlarmEntry> {
+        self.rules
+            .iter()
+            .map(|state| &state.entry)
+            .filter(|entry| entry.active || entry.latched)
+            .collect()
+    }
+
+    /// Acknowledge
+    ///
+    /// Releases the latch of an alarm. An alarm which is still active stays
+    /// active. Returns whether the rule exists.
+    ///
+    /// # Arguments
+    /// `name` - Name of the rule
+    pub fn acknowledge(&mut self, name: &str) -> bool {
+        match self.rules.iter_mut().find(|state| state.rule.name == name) {
+            Some(state) => {
+                state.entry.latched = false;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SampleQuality;
+    use crate::test_util::{calibrated, tube_rate};
+    use std::sync::{Arc, Mutex};
+
+    fn sample(second: u64, rates: [f64; 3]) -> CalibratedSample {
+        let tube = |rate: f64| tube_rate(0, rate, rate);
+        calibrated(
+            second * 1000,
+            1000,
+            [tube(rates[0]), tube(rates[1]), tube(rates[2])],
+        )
+    }
+
+    fn above(consecutive: u32) -> AlarmRule {
+        AlarmRule {
+            name: "high".to_string(),
+            source: RateSource::Tube(0),
+            condition: AlarmCondition::Above {
+                threshold: 100.0,
+                clear: 50.0,
+            },
+            consecutive,
+        }
+    }
+
+    #[test]
+    fn test_above_with_hysteresis() {
+        let mut monitor = AlarmMonitor::new();
+        monitor.add_rule(above(2)).unwrap();
+        let receiver = monitor.subscribe();
+
+        let rates = [150.0, 20.0, 150.0, 150.0, 80.0, 40.0, 120.0, 40.0, 40.0];
+        let kinds: Vec<(u64, AlarmEventKind)> = rates
+            .iter()
+            .enumerate()
+            .flat_map(|(second, rate)| monitor.update(&sample(second as u64, [*rate, 0.0, 0.0])))
+            .map(|event| (event.utc_ms / 1000, event.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![(3, AlarmEventKind::Raised), (8, AlarmEventKind::Cleared)]
+        );
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(event.kind, AlarmEventKind::Raised);
+        assert_eq!(event.value, 150.0);
+        assert_eq!(receiver.try_recv().unwrap().kind, AlarmEventKind::Cleared);
+
+        let entry = monitor.entry("high").unwrap();
+        assert!(!entry.active);
+        assert!(entry.latched);
+        assert_eq!(entry.peak, 150.0);
+        assert_eq!(entry.raised_count, 1);
+    }
+
+    #[test]
+    fn test_rate_of_change_and_callback() {
+        let mut monitor = AlarmMonitor::new();
+        monitor
+            .add_rule(AlarmRule {
+                name: "spike".to_string(),
+                source: RateSource::Combined,
+                condition: AlarmCondition::RateOfChange {
+                    limit: 30.0,
+                    clear: 10.0,
+                },
+                consecutive: 1,
+            })
+            .unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        monitor.on_event(move |event| log.lock().unwrap().push(event.kind));
+
+        monitor.update(&sample(0, [10.0, 10.0, 10.0]));
+        monitor.update(&sample(2, [100.0, 100.0, 160.0]));
+        monitor.update(&sample(3, [100.0, 100.0, 160.0]));
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![AlarmEventKind::Raised, AlarmEventKind::Cleared]
+        );
+    }
+
+    #[test]
+    fn test_acknowledge() {
+        let mut monitor = AlarmMonitor::new();
+        monitor.add_rule(above(1)).unwrap();
+        monitor.update(&sample(0, [200.0, 0.0, 0.0]));
+        assert_eq!(monitor.latched().len(), 1);
+
+        assert!(monitor.acknowledge("high"));
+        assert!(monitor.entry("high").unwrap().active);
+        monitor.update(&sample(1, [0.0, 0.0, 0.0]));
+        assert!(monitor.latched().is_empty());
+        assert!(!monitor.acknowledge("unknown"));
+    }
+
+    #[test]
+    fn test_dropped_subscriber() {
+        let mut monitor = AlarmMonitor::new();
+        monitor.add_rule(above(1)).unwrap();
+        drop(monitor.subscribe());
+        monitor.update(&sample(0, [200.0, 0.0, 0.0]));
+        assert!(monitor.subscribers.is_empty());
+    }
+
+    #[test]
+    fn test_add_rule_unknown_tube() {
+        let mut monitor = AlarmMonitor::new();
+        let rule = AlarmRule {
+            source: RateSource::Tube(3),
+            ..above(1)
+        };
+        assert_eq!(monitor.add_rule(rule), Err(CounterError::GenericError));
+        assert!(monitor.table().is_empty());
+    }
+
+    #[test]
+    fn test_holds_on_suspect_samples() {
+        let mut monitor = AlarmMonitor::new();
+        monitor.add_rule(above(2)).unwrap();
+        monitor.update(&sample(0, [200.0, 0.0, 0.0]));
+
+        // Neither raises, nor breaks the streak
+        let mut suspect = sample(1, [0.0, 0.0, 0.0]);
+        suspect.sample.quality.insert(SampleQuality::SUSPECT);
+        assert!(monitor.update(&suspect).is_empty());
+        assert_eq!(monitor.update(&sample(2, [200.0, 0.0, 0.0])).len(), 1);
+
+        assert!(monitor.update(&suspect).is_empty());
+        monitor.update(&sample(3, [0.0, 0.0, 0.0]));
+        assert!(monitor.update(&suspect).is_empty());
+        assert_eq!(monitor.update(&sample(4, [0.0, 0.0, 0.0])).len(), 1);
+    }
+
+    #[test]
+    fn test_saturated_rate_is_lower_bound() {
+        let mut monitor = AlarmMonitor::new();
+        monitor.add_rule(above(2)).unwrap();
+        let saturated = |second: u64, rate: f64| {
+            let mut sample = sample(second, [rate, 0.0, 0.0]);
+            sample.tubes[0].reliable = false;
+            sample
+        };
+
+        // A lower bound below the threshold holds the streak
+        assert!(monitor.update(&saturated(0, 150.0)).is_empty());
+        assert!(monitor.update(&saturated(1, 80.0)).is_empty());
+        let events = monitor.update(&saturated(2, 300.0));
+        assert_eq!(events[0].kind, AlarmEventKind::Raised);
+        assert_eq!(events[0].value, 300.0);
+
+        // A lower bound never clears, however low
+        monitor.update(&sample(3, [0.0, 0.0, 0.0]));
+        assert!(monitor.update(&saturated(4, 0.0)).is_empty());
+        monitor.update(&sample(5, [0.0, 0.0, 0.0]));
+        assert!(monitor.entry("high").unwrap().active);
+        assert_eq!(monitor.update(&sample(6, [0.0, 0.0, 0.0])).len(), 1);
+
+        // An unreliable tube the rule does not use is evaluated as usual
+        let mut other = sample(7, [200.0, 0.0, 0.0]);
+        other.tubes[1].reliable = false;
+        monitor.update(&other);
+        assert_eq!(monitor.update(&other).len(), 1);
+    }
+}
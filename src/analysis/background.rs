@@ -0,0 +1,383 @@
+//! Background Model
+//!
+//! Estimates the background count rate of each tube from quiet periods, e.g.
+//! outside the South Atlantic Anomaly and solar particle events. Quiet samples
+//! are collected into fixed-length bins, and the background is the minimum or
+//! median bin rate over a rolling window spanning a few orbits.
+//!
+//! The resulting [`BackgroundModel`] is persisted with atomic file
+//! replacement and can be replaced by a model uploaded from the ground, which
+//! then takes precedence over the onboard estimate until onboard estimation
+//! is resumed.
+
+use crate::analysis::calibration::{CalibratedSample, Measurement};
+use crate::storage::write_atomic;
+use crate::{CounterError, CounterResult};
+use serde::*;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+/// Statistic used to derive the background from the bin rates
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackgroundMethod {
+    /// Lowest bin rate, robust against any remaining enhancements
+    Minimum,
+    /// Median bin rate, less sensitive to low fluctuations
+    #[default]
+    Median,
+}
+
+/// Background estimator configuration
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BackgroundConfig {
+    /// Statistic over the bin rates
+    pub method: BackgroundMethod,
+    /// Length of a bin (ms)
+    pub bin_ms: u64,
+    /// Length of the rolling window, e.g. three orbits (ms)
+    pub window_ms: u64,
+    /// Completed bins needed before the first estimate
+    pub min_bins: usize,
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        BackgroundConfig {
+            method: BackgroundMethod::Median,
+            bin_ms: 60_000,
+            window_ms: 3 * 5_580_000,
+            min_bins: 10,
+        }
+    }
+}
+
+/// Background rate of the three tubes
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BackgroundModel {
+    /// Background rate per tube (cps)
+    pub rates: [Measurement; 3],
+    /// UTC time of the last update (ms)
+    pub updated_utc_ms: u64,
+    /// Whether the model was uploaded from the ground
+    pub ground: bool,
+}
+
+impl BackgroundModel {
+    /// Loads a model saved with [`BackgroundModel::save`]
+    ///
+    /// # Arguments
+    /// `path` - Path of the model file
+    pub fn load<P: AsRef<Path>>(path: P) -> CounterResult<Self> {
+        let data = fs::read(&path).map_err(|error| CounterError::file_error(&path, &error))?;
+        bincode::deserialize(&data).map_err(|_| CounterError::parsing_failure("Background Model"))
+    }
+
+    /// Save
+    ///
+    /// Writes the model to a file, replacing any previous model atomically.
+    ///
+    /// # Arguments
+    /// `path` - Path of the model file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> CounterResult<()> {
+        let path = path.as_ref();
+        let data = bincode::serialize(self)
+            .map_err(|_| CounterError::parsing_failure("Background Model"))?;
+        write_atomic(path, &data)
+    }
+
+    /// Background subtracted rates of a sample (cps)
+    ///
+    /// # Arguments
+    /// `sample` - Calibrated sample
+    pub fn subtract(&self, sample: &CalibratedSample) -> [Measurement; 3] {
+        let mut rates = [Measurement::default(); 3];
+        for (tube, rate) in rates.iter_mut().enumerate() {
+            let corrected = sample.tubes[tube].corrected_rate;
+            let background = self.rates[tube];
+            *rate = Measurement::new(
+                corrected.value - background.value,
+                corrected.uncertainty.hypot(background.uncertainty),
+            );
+        }
+        rates
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Bin {
+    start_ms: u64,
+    live_ms: u64,
+    counts: [u64; 3],
+}
+
+impl Bin {
+    fn rate(&self, tube: usize) -> Measurement {
+        let live = self.live_ms as f64 / 1000.0;
+        let counts = self.counts[tube] as f64;
+        Measurement::new(counts / live, counts.sqrt() / live)
+    }
+}
+
+/// Rolling background estimator
+pub struct BackgroundEstimator {
+    config: BackgroundConfig,
+    bins: VecDeque<Bin>,
+    open: Option<Bin>,
+    model: Option<BackgroundModel>,
+}
+
+impl BackgroundEstimator {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// `config` - Estimator configuration
+    /// `model` - Previously persisted model to start from, if any
+    pub fn new(config: BackgroundConfig, model: Option<BackgroundModel>) -> Self {
+        BackgroundEstimator {
+            config,
+            bins: VecDeque::new(),
+            open: None,
+            model,
+        }
+    }
+
+    /// Current background model
+    pub fn model(&self) -> Option<&BackgroundModel> {
+        self.model.as_ref()
+    }
+
+    /// Set Ground Model
+    ///
+    /// Replaces the background with a model uploaded from the ground. The
+    /// onboard estimate no longer updates it until
+    /// [`BackgroundEstimator::resume_onboard`] is called.
+    ///
+    /// # Arguments
+    /// `model` - Uploaded model
+    pub fn set_ground_model(&mut self, mut model: BackgroundModel) {
+        model.ground = true;
+        self.model = Some(model);
+    }
+
+    /// Lets the onboard estimate replace a ground model again
+    pub fn resume_onboard(&mut self) {
+        if let Some(model) = self.model.as_mut() {
+            model.ground = false;
+        }
+        self.estimate();
+    }
+
+    /// Update
+    ///
+    /// Adds a sample. Only quiet samples contribute to the background, and
+    /// suspect samples or samples with an unreliable tube are left out.
+    /// Returns whether the model was updated.
+    ///
+    /// # Arguments
+    /// `sample` - Calibrated sample
+    /// `quiet` - Whether the sample was taken in a quiet period
+    pub fn update(&mut self, sample: &CalibratedSample, quiet: bool) -> bool {
+        let reliable = sample.is_reliable();
+        let sample = &sample.sample;
+        if !quiet || sample.interval_ms == 0 || !sample.quality.is_usable() || !reliable {
+            return false;
+        }
+        let utc_ms = sample.utc_ms;
+        let bin_ms = self.config.bin_ms.max(1);
+
+        let mut closed = false;
+        if let Some(open) = &self.open {
+            if utc_ms >= open.start_ms + bin_ms {
+                if let Some(bin) = self.open.take() {
+                    if bin.live_ms > 0 {
+                        self.bins.push_back(bin);
+                        closed = true;
+                    }
+                }
+            }
+        }
+        let open = self.open.get_or_insert(Bin {
+            start_ms: utc_ms - utc_ms % bin_ms,
+            ..Bin::default()
+        });
+        open.live_ms += u64::from(sample.interval_ms);
+        for (total, counts) in open.counts.iter_mut().zip(sample.counts.iter()) {
+            *total += u64::from(*counts);
+        }
+
+        let oldest = utc_ms.saturating_sub(self.config.window_ms);
+        while self.bins.front().is_some_and(|bin| bin.start_ms < oldest) {
+            self.bins.pop_front();
+        }
+
+        if closed {
+            self.estimate()
+        } else {
+            false
+        }
+    }
+
+    // Updates the model from the binned rates, unless a ground model is in use
+    fn estimate(&mut self) -> bool {
+        if self.model.as_ref().is_some_and(|model| model.ground)
+            || self.bins.len() < self.config.min_bins.max(1)
+        {
+            return false;
+        }
+
+        let mut rates = [Measurement::default(); 3];
+        for (tube, rate) in rates.iter_mut().enumerate() {
+            let mut bins: Vec<Measurement> = self.bins.iter().map(|bin| bin.rate(tube)).collect();
+            bins.sort_by(|a, b| {
+                a.value
+                    .partial_cmp(&b.value)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            *rate = match self.config.method {
+                BackgroundMethod::Minimum => bins[0],
+                BackgroundMethod::Median => {
+                    let n = bins.len() as f64;
+                    let mean = bins.iter().map(|bin| bin.value).sum::<f64>() / n;
+                    let variance = bins
+                        .iter()
+                        .map(|bin| (bin.value - mean).powi(2))
+                        .sum::<f64>()
+                        / (n - 1.0).max(1.0);
+                    // Standard error of the median of normally distributed values
+                    Measurement::new(bins[bins.len() / 2].value, 1.2533 * (variance / n).sqrt())
+                }
+            };
+        }
+
+        self.model = Some(BackgroundModel {
+            rates,
+            updated_utc_ms: self.bins.back().map_or(0, |bin| bin.start_ms),
+            ground: false,
+        });
+        true
+    }
+
+    /// Background subtracted rates of a sample (cps)
+    ///
+    /// Returns `None` until a background model is available.
+    ///
+    /// # Arguments
+    /// `sample` - Calibrated sample
+    pub fn subtract(&self, sample: &CalibratedSample) -> Option<[Measurement; 3]> {
+        self.model.as_ref().map(|model| model.subtract(sample))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SampleQuality;
+    use crate::test_util::{calibrated, temp_path, tube_rate};
+
+    fn sample(second: u64, counts: [u16; 3]) -> CalibratedSample {
+        let tube = |counts: u16| tube_rate(counts, f64::from(counts), 0.0);
+        let tubes = [tube(counts[0]), tube(counts[1]), tube(counts[2])];
+        calibrated(second * 1000, 1000, tubes)
+    }
+
+    fn config(method: BackgroundMethod) -> BackgroundConfig {
+        BackgroundConfig {
+            method,
+            bin_ms: 10_000,
+            window_ms: 100_000,
+            min_bins: 3,
+        }
+    }
+
+    // Ten second bins with rates of 2, 4, 6, ... cps on the first tube
+    fn feed(estimator: &mut BackgroundEstimator, bins: u64) {
+        for second in 0..bins * 10 + 1 {
+            let rate = 2 * (second / 10 + 1) as u16;
+            estimator.update(&sample(second, [rate, 10, 0]), true);
+        }
+    }
+
+    #[test]
+    fn test_median_and_minimum() {
+        let mut median = BackgroundEstimator::new(config(BackgroundMethod::Median), None);
+        feed(&mut median, 5);
+        let model = median.model().unwrap();
+        assert_eq!(model.rates[0].value, 6.0);
+        assert_eq!(model.rates[1], Measurement::new(10.0, 0.0));
+
+        let mut minimum = BackgroundEstimator::new(config(BackgroundMethod::Minimum), None);
+        feed(&mut minimum, 5);
+        let model = minimum.model().unwrap();
+        assert_eq!(model.rates[0].value, 2.0);
+        assert!((model.rates[0].uncertainty - 20f64.sqrt() / 10.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_window_and_quiet() {
+        let mut estimator = BackgroundEstimator::new(config(BackgroundMethod::Minimum), None);
+        feed(&mut estimator, 15);
+        // Bins older than the 100 s window are dropped
+        assert_eq!(estimator.model().unwrap().rates[0].value, 12.0);
+
+        assert!(!estimator.update(&sample(1000, [0, 0, 0]), false));
+        assert_eq!(estimator.model().unwrap().rates[0].value, 12.0);
+    }
+
+    #[test]
+    fn test_skips_suspect_and_unreliable() {
+        let mut estimator = BackgroundEstimator::new(config(BackgroundMethod::Minimum), None);
+        let mut suspect = sample(0, [0, 0, 0]);
+        suspect.sample.quality.insert(SampleQuality::SUSPECT);
+        let mut unreliable = sample(0, [1, 1, 1]);
+        unreliable.tubes[1].reliable = false;
+        for second in 0..31 {
+            estimator.update(&sample(second, [5, 5, 5]), true);
+            suspect.sample.utc_ms = second * 1000 + 500;
+            assert!(!estimator.update(&suspect, true));
+            unreliable.sample.utc_ms = second * 1000 + 500;
+            assert!(!estimator.update(&unreliable, true));
+        }
+        assert_eq!(estimator.model().unwrap().rates[0].value, 5.0);
+
+        let mut wrapped = sample(31, [5, 5, 5]);
+        wrapped.sample.quality.insert(SampleQuality::WRAPPED);
+        estimator.update(&wrapped, true);
+        assert_eq!(estimator.open.as_ref().unwrap().live_ms, 2000);
+    }
+
+    #[test]
+    fn test_ground_model_and_subtract() {
+        let mut estimator = BackgroundEstimator::new(config(BackgroundMethod::Median), None);
+        assert!(estimator.subtract(&sample(0, [5, 5, 5])).is_none());
+
+        estimator.set_ground_model(BackgroundModel {
+            rates: [Measurement::new(1.0, 0.3); 3],
+            updated_utc_ms: 0,
+            ground: false,
+        });
+        feed(&mut estimator, 5);
+        assert!(estimator.model().unwrap().ground);
+
+        let rates = estimator.subtract(&sample(0, [5, 5, 5])).unwrap();
+        assert_eq!(rates[0].value, 4.0);
+        assert_eq!(rates[0].uncertainty, 0.3);
+
+        estimator.resume_onboard();
+        assert_eq!(estimator.model().unwrap().rates[0].value, 6.0);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let path = temp_path("background.bin");
+        let model = BackgroundModel {
+            rates: [Measurement::new(1.0, 0.1); 3],
+            updated_utc_ms: 42,
+            ground: true,
+        };
+        model.save(&path).unwrap();
+        assert_eq!(BackgroundModel::load(&path).unwrap(), model);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,285 @@
+//! Count Rate and Flux Calibration
+//!
+//! Converts the counts of a [`CountSample`] into count rates and particle
+//! flux using a per-tube [`CalibrationProfile`]. Uncertainties follow Poisson
+//! counting statistics and are propagated through the dead-time correction,
+//! background subtraction and flux conversion.
+//!
+//! Readings whose dead-time correction factor exceeds the tube's
+//! `max_correction`, or which saturate the dead-time model, are flagged as
+//! unreliable instead of being silently corrected.
+//!
+//! The profile is loaded from a TOML file with one `[[tubes]]` table per tube:
+//!
+//! ```toml
+//! [[tubes]]
+//! geometric_factor = 0.8    # cm^2 sr
+//! efficiency = 0.95
+//! dead_time = 90e-6         # s
+//! dead_time_model = "paralyzable"
+//! max_correction = 1.5
+//! background = 0.2          # cps
+//! background_uncertainty = 0.05
+//! ```
+
+use crate::analysis::deadtime::DeadTimeModel;
+use crate::objects::CountSample;
+use crate::{CounterError, CounterResult};
+use serde::*;
+use std::fs;
+use std::path::Path;
+
+/// Value with its one standard deviation uncertainty
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    /// Value
+    pub value: f64,
+    /// One standard deviation uncertainty
+    pub uncertainty: f64,
+}
+
+impl Measurement {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// `value` - Value
+    /// `uncertainty` - One standard deviation uncertainty
+    pub fn new(value: f64, uncertainty: f64) -> Self {
+        Measurement { value, uncertainty }
+    }
+}
+
+/// Calibration of a single Geiger tube
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TubeCalibration {
+    /// Geometric factor (cm^2 sr)
+    pub geometric_factor: f64,
+    /// Detection efficiency, between 0 and 1
+    pub efficiency: f64,
+    /// Dead time after each count (s)
+    pub dead_time: f64,
+    /// Dead-time model of the tube
+    #[serde(default)]
+    pub dead_time_model: DeadTimeModel,
+    /// Largest dead-time correction factor accepted as reliable
+    #[serde(default = "default_max_correction")]
+    pub max_correction: f64,
+    /// Background count rate (cps)
+    #[serde(default)]
+    pub background: f64,
+    /// Uncertainty of the background count rate (cps)
+    #[serde(default)]
+    pub background_uncertainty: f64,
+}
+
+fn default_max_correction() -> f64 {
+    2.0
+}
+
+/// Count rate and flux of a single tube
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TubeRate {
+    /// Counts during the integration interval
+    pub counts: u16,
+    /// Measured count rate (cps)
+    pub rate: Measurement,
+    /// Dead-time corrected count rate (cps)
+    pub corrected_rate: Measurement,
+    /// Background subtracted particle flux (1 / (cm^2 sr s))
+    pub flux: Measurement,
+    /// Whether the dead-time correction stayed within `max_correction`
+    pub reliable: bool,
+}
+
+/// Count sample with calibrated rates
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CalibratedSample {
+    /// Raw sample
+    pub sample: CountSample,
+    /// Rates and flux per tube
+    pub tubes: [TubeRate; 3],
+}
+
+impl CalibratedSample {
+    /// Whether the dead-time correction of every tube is reliable
+    pub fn is_reliable(&self) -> bool {
+        self.tubes.iter().all(|tube| tube.reliable)
+    }
+}
+
+/// Calibration of the three tubes of a radiation counter
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationProfile {
+    /// Calibration per tube
+    pub tubes: [TubeCalibration; 3],
+}
+
+impl TubeCalibration {
+    /// Calibrates the counts registered during `interval` seconds
+    ///
+    /// # Arguments
+    /// `counts` - Counts during the integration interval
+    /// `interval` - Integration interval (s)
+    pub fn calibrate(&self, counts: u16, interval: f64) -> TubeRate {
+        let n = f64::from(counts);
+        let rate = Measurement::new(n / interval, n.sqrt() / interval);
+
+        let (corrected_rate, reliable) =
+            match self.dead_time_model.correct(rate.value, self.dead_time) {
+                Some(correction) => (
+                    Measurement::new(correction.rate, rate.uncertainty * correction.derivative),
+                    correction.factor(rate.value) <= self.max_correction,
+                ),
+                // Saturated tube, the measured rate is only a lower bound
+                None => (rate, false),
+            };
+
+        let response = self.geometric_factor * self.efficiency;
+        let flux = Measurement::new(
+            (corrected_rate.value - self.background) / response,
+            corrected_rate
+                .uncertainty
+                .hypot(self.background_uncertainty)
+                / response,
+        );
+
+        TubeRate {
+            counts,
+            rate,
+            corrected_rate,
+            flux,
+            reliable,
+        }
+    }
+}
+
+impl CalibrationProfile {
+    /// Parses a calibration profile from TOML
+    ///
+    /// # Arguments
+    /// `source` - TOML text of the profile
+    pub fn from_toml(source: &str) -> CounterResult<Self> {
+        toml::from_str(source).map_err(|_| CounterError::parsing_failure("Calibration Profile"))
+    }
+
+    /// Loads a calibration profile from a TOML file
+    ///
+    /// # Arguments
+    /// `path` - Path of the profile
+    pub fn load<P: AsRef<Path>>(path: P) -> CounterResult<Self> {
+        let source =
+            fs::read_to_string(&path).map_err(|error| CounterError::file_error(&path, &error))?;
+        Self::from_toml(&source)
+    }
+
+    /// Calibrates a count sample
+    ///
+    /// Returns `None` for samples without an integration interval, such as
+    /// the first sample after start-up.
+    ///
+    /// # Arguments
+    /// `sample` - Sample to calibrate
+    pub fn calibrate(&self, sample: &CountSample) -> Option<CalibratedSample> {
+        if sample.interval_ms == 0 {
+            return None;
+        }
+        let interval = sample.interval_secs();
+        Some(CalibratedSample {
+            sample: sample.clone(),
+            tubes: [
+                self.tubes[0].calibrate(sample.counts[0], interval),
+                self.tubes[1].calibrate(sample.counts[1], interval),
+                self.tubes[2].calibrate(sample.counts[2], interval),
+            ],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::count_sample;
+
+    const PROFILE: &str = r#"
+        [[tubes]]
+        geometric_factor = 0.5
+        efficiency = 1.0
+        dead_time = 0.0
+        background = 2.0
+        background_uncertainty = 0.0
+
+        [[tubes]]
+        geometric_factor = 1.0
+        efficiency = 0.5
+        dead_time = 0.001
+
+        [[tubes]]
+        geometric_factor = 1.0
+        efficiency = 1.0
+        dead_time = 0.0001
+        dead_time_model = "paralyzable"
+        max_correction = 1.5
+    "#;
+
+    fn sample(counts: [u16; 3], interval_ms: u32) -> CountSample {
+        count_sample(1, 0, 0, interval_ms, counts)
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let profile = CalibrationProfile::from_toml(PROFILE).unwrap();
+        assert_eq!(profile.tubes[1].dead_time, 0.001);
+        assert_eq!(profile.tubes[1].background, 0.0);
+    }
+
+    #[test]
+    fn test_from_toml_bad_data() {
+        assert_eq!(
+            CalibrationProfile::from_toml("[[tubes]]\ngeometric_factor = 1.0"),
+            Err(CounterError::parsing_failure("Calibration Profile"))
+        );
+    }
+
+    #[test]
+    fn test_calibrate() {
+        let profile = CalibrationProfile::from_toml(PROFILE).unwrap();
+        let calibrated = profile.calibrate(&sample([100, 500, 0], 10_000)).unwrap();
+
+        let tube = &calibrated.tubes[0];
+        assert_eq!(tube.rate, Measurement::new(10.0, 1.0));
+        assert_eq!(tube.corrected_rate, tube.rate);
+        assert_eq!(tube.flux, Measurement::new(16.0, 2.0));
+
+        // 50 cps with 1 ms dead time loses 5% of the counts
+        let tube = &calibrated.tubes[1];
+        assert!((tube.corrected_rate.value - 50.0 / 0.95).abs() < 1e-9);
+        assert!((tube.flux.value - 2.0 * 50.0 / 0.95).abs() < 1e-9);
+
+        assert_eq!(calibrated.tubes[2].flux, Measurement::new(0.0, 0.0));
+        assert!(calibrated.is_reliable());
+    }
+
+    #[test]
+    fn test_calibrate_unreliable() {
+        let profile = CalibrationProfile::from_toml(PROFILE).unwrap();
+
+        // 3000 cps on a 100 us paralyzable tube needs a correction factor above 1.5
+        let calibrated = profile.calibrate(&sample([0, 0, 30_000], 10_000)).unwrap();
+        let tube = &calibrated.tubes[2];
+        assert!(tube.corrected_rate.value > 1.5 * tube.rate.value);
+        assert!(!tube.reliable);
+        assert!(!calibrated.is_reliable());
+
+        // 2000 cps on a 1 ms non-paralyzable tube saturates it
+        let calibrated = profile.calibrate(&sample([0, 20_000, 0], 10_000)).unwrap();
+        let tube = &calibrated.tubes[1];
+        assert_eq!(tube.corrected_rate, tube.rate);
+        assert!(!tube.reliable);
+    }
+
+    #[test]
+    fn test_calibrate_without_interval() {
+        let profile = CalibrationProfile::from_toml(PROFILE).unwrap();
+        assert_eq!(profile.calibrate(&sample([1, 1, 1], 0)), None);
+    }
+}
@@ -0,0 +1,239 @@
+//! Coincidence Analysis
+//!
+//! A penetrating particle crossing several tubes increments them in the same
+//! polling interval, while single-tube hits increment only one. With short
+//! polling intervals each sample acts as a coincidence window: the analyzer
+//! counts the windows in which each pair of tubes, and all three, registered
+//! counts.
+//!
+//! Uncorrelated singles also land in the same window by chance. Treating the
+//! tubes as independent Poisson sources with rate `r`, a tube registers at
+//! least one count in a window of length `T` with probability
+//! `1 - exp(-r T)`, and the accidental coincidence background is the product
+//! of these probabilities over the tubes involved. The mean window length is
+//! used, so windows should be of similar length.
+
+use crate::analysis::calibration::Measurement;
+use crate::objects::CountSample;
+
+/// Tube pairs, in the order of [`CoincidenceReport::pairs`]
+pub const TUBE_PAIRS: [(usize, usize); 3] = [(0, 1), (0, 2), (1, 2)];
+
+/// Coincidence analysis configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoincidenceConfig {
+    /// Longest polling interval used as a coincidence window (ms)
+    pub max_window_ms: u32,
+}
+
+impl Default for CoincidenceConfig {
+    fn default() -> Self {
+        CoincidenceConfig { max_window_ms: 100 }
+    }
+}
+
+/// Coincidence rate of a combination of tubes
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoincidenceRate {
+    /// Windows in which every tube of the combination registered counts
+    pub observed: u64,
+    /// Observed coincidence rate (1/s)
+    pub rate: Measurement,
+    /// Expected rate of accidental coincidences (1/s)
+    pub accidental_rate: f64,
+    /// Coincidence rate above the accidental background (1/s)
+    pub excess_rate: Measurement,
+}
+
+/// Result of the coincidence analysis
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoincidenceReport {
+    /// Number of coincidence windows
+    pub windows: u64,
+    /// Total length of the windows (s)
+    pub live_time: f64,
+    /// Singles count rate per tube (cps)
+    pub singles: [Measurement; 3],
+    /// Pair coincidences, in the order of [`TUBE_PAIRS`]
+    pub pairs: [CoincidenceRate; 3],
+    /// Triple coincidences
+    pub triple: CoincidenceRate,
+}
+
+/// Accumulates tube coincidences over short polling intervals
+pub struct CoincidenceAnalyzer {
+    config: CoincidenceConfig,
+    windows: u64,
+    live_ms: u64,
+    singles: [u64; 3],
+    pairs: [u64; 3],
+    triple: u64,
+}
+
+impl CoincidenceAnalyzer {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// `config` - Analysis configuration
+    pub fn new(config: CoincidenceConfig) -> Self {
+        CoincidenceAnalyzer {
+            config,
+            windows: 0,
+            live_ms: 0,
+            singles: [0; 3],
+            pairs: [0; 3],
+            triple: 0,
+        }
+    }
+
+    /// Update
+    ///
+    /// Adds a sample as a coincidence window. Suspect samples and samples
+    /// without an integration interval or longer than the maximum window are
+    /// ignored. Returns whether the sample was used.
+    ///
+    /// # Arguments
+    /// `sample` - Sample to add
+    pub fn update(&mut self, sample: &CountSample) -> bool {
+        if sample.interval_ms == 0
+            || sample.interval_ms > self.config.max_window_ms
+            || !sample.quality.is_usable()
+        {
+            return false;
+        }
+        self.windows += 1;
+        self.live_ms += u64::from(sample.interval_ms);
+
+        let hit = [
+            sample.counts[0] > 0,
+            sample.counts[1] > 0,
+            sample.counts[2] > 0,
+        ];
+        for (singles, counts) in self.singles.iter_mut().zip(sample.counts.iter()) {
+            *singles += u64::from(*counts);
+        }
+        for (pair, (a, b)) in TUBE_PAIRS.iter().enumerate() {
+            if hit[*a] && hit[*b] {
+                self.pairs[pair] += 1;
+            }
+        }
+        if hit.iter().all(|hit| *hit) {
+            self.triple += 1;
+        }
+        true
+    }
+
+    /// Clears the accumulated windows
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config.clone());
+    }
+
+    /// Report
+    ///
+    /// Returns `None` until at least one window was added.
+    pub fn report(&self) -> Option<CoincidenceReport> {
+        if self.windows == 0 {
+            return None;
+        }
+        let live_time = self.live_ms as f64 / 1000.0;
+        let window = live_time / self.windows as f64;
+
+        let mut singles = [Measurement::default(); 3];
+        let mut hit_probability = [0.0; 3];
+        for tube in 0..3 {
+            let counts = self.singles[tube] as f64;
+            singles[tube] = Measurement::new(counts / live_time, counts.sqrt() / live_time);
+            hit_probability[tube] = 1.0 - (-singles[tube].value * window).exp();
+        }
+
+        let rate = |observed: u64, probability: f64| {
+            let measured = Measurement::new(
+                observed as f64 / live_time,
+                (observed as f64).sqrt() / live_time,
+            );
+            let accidental_rate = probability * self.windows as f64 / live_time;
+            CoincidenceRate {
+                observed,
+                rate: measured,
+                accidental_rate,
+                excess_rate: Measurement::new(
+                    measured.value - accidental_rate,
+                    measured.uncertainty,
+                ),
+            }
+        };
+
+        let pair = |index: usize| {
+            let (a, b) = TUBE_PAIRS[index];
+            rate(self.pairs[index], hit_probability[a] * hit_probability[b])
+        };
+
+        Some(CoincidenceReport {
+            windows: self.windows,
+            live_time,
+            singles,
+            pairs: [pair(0), pair(1), pair(2)],
+            triple: rate(self.triple, hit_probability.iter().product::<f64>()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SampleQuality;
+    use crate::test_util::count_sample;
+
+    fn sample(counts: [u16; 3], interval_ms: u32) -> CountSample {
+        count_sample(0, 0, 0, interval_ms, counts)
+    }
+
+    #[test]
+    fn test_counts_coincidences() {
+        let mut analyzer = CoincidenceAnalyzer::new(CoincidenceConfig::default());
+        assert!(analyzer.report().is_none());
+
+        for counts in &[[1, 1, 0], [1, 1, 1], [0, 0, 1], [0, 2, 1], [0, 0, 0]] {
+            assert!(analyzer.update(&sample(*counts, 100)));
+        }
+        assert!(!analyzer.update(&sample([5, 5, 5], 1000)));
+        assert!(!analyzer.update(&sample([5, 5, 5], 0)));
+        let mut suspect = sample([0, 0, 0], 100);
+        suspect.quality.insert(SampleQuality::SUSPECT);
+        assert!(!analyzer.update(&suspect));
+
+        let report = analyzer.report().unwrap();
+        assert_eq!(report.windows, 5);
+        assert_eq!(report.live_time, 0.5);
+        assert_eq!(report.singles[1].value, 8.0);
+        assert_eq!(report.pairs[0].observed, 2);
+        assert_eq!(report.pairs[1].observed, 1);
+        assert_eq!(report.pairs[2].observed, 2);
+        assert_eq!(report.triple.observed, 1);
+        assert_eq!(report.triple.rate.value, 2.0);
+    }
+
+    #[test]
+    fn test_accidental_background() {
+        // Independent tubes hitting in alternate windows never coincide,
+        // but the accidental estimate expects some coincidences by chance
+        let mut analyzer = CoincidenceAnalyzer::new(CoincidenceConfig::default());
+        for window in 0..1000 {
+            let counts = match window % 4 {
+                0 => [1, 0, 0],
+                1 => [0, 1, 0],
+                2 => [0, 0, 1],
+                _ => [0, 0, 0],
+            };
+            analyzer.update(&sample(counts, 50));
+        }
+        let report = analyzer.report().unwrap();
+
+        // Singles rate of 5 cps, hit probability 1 - exp(-0.25) per window
+        let probability = 1.0 - (-0.25f64).exp();
+        let expected = probability * probability * 1000.0 / 50.0;
+        assert!((report.pairs[0].accidental_rate - expected).abs() < 1e-9);
+        assert_eq!(report.pairs[0].observed, 0);
+        assert!(report.pairs[0].excess_rate.value < 0.0);
+    }
+}
@@ -0,0 +1,374 @@
+//! Cross-Tube Consistency
+//!
+//! The three tubes of a board see the same environment, so their count rates
+//! keep a fixed ratio set by their geometry and efficiency. The
+//! [`ConsistencyMonitor`] learns these ratios from the first samples, or takes
+//! them from the ground, and gives each channel a [`ChannelHealth`] verdict
+//! from a window of recent samples.
+//!
+//! A channel is only judged faulty while the other channels are counting, so
+//! a quiet environment does not flag every tube at once.
+
+use crate::objects::CountSample;
+use std::collections::VecDeque;
+
+/// Health verdict of a tube channel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChannelHealth {
+    /// Inter-tube ratios are still being learned and no fault was seen
+    Learning,
+    /// Channel tracks the other channels
+    Healthy,
+    /// Counter reads zero while the other channels count
+    ZeroCount,
+    /// Counter holds the same non-zero value while the other channels count
+    Stuck,
+    /// Channel registers exactly the same counts in every sample
+    Constant,
+    /// Ratio to the other channels moved away from the learned ratio
+    Drifting,
+}
+
+impl ChannelHealth {
+    /// Whether the verdict indicates a faulty channel
+    pub fn is_fault(self) -> bool {
+        !matches!(self, ChannelHealth::Learning | ChannelHealth::Healthy)
+    }
+}
+
+/// Consistency monitor configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConsistencyConfig {
+    /// Number of fault free samples used to learn the inter-tube ratios
+    pub learning_samples: usize,
+    /// Number of recent samples each verdict is based on
+    pub window: usize,
+    /// Counts the other channels must register in the window before a
+    /// channel can be judged
+    pub min_counts: u32,
+    /// Largest accepted relative deviation from the learned ratio
+    pub drift_tolerance: f64,
+}
+
+impl Default for ConsistencyConfig {
+    fn default() -> Self {
+        ConsistencyConfig {
+            learning_samples: 60,
+            window: 10,
+            min_counts: 50,
+            drift_tolerance: 0.3,
+        }
+    }
+}
+
+/// Monitor of the agreement between the three tube channels
+pub struct ConsistencyMonitor {
+    config: ConsistencyConfig,
+    window: VecDeque<CountSample>,
+    learned: [u64; 3],
+    learned_samples: usize,
+    ratios: Option<[f64; 3]>,
+    health: [ChannelHealth; 3],
+}
+
+impl ConsistencyMonitor {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// `config` - Monitor configuration
+    pub fn new(config: ConsistencyConfig) -> Self {
+        ConsistencyMonitor {
+            window: VecDeque::with_capacity(config.window),
+            config,
+            learned: [0; 3],
+            learned_samples: 0,
+            ratios: None,
+            health: [ChannelHealth::Learning; 3],
+        }
+    }
+
+    /// Learned inter-tube ratios
+    ///
+    /// Each ratio is the count rate of a tube relative to the mean rate of
+    /// the three tubes. `None` while the ratios are being learned.
+    pub fn ratios(&self) -> Option<[f64; 3]> {
+        self.ratios
+    }
+
+    /// Set Ratios
+    ///
+    /// Replaces the learned inter-tube ratios, e.g. with values from the
+    /// ground calibration.
+    ///
+    /// # Arguments
+    /// `ratios` - Rate of each tube relative to the mean rate of the three
+    pub fn set_ratios(&mut self, ratios: [f64; 3]) {
+        self.ratios = Some(ratios);
+    }
+
+    /// Forgets the learned ratios and starts learning again
+    pub fn relearn(&mut self) {
+        self.learned = [0; 3];
+        self.learned_samples = 0;
+        self.ratios = None;
+    }
+
+    /// Current verdict of each channel
+    pub fn health(&self) -> [ChannelHealth; 3] {
+        self.health
+    }
+
+    /// Update
+    ///
+    /// Adds a sample and returns the updated verdict of each channel.
+    /// Suspect samples and samples without an integration interval are
+    /// ignored, so a failed read does not look like a dead channel.
+    ///
+    /// # Arguments
+    /// `sample` - Sample to add
+    pub fn update(&mut self, sample: &CountSample) -> [ChannelHealth; 3] {
+        if sample.interval_ms == 0 || !sample.quality.is_usable() {
+            return self.health;
+        }
+        if self.window.len() == self.config.window.max(1) {
+            self.window.pop_front();
+        }
+        self.window.push_back(sample.clone());
+
+        let mut totals = [0u32; 3];
+        for sample in &self.window {
+            for (total, counts) in totals.iter_mut().zip(sample.counts.iter()) {
+                *total += u32::from(*counts);
+            }
+        }
+
+        let full = self.window.len() == self.config.window.max(1);
+        let mut faults = [None; 3];
+        for (tube, fault) in faults.iter_mut().enumerate() {
+            let others: u32 = (0..3).filter(|&i| i != tube).map(|i| totals[i]).sum();
+            *fault = if full && others >= self.config.min_counts {
+                self.fault(tube, totals[tube])
+            } else if self.health[tube].is_fault() && self.health[tube] != ChannelHealth::Drifting {
+                // Not enough activity to judge, keep the previous fault
+                Some(self.health[tube])
+            } else {
+                None
+            };
+        }
+
+        if faults.iter().all(Option::is_none) {
+            self.learn(sample.counts);
+        }
+        let drift = self.drift(&totals);
+        for tube in 0..3 {
+            self.health[tube] = faults[tube].unwrap_or(drift[tube]);
+        }
+        self.health
+    }
+
+    // Stuck, zero or constant channel, judged from the raw readings
+    fn fault(&self, tube: usize, total: u32) -> Option<ChannelHealth> {
+        let readings: Vec<i16> = self
+            .window
+            .iter()
+            .map(|sample| match tube {
+                0 => sample.reading.rc1_reading,
+                1 => sample.reading.rc2_reading,
+                _ => sample.reading.rc3_reading,
+            })
+            .collect();
+        let first_counts = self.window[0].counts[tube];
+
+        if readings.iter().all(|reading| *reading == 0) {
+            Some(ChannelHealth::ZeroCount)
+        } else if total == 0 {
+            Some(ChannelHealth::Stuck)
+        } else if self
+            .window
+            .iter()
+            .all(|sample| sample.counts[tube] == first_counts)
+        {
+            Some(ChannelHealth::Constant)
+        } else {
+            None
+        }
+    }
+
+    fn learn(&mut self, counts: [u16; 3]) {
+        if self.ratios.is_some() {
+            return;
+        }
+        for (learned, counts) in self.learned.iter_mut().zip(counts.iter()) {
+            *learned += u64::from(*counts);
+        }
+        self.learned_samples += 1;
+
+        let mean = self.learned.iter().sum::<u64>() as f64 / 3.0;
+        if self.learned_samples >= self.config.learning_samples && mean > 0.0 {
+            let mut ratios = [0.0; 3];
+            for (ratio, learned) in ratios.iter_mut().zip(self.learned.iter()) {
+                *ratio = *learned as f64 / mean;
+            }
+            self.ratios = Some(ratios);
+        }
+    }
+
+    // Compares each channel with the median of the ratio-normalised channels,
+    // which stays put when a single channel drifts
+    fn drift(&self, totals: &[u32; 3]) -> [ChannelHealth; 3] {
+        let ratios = match self.ratios {
+            Some(ratios) => ratios,
+            None => return [ChannelHealth::Learning; 3],
+        };
+
+        let mut normalised = [0.0; 3];
+        for tube in 0..3 {
+            normalised[tube] = if ratios[tube] > 0.0 {
+                f64::from(totals[tube]) / ratios[tube]
+            } else {
+                0.0
+            };
+        }
+        let mut sorted = normalised;
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let median = sorted[1];
+
+        let mut health = [ChannelHealth::Healthy; 3];
+        if median * 3.0 < f64::from(self.config.min_counts) {
+            return health;
+        }
+        for tube in 0..3 {
+            if (normalised[tube] / median - 1.0).abs() > self.config.drift_tolerance {
+                health[tube] = ChannelHealth::Drifting;
+            }
+        }
+        health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::{RCHk, SampleQuality};
+    use crate::test_util::count_sample;
+
+    struct Feed {
+        reading: [i16; 3],
+    }
+
+    impl Feed {
+        fn sample(&mut self, counts: [u16; 3]) -> CountSample {
+            for (reading, counts) in self.reading.iter_mut().zip(counts.iter()) {
+                *reading = reading.wrapping_add(*counts as i16);
+            }
+            let mut sample = count_sample(1, 0, 0, 1000, counts);
+            sample.reading = RCHk {
+                rc1_reading: self.reading[0],
+                rc2_reading: self.reading[1],
+                rc3_reading: self.reading[2],
+            };
+            sample
+        }
+    }
+
+    // Poisson-like counts with ratios 1 : 2 : 3
+    fn counts(i: usize) -> [u16; 3] {
+        let jitter = [0, 2, 1, 3, 1, 0, 2][i % 7];
+        [10 + jitter, 20 + 2 * jitter, 30 + jitter]
+    }
+
+    fn learned() -> (ConsistencyMonitor, Feed) {
+        let config = ConsistencyConfig {
+            learning_samples: 20,
+            window: 5,
+            min_counts: 50,
+            drift_tolerance: 0.3,
+        };
+        let mut monitor = ConsistencyMonitor::new(config);
+        let mut feed = Feed {
+            reading: [100, 100, 100],
+        };
+        for i in 0..30 {
+            monitor.update(&feed.sample(counts(i)));
+        }
+        (monitor, feed)
+    }
+
+    #[test]
+    fn test_learn_ratios() {
+        let (monitor, _feed) = learned();
+        let ratios = monitor.ratios().unwrap();
+        assert!((ratios[0] - 0.5).abs() < 0.1);
+        assert!((ratios[2] - 1.5).abs() < 0.1);
+        assert_eq!(monitor.health(), [ChannelHealth::Healthy; 3]);
+    }
+
+    #[test]
+    fn test_skips_suspect_samples() {
+        let (mut monitor, mut feed) = learned();
+        for _ in 0..10 {
+            let mut suspect = feed.sample([0, 0, 0]);
+            suspect.quality.insert(SampleQuality::SUSPECT);
+            assert_eq!(monitor.update(&suspect), [ChannelHealth::Healthy; 3]);
+        }
+    }
+
+    #[test]
+    fn test_stuck_and_zero() {
+        let (mut monitor, mut feed) = learned();
+        for i in 0..5 {
+            let [_, second, third] = counts(i);
+            monitor.update(&feed.sample([0, second, third]));
+        }
+        assert_eq!(monitor.health()[0], ChannelHealth::Stuck);
+        assert_eq!(monitor.health()[1], ChannelHealth::Healthy);
+
+        feed.reading[0] = 0;
+        for i in 0..5 {
+            let [_, second, third] = counts(i);
+            monitor.update(&feed.sample([0, second, third]));
+        }
+        assert_eq!(monitor.health()[0], ChannelHealth::ZeroCount);
+
+        for i in 0..5 {
+            monitor.update(&feed.sample(counts(i)));
+        }
+        assert_eq!(monitor.health(), [ChannelHealth::Healthy; 3]);
+    }
+
+    #[test]
+    fn test_constant() {
+        let (mut monitor, mut feed) = learned();
+        for i in 0..5 {
+            let [first, second, _] = counts(i);
+            monitor.update(&feed.sample([first, second, 255]));
+        }
+        assert_eq!(monitor.health()[2], ChannelHealth::Constant);
+    }
+
+    #[test]
+    fn test_drifting() {
+        let (mut monitor, mut feed) = learned();
+        for i in 0..5 {
+            let [first, second, third] = counts(i);
+            monitor.update(&feed.sample([first, second * 2, third]));
+        }
+        assert_eq!(
+            monitor.health(),
+            [
+                ChannelHealth::Healthy,
+                ChannelHealth::Drifting,
+                ChannelHealth::Healthy
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quiet_environment() {
+        let (mut monitor, mut feed) = learned();
+        for _ in 0..5 {
+            monitor.update(&feed.sample([0, 0, 0]));
+        }
+        assert_eq!(monitor.health(), [ChannelHealth::Healthy; 3]);
+    }
+}
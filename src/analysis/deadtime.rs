@@ -0,0 +1,147 @@
+//! Dead-time Correction
+//!
+//! After each count a Geiger tube is blind for its dead time, so at high rates
+//! the measured rate `m` under-reports the true rate `n`. Two models relate
+//! them, for a dead time `tau`:
+//!
+//! - Non-paralyzable: `m = n / (1 + n * tau)`, counts during the dead time are lost
+//! - Paralyzable: `m = n * exp(-n * tau)`, counts during the dead time extend it
+//!
+//! A paralyzable tube reads at most `1 / (e * tau)`. Beyond that no true rate
+//! explains the measurement and the correction fails.
+
+use serde::*;
+
+// Convergence limits of the paralyzable inversion
+const MAX_ITERATIONS: usize = 100;
+const TOLERANCE: f64 = 1e-12;
+
+/// Dead-time model of a Geiger tube
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadTimeModel {
+    /// Counts during the dead time are lost without extending it
+    #[default]
+    NonParalyzable,
+    /// Counts during the dead time restart it
+    Paralyzable,
+}
+
+/// Result of a dead-time correction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeadTimeCorrection {
+    /// Corrected (true) count rate (cps)
+    pub rate: f64,
+    /// Sensitivity of the corrected rate to the measured rate, `dn / dm`
+    pub derivative: f64,
+}
+
+impl DeadTimeCorrection {
+    /// Ratio of corrected to measured rate
+    ///
+    /// # Arguments
+    /// `measured` - Measured count rate (cps)
+    pub fn factor(&self, measured: f64) -> f64 {
+        if measured > 0.0 {
+            self.rate / measured
+        } else {
+            1.0
+        }
+    }
+}
+
+impl DeadTimeModel {
+    /// Corrects a measured count rate
+    ///
+    /// Returns `None` if the measured rate is beyond what the model can
+    /// produce, i.e. the tube is saturated.
+    ///
+    /// # Arguments
+    /// `measured` - Measured count rate (cps)
+    /// `dead_time` - Dead time of the tube (s)
+    pub fn correct(self, measured: f64, dead_time: f64) -> Option<DeadTimeCorrection> {
+        if measured <= 0.0 || dead_time <= 0.0 {
+            return Some(DeadTimeCorrection {
+                rate: measured.max(0.0),
+                derivative: 1.0,
+            });
+        }
+        let y = measured * dead_time;
+        match self {
+            DeadTimeModel::NonParalyzable => {
+                let live = 1.0 - y;
+                if live <= 0.0 {
+                    return None;
+                }
+                Some(DeadTimeCorrection {
+                    rate: measured / live,
+                    derivative: 1.0 / (live * live),
+                })
+            }
+            DeadTimeModel::Paralyzable => {
+                // Solve x * exp(-x) = y on the lower branch x < 1, with x = n * tau
+                if y >= (-1.0f64).exp() {
+                    return None;
+                }
+                let mut x = y;
+                for _ in 0..MAX_ITERATIONS {
+                    let f = x * (-x).exp() - y;
+                    let step = f / ((1.0 - x) * (-x).exp());
+                    x -= step;
+                    if step.abs() < TOLERANCE {
+                        break;
+                    }
+                }
+                if !(0.0..1.0).contains(&x) {
+                    return None;
+                }
+                Some(DeadTimeCorrection {
+                    rate: x / dead_time,
+                    derivative: x.exp() / (1.0 - x),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_non_paralyzable() {
+        let correction = DeadTimeModel::NonParalyzable.correct(100.0, 0.001).unwrap();
+        assert!((correction.rate - 100.0 / 0.9).abs() < 1e-9);
+        assert!((correction.factor(100.0) - 1.0 / 0.9).abs() < 1e-9);
+        assert_eq!(DeadTimeModel::NonParalyzable.correct(1000.0, 0.001), None);
+    }
+
+    #[test]
+    fn test_paralyzable_round_trip() {
+        let dead_time = 0.0001;
+        for &true_rate in &[1.0, 100.0, 1000.0, 5000.0, 9000.0] {
+            let measured = true_rate * f64::exp(-true_rate * dead_time);
+            let correction = DeadTimeModel::Paralyzable
+                .correct(measured, dead_time)
+                .unwrap();
+            assert!((correction.rate - true_rate).abs() / true_rate < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_paralyzable_saturated() {
+        let dead_time = 0.0001;
+        let maximum = 1.0 / (std::f64::consts::E * dead_time);
+        assert_eq!(
+            DeadTimeModel::Paralyzable.correct(maximum * 1.01, dead_time),
+            None
+        );
+    }
+
+    #[test]
+    fn test_no_dead_time() {
+        let correction = DeadTimeModel::Paralyzable.correct(50.0, 0.0).unwrap();
+        assert_eq!(correction.rate, 50.0);
+        assert_eq!(correction.derivative, 1.0);
+    }
+}
@@ -0,0 +1,347 @@
+//! Accumulated Dose
+//!
+//! Integrates the calibrated particle flux of each tube into a running dose
+//! estimate. The combined dose is the mean of the three tubes, which all see
+//! the same environment.
+//!
+//! Suspect samples are not counted, as their flux is unknown. A tube with an
+//! unreliable dead-time correction, e.g. saturating during an SPE or SAA
+//! pass, only gives a lower bound of its flux. Its dose is counted, and also
+//! tracked separately with the time it was saturated, so the ground can see
+//! how much of the total is a lower bound.
+//!
+//! The accumulator state is checkpointed to a file at a configurable interval
+//! and resumed on start-up. Samples are ordered by their sequence number, as
+//! the UTC time may step backwards when the clock is set. A sample at or
+//! before the last accumulated sequence number is taken as replayed after a
+//! restart and ignored, unless its UTC time shows it was taken by a sampler
+//! started since. Samples replayed after a restart must therefore come from
+//! the run of the checkpoint or a later one.
+
+use crate::analysis::calibration::CalibratedSample;
+use crate::storage::write_atomic;
+use crate::{CounterError, CounterResult};
+use serde::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MS_PER_DAY: u64 = 86_400_000;
+
+/// Dose accumulator configuration
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DoseConfig {
+    /// Flux to dose conversion per tube (uGy per particle / (cm^2 sr))
+    pub conversion: [f64; 3],
+    /// Orbital period (ms)
+    pub orbit_period_ms: u64,
+    /// UTC time of an orbit start, e.g. an ascending node crossing (ms)
+    pub orbit_epoch_ms: u64,
+    /// Minimum time between two checkpoints (ms)
+    pub checkpoint_interval_ms: u64,
+    /// Path of the checkpoint file
+    pub checkpoint_path: PathBuf,
+}
+
+/// Dose per tube and combined (uGy)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DoseTotals {
+    /// Dose per tube
+    pub tubes: [f64; 3],
+    /// Mean dose of the three tubes
+    pub combined: f64,
+    /// Part of the dose per tube counted while the tube was saturated, which
+    /// is only a lower bound
+    pub saturated: [f64; 3],
+    /// Integration time with at least one saturated tube (ms)
+    pub saturated_ms: u64,
+}
+
+impl DoseTotals {
+    fn add(&mut self, dose: [f64; 3], saturated: [bool; 3], interval_ms: u64) {
+        for tube in 0..3 {
+            self.tubes[tube] += dose[tube];
+            if saturated[tube] {
+                self.saturated[tube] += dose[tube];
+            }
+        }
+        self.combined = self.tubes.iter().sum::<f64>() / 3.0;
+        if saturated.contains(&true) {
+            self.saturated_ms += interval_ms;
+        }
+    }
+
+    /// Whether part of the dose is only a lower bound
+    pub fn is_lower_bound(&self) -> bool {
+        self.saturated_ms > 0
+    }
+}
+
+/// Dose accumulated during one orbit or day
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DosePeriod {
+    /// Index of the orbit or day, counted from the epoch
+    pub index: u64,
+    /// Dose during the period
+    pub dose: DoseTotals,
+}
+
+/// Accumulated dose, as stored in the checkpoint file
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DoseState {
+    /// Sequence number of the last accumulated sample
+    pub last_sequence: u32,
+    /// UTC time of the last accumulated sample (ms)
+    pub last_utc_ms: u64,
+    /// Dose since the accumulator was first started
+    pub lifetime: DoseTotals,
+    /// Current orbit
+    pub orbit: DosePeriod,
+    /// Previous orbit, once one has completed
+    pub previous_orbit: Option<DosePeriod>,
+    /// Current UTC day
+    pub day: DosePeriod,
+    /// Previous UTC day, once one has completed
+    pub previous_day: Option<DosePeriod>,
+}
+
+impl DosePeriod {
+    // Periods only move forward, a clock set backwards keeps the current one
+    fn roll(current: &mut DosePeriod, previous: &mut Option<DosePeriod>, index: u64) {
+        if index > current.index {
+            if current.dose != DoseTotals::default() {
+                *previous = Some(*current);
+            }
+            *current = DosePeriod {
+                index,
+                dose: DoseTotals::default(),
+            };
+        }
+    }
+}
+
+/// Running dose estimate with checkpointing
+pub struct DoseAccumulator {
+    config: DoseConfig,
+    state: DoseState,
+    last_checkpoint_ms: u64,
+}
+
+impl DoseAccumulator {
+    /// Constructor
+    ///
+    /// Resumes from the checkpoint file if it exists, or starts from zero.
+    ///
+    /// # Arguments
+    /// `config` - Accumulator configuration
+    pub fn open(config: DoseConfig) -> CounterResult<Self> {
+        let state = if config.checkpoint_path.exists() {
+            read_checkpoint(&config.checkpoint_path)?
+        } else {
+            DoseState::default()
+        };
+        Ok(DoseAccumulator {
+            last_checkpoint_ms: state.last_utc_ms,
+            config,
+            state,
+        })
+    }
+
+    /// Current accumulated dose
+    pub fn state(&self) -> &DoseState {
+        &self.state
+    }
+
+    /// Dose since the accumulator was first started
+    pub fn lifetime(&self) -> DoseTotals {
+        self.state.lifetime
+    }
+
+    /// Dose during the current orbit
+    pub fn orbit(&self) -> DoseTotals {
+        self.state.orbit.dose
+    }
+
+    /// Dose during the current UTC day
+    pub fn day(&self) -> DoseTotals {
+        self.state.day.dose
+    }
+
+    /// Accumulate Sample
+    ///
+    /// Adds the dose of a calibrated sample and writes a checkpoint once the
+    /// checkpoint interval has passed. Returns whether the sample was counted,
+    /// which it is not when it is suspect or already counted.
+    ///
+    /// # Arguments
+    /// `sample` - Calibrated sample
+    pub fn accumulate(&mut self, sample: &CalibratedSample) -> CounterResult<bool> {
+        if !sample.sample.quality.is_usable() {
+            return Ok(false);
+        }
+        let (sequence, utc_ms) = (sample.sample.sequence, sample.sample.utc_ms);
+        if sequence <= self.state.last_sequence && utc_ms <= self.state.last_utc_ms {
+            return Ok(false);
+        }
+
+        let interval = sample.sample.interval_secs();
+        let mut dose = [0.0; 3];
+        let mut saturated = [false; 3];
+        for tube in 0..3 {
+            let rate = &sample.tubes[tube];
+            dose[tube] = rate.flux.value.max(0.0) * interval * self.config.conversion[tube];
+            saturated[tube] = !rate.reliable;
+        }
+        let interval_ms = u64::from(sample.sample.interval_ms);
+
+        let orbit =
+            utc_ms.saturating_sub(self.config.orbit_epoch_ms) / self.config.orbit_period_ms.max(1);
+        DosePeriod::roll(&mut self.state.orbit, &mut self.state.previous_orbit, orbit);
+        DosePeriod::roll(
+            &mut self.state.day,
+            &mut self.state.previous_day,
+            utc_ms / MS_PER_DAY,
+        );
+
+        self.state.lifetime.add(dose, saturated, interval_ms);
+        self.state.orbit.dose.add(dose, saturated, interval_ms);
+        self.state.day.dose.add(dose, saturated, interval_ms);
+        self.state.last_sequence = sequence;
+        self.state.last_utc_ms = utc_ms;
+
+        // A clock set backwards restarts the checkpoint interval
+        if utc_ms < self.last_checkpoint_ms
+            || utc_ms - self.last_checkpoint_ms >= self.config.checkpoint_interval_ms
+        {
+            self.checkpoint()?;
+        }
+        Ok(true)
+    }
+
+    /// Checkpoint
+    ///
+    /// Writes the accumulated dose to the checkpoint file, replacing the
+    /// previous checkpoint atomically.
+    pub fn checkpoint(&mut self) -> CounterResult<()> {
+        let path = &self.config.checkpoint_path;
+        let data = bincode::serialize(&self.state)
+            .map_err(|_| CounterError::parsing_failure("Dose Checkpoint"))?;
+        write_atomic(path, &data)?;
+
+        self.last_checkpoint_ms = self.state.last_utc_ms;
+        Ok(())
+    }
+}
+
+fn read_checkpoint(path: &Path) -> CounterResult<DoseState> {
+    let data = fs::read(path).map_err(|error| CounterError::file_error(path, &error))?;
+    bincode::deserialize(&data).map_err(|_| CounterError::parsing_failure("Dose Checkpoint"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SampleQuality;
+    use crate::test_util::{calibrated, temp_path, tube_rate};
+
+    fn sample(sequence: u32, utc_ms: u64, flux: f64) -> CalibratedSample {
+        let tube = tube_rate(0, 0.0, flux);
+        let mut sample = calibrated(utc_ms, 1000, [tube.clone(), tube.clone(), tube]);
+        sample.sample.sequence = sequence;
+        sample
+    }
+
+    fn config(name: &str) -> DoseConfig {
+        let checkpoint_path = temp_path(&format!("dose-{}.bin", name));
+        DoseConfig {
+            conversion: [1.0, 2.0, 3.0],
+            orbit_period_ms: 10_000,
+            orbit_epoch_ms: 0,
+            checkpoint_interval_ms: 5_000,
+            checkpoint_path,
+        }
+    }
+
+    #[test]
+    fn test_accumulate() {
+        let mut dose = DoseAccumulator::open(config("accumulate")).unwrap();
+        for second in 1..=12 {
+            dose.accumulate(&sample(second, u64::from(second) * 1000, 1.0))
+                .unwrap();
+        }
+        assert_eq!(dose.lifetime().tubes, [12.0, 24.0, 36.0]);
+        assert_eq!(dose.lifetime().combined, 24.0);
+        assert_eq!(dose.orbit().tubes, [3.0, 6.0, 9.0]);
+        assert_eq!(
+            dose.state().previous_orbit.unwrap().dose.tubes,
+            [9.0, 18.0, 27.0]
+        );
+        assert_eq!(dose.day().tubes, [12.0, 24.0, 36.0]);
+        let _ = fs::remove_file(&dose.config.checkpoint_path);
+    }
+
+    #[test]
+    fn test_resume_without_double_counting() {
+        let config = config("resume");
+        let mut dose = DoseAccumulator::open(config.clone()).unwrap();
+        for second in 1..=6 {
+            dose.accumulate(&sample(second, u64::from(second) * 1000, 1.0))
+                .unwrap();
+        }
+        // Checkpoint written at 5 s, the sample at 6 s is lost on restart
+        let mut dose = DoseAccumulator::open(config.clone()).unwrap();
+        assert_eq!(dose.lifetime().tubes[0], 5.0);
+
+        for second in 1..=7 {
+            dose.accumulate(&sample(second, u64::from(second) * 1000, 1.0))
+                .unwrap();
+        }
+        assert_eq!(dose.lifetime().tubes[0], 7.0);
+        let _ = fs::remove_file(&config.checkpoint_path);
+    }
+
+    #[test]
+    fn test_sampler_restart_and_clock_step() {
+        let config = config("restart");
+        let mut dose = DoseAccumulator::open(config.clone()).unwrap();
+        for second in 1..=3 {
+            assert!(dose
+                .accumulate(&sample(second, 100_000 + u64::from(second) * 1000, 1.0))
+                .unwrap());
+        }
+        // Clock set backwards, the sampler keeps counting
+        assert!(dose.accumulate(&sample(4, 50_000, 1.0)).unwrap());
+        assert!(dose.accumulate(&sample(5, 51_000, 1.0)).unwrap());
+        // Sampler restarted, its sequence starts over at a later UTC time
+        assert!(dose.accumulate(&sample(0, 60_000, 1.0)).unwrap());
+        assert!(!dose.accumulate(&sample(0, 60_000, 1.0)).unwrap());
+        assert_eq!(dose.lifetime().tubes[0], 6.0);
+        let _ = fs::remove_file(&config.checkpoint_path);
+    }
+
+    #[test]
+    fn test_suspect_and_saturated_samples() {
+        let config = config("unusable");
+        let mut dose = DoseAccumulator::open(config.clone()).unwrap();
+        let mut suspect = sample(1, 1000, 1.0);
+        suspect.sample.quality.insert(SampleQuality::SUSPECT);
+        assert!(!dose.accumulate(&suspect).unwrap());
+        assert_eq!(dose.lifetime(), DoseTotals::default());
+
+        let mut wrapped = sample(2, 2000, 1.0);
+        wrapped.sample.quality.insert(SampleQuality::WRAPPED);
+        assert!(dose.accumulate(&wrapped).unwrap());
+        assert!(!dose.lifetime().is_lower_bound());
+
+        // A saturated tube counts its lower bound, tracked separately
+        let mut saturated = sample(3, 3000, 1.0);
+        saturated.tubes[1].reliable = false;
+        assert!(dose.accumulate(&saturated).unwrap());
+        let lifetime = dose.lifetime();
+        assert_eq!(lifetime.tubes, [2.0, 4.0, 6.0]);
+        assert_eq!(lifetime.saturated, [0.0, 2.0, 0.0]);
+        assert_eq!(lifetime.saturated_ms, 1000);
+        assert!(lifetime.is_lower_bound());
+        assert_eq!(dose.day().saturated_ms, 1000);
+        let _ = fs::remove_file(&config.checkpoint_path);
+    }
+}
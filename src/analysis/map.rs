@@ -0,0 +1,478 @@
+//! Radiation Map
+//!
+//! Bins the combined count rate of geolocated samples into a latitude and
+//! longitude grid. Each cell keeps the number of samples, the mean and the
+//! largest rate. Samples with an unreliable dead-time correction, e.g. from
+//! tubes saturating in the South Atlantic Anomaly, are binned with their
+//! lower-bound rate and counted per cell, so a cell holding any of them is
+//! known to be biased low. Only visited cells are stored, so a fine grid costs
+//! no more than the area covered. The map is persisted with atomic file
+//! replacement, like the dose checkpoint, and exported as a compact
+//! [`MapProduct`].
+//!
+//! The product is encoded big-endian as a 10-byte header followed by one
+//! 16-byte record per visited cell:
+//!
+//! | Field          | Type | Description                           |
+//! |----------------|------|---------------------------------------|
+//! | version        | u8   | Product format version                |
+//! | reserved       | u8   |                                       |
+//! | lat_resolution | u16  | Latitude step (mdeg)                  |
+//! | lon_resolution | u16  | Longitude step (mdeg)                 |
+//! | cells          | u32  | Number of cell records                |
+//! | index          | u32  | Row-major cell index, from -90 / -180 |
+//! | samples        | u16  | Samples in the cell, saturating       |
+//! | saturated      | u16  | Saturated samples, saturating         |
+//! | mean           | f32  | Mean rate (cps)                       |
+//! | max            | f32  | Largest rate (cps)                    |
+//!
+//! The resolutions are whole millidegrees, so the ground rebuilds the exact
+//! grid from the header.
+
+use crate::analysis::calibration::CalibratedSample;
+use crate::objects::GeoPosition;
+use crate::storage::write_atomic;
+use crate::{CounterError, CounterResult};
+use serde::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const PRODUCT_VERSION: u8 = 2;
+const HEADER_LEN: usize = 10;
+const RECORD_LEN: usize = 16;
+
+// Resolution limits (deg), keeping the cell index of the product within a u32
+const MIN_RESOLUTION: f64 = 0.01;
+const MAX_RESOLUTION: f64 = 60.0;
+
+/// Statistics of a map cell
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MapCell {
+    /// Number of samples in the cell
+    pub samples: u32,
+    /// Sum of the rates (cps)
+    pub sum: f64,
+    /// Largest rate (cps)
+    pub max: f64,
+    /// Samples whose rate is only a lower bound
+    pub saturated: u32,
+}
+
+impl MapCell {
+    /// Mean rate (cps), `None` for an empty cell
+    pub fn mean(&self) -> Option<f64> {
+        if self.samples == 0 {
+            None
+        } else {
+            Some(self.sum / f64::from(self.samples))
+        }
+    }
+}
+
+/// Latitude and longitude grid of count rates
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RadiationMap {
+    lat_resolution: f64,
+    lon_resolution: f64,
+    rows: usize,
+    columns: usize,
+    cells: BTreeMap<usize, MapCell>,
+}
+
+impl RadiationMap {
+    /// Constructor
+    ///
+    /// Resolutions must lie between 0.01 and 60 degrees and be whole
+    /// millidegrees, as written to the product.
+    ///
+    /// # Arguments
+    /// `lat_resolution` - Latitude step of the grid (deg)
+    /// `lon_resolution` - Longitude step of the grid (deg)
+    pub fn new(lat_resolution: f64, lon_resolution: f64) -> CounterResult<Self> {
+        let valid = |resolution: f64| {
+            (MIN_RESOLUTION..=MAX_RESOLUTION).contains(&resolution)
+                && (resolution * 1000.0).round() / 1000.0 == resolution
+        };
+        if !valid(lat_resolution) || !valid(lon_resolution) {
+            return Err(CounterError::GenericError);
+        }
+        let rows = (180.0 / lat_resolution).ceil() as usize;
+        let columns = (360.0 / lon_resolution).ceil() as usize;
+        Ok(RadiationMap {
+            lat_resolution,
+            lon_resolution,
+            rows,
+            columns,
+            cells: BTreeMap::new(),
+        })
+    }
+
+    /// Open
+    ///
+    /// Loads a persisted map, or creates an empty one if the file does not
+    /// exist. Fails if the map was saved with a different resolution, rather
+    /// than starting a new map which would replace it on the next save.
+    ///
+    /// # Arguments
+    /// `path` - Path of the persisted map
+    /// `lat_resolution` - Latitude step of the grid (deg)
+    /// `lon_resolution` - Longitude step of the grid (deg)
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        lat_resolution: f64,
+        lon_resolution: f64,
+    ) -> CounterResult<Self> {
+        if !path.as_ref().exists() {
+            return Self::new(lat_resolution, lon_resolution);
+        }
+        let map = Self::load(path)?;
+        if map.lat_resolution != lat_resolution || map.lon_resolution != lon_resolution {
+            return Err(CounterError::GenericError);
+        }
+        Ok(map)
+    }
+
+    /// Loads a map saved with [`RadiationMap::save`]
+    ///
+    /// # Arguments
+    /// `path` - Path of the persisted map
+    pub fn load<P: AsRef<Path>>(path: P) -> CounterResult<Self> {
+        let data = fs::read(&path).map_err(|error| CounterError::file_error(&path, &error))?;
+        let failure = || CounterError::parsing_failure("Radiation Map");
+        let map: RadiationMap = bincode::deserialize(&data).map_err(|_| failure())?;
+        let grid = Self::new(map.lat_resolution, map.lon_resolution).map_err(|_| failure())?;
+        let last = map.cells.keys().next_back().copied().unwrap_or_default();
+        if map.rows != grid.rows || map.columns != grid.columns || last >= map.rows * map.columns {
+            return Err(failure());
+        }
+        Ok(map)
+    }
+
+    /// Save
+    ///
+    /// Writes the map to a file, replacing the previous map atomically.
+    ///
+    /// # Arguments
+    /// `path` - Path of the persisted map
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> CounterResult<()> {
+        let path = path.as_ref();
+        let data =
+            bincode::serialize(self).map_err(|_| CounterError::parsing_failure("Radiation Map"))?;
+        write_atomic(path, &data)
+    }
+
+    /// Latitude and longitude step of the grid (deg)
+    pub fn resolution(&self) -> (f64, f64) {
+        (self.lat_resolution, self.lon_resolution)
+    }
+
+    /// Index of the cell holding a position
+    ///
+    /// # Arguments
+    /// `latitude` - Latitude (deg)
+    /// `longitude` - Longitude (deg)
+    pub fn index(&self, latitude: f64, longitude: f64) -> Option<usize> {
+        if !(-90.0..=90.0).contains(&latitude) || !longitude.is_finite() {
+            return None;
+        }
+        let longitude = (longitude + 180.0).rem_euclid(360.0);
+        let row = (((latitude + 90.0) / self.lat_resolution) as usize).min(self.rows - 1);
+        let column = ((longitude / self.lon_resolution) as usize).min(self.columns - 1);
+        Some(row * self.columns + column)
+    }
+
+    /// Cell holding a position
+    ///
+    /// # Arguments
+    /// `latitude` - Latitude (deg)
+    /// `longitude` - Longitude (deg)
+    pub fn cell(&self, latitude: f64, longitude: f64) -> Option<&MapCell> {
+        self.cells.get(&self.index(latitude, longitude)?)
+    }
+
+    /// Add Rate
+    ///
+    /// Adds a rate measured at a position. Returns whether the position lies
+    /// on the grid.
+    ///
+    /// # Arguments
+    /// `position` - Spacecraft position
+    /// `rate` - Count rate (cps)
+    pub fn add(&mut self, position: &GeoPosition, rate: f64) -> bool {
+        self.insert(position, rate, false)
+    }
+
+    fn insert(&mut self, position: &GeoPosition, rate: f64, saturated: bool) -> bool {
+        match self.index(position.latitude, position.longitude) {
+            Some(index) => {
+                let cell = self.cells.entry(index).or_default();
+                cell.max = if cell.samples == 0 {
+                    rate
+                } else {
+                    cell.max.max(rate)
+                };
+                cell.samples = cell.samples.saturating_add(1);
+                cell.sum += rate;
+                if saturated {
+                    cell.saturated = cell.saturated.saturating_add(1);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Add Sample
+    ///
+    /// Adds the mean dead-time corrected rate of the three tubes, counted as
+    /// saturated when a tube has an unreliable dead-time correction. Returns
+    /// whether the sample was added, which it is not when it is suspect or
+    /// lies off the grid.
+    ///
+    /// # Arguments
+    /// `sample` - Calibrated sample
+    /// `position` - Spacecraft position at the time of the sample
+    pub fn add_sample(&mut self, sample: &CalibratedSample, position: &GeoPosition) -> bool {
+        if !sample.sample.quality.is_usable() {
+            return false;
+        }
+        let rate = sample
+            .tubes
+            .iter()
+            .map(|tube| tube.corrected_rate.value)
+            .sum::<f64>()
+            / 3.0;
+        self.insert(position, rate, !sample.is_reliable())
+    }
+
+    /// Empties every cell, e.g. after the map was downlinked
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    /// Number of cells holding at least one sample
+    pub fn visited(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Compact product of the visited cells
+    pub fn export(&self) -> MapProduct {
+        MapProduct {
+            lat_resolution: self.lat_resolution,
+            lon_resolution: self.lon_resolution,
+            cells: self
+                .cells
+                .iter()
+                .map(|(index, cell)| ProductCell {
+                    index: *index as u32,
+                    samples: cell.samples.min(u32::from(u16::MAX)) as u16,
+                    saturated: cell.saturated.min(u32::from(u16::MAX)) as u16,
+                    mean: cell.mean().unwrap_or_default() as f32,
+                    max: cell.max as f32,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Cell record of a map product
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProductCell {
+    /// Row-major cell index, counted from latitude -90 and longitude -180
+    pub index: u32,
+    /// Samples in the cell, saturating
+    pub samples: u16,
+    /// Samples whose rate is only a lower bound, saturating
+    pub saturated: u16,
+    /// Mean rate (cps)
+    pub mean: f32,
+    /// Largest rate (cps)
+    pub max: f32,
+}
+
+/// Compact map product for downlink
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapProduct {
+    /// Latitude step of the grid (deg)
+    pub lat_resolution: f64,
+    /// Longitude step of the grid (deg)
+    pub lon_resolution: f64,
+    /// Visited cells
+    pub cells: Vec<ProductCell>,
+}
+
+impl MapProduct {
+    /// Encodes the product
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_LEN + RECORD_LEN * self.cells.len());
+        data.push(PRODUCT_VERSION);
+        data.push(0);
+        data.extend_from_slice(&((self.lat_resolution * 1000.0).round() as u16).to_be_bytes());
+        data.extend_from_slice(&((self.lon_resolution * 1000.0).round() as u16).to_be_bytes());
+        data.extend_from_slice(&(self.cells.len() as u32).to_be_bytes());
+        for cell in &self.cells {
+            data.extend_from_slice(&cell.index.to_be_bytes());
+            data.extend_from_slice(&cell.samples.to_be_bytes());
+            data.extend_from_slice(&cell.saturated.to_be_bytes());
+            data.extend_from_slice(&cell.mean.to_be_bytes());
+            data.extend_from_slice(&cell.max.to_be_bytes());
+        }
+        data
+    }
+
+    /// Decodes a product encoded with [`MapProduct::to_bytes`]
+    ///
+    /// # Arguments
+    /// `data` - Encoded product
+    pub fn from_bytes(data: &[u8]) -> CounterResult<Self> {
+        let failure = || CounterError::parsing_failure("Map Product");
+        if data.len() < HEADER_LEN || data[0] != PRODUCT_VERSION {
+            return Err(failure());
+        }
+        let u16_at = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+        let u32_at =
+            |at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+
+        let count = u32_at(6) as usize;
+        if data.len() != HEADER_LEN + count * RECORD_LEN {
+            return Err(failure());
+        }
+        let cells = (0..count)
+            .map(|cell| {
+                let at = HEADER_LEN + cell * RECORD_LEN;
+                ProductCell {
+                    index: u32_at(at),
+                    samples: u16_at(at + 4),
+                    saturated: u16_at(at + 6),
+                    mean: f32::from_bits(u32_at(at + 8)),
+                    max: f32::from_bits(u32_at(at + 12)),
+                }
+            })
+            .collect();
+
+        Ok(MapProduct {
+            lat_resolution: f64::from(u16_at(2)) / 1000.0,
+            lon_resolution: f64::from(u16_at(4)) / 1000.0,
+            cells,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SampleQuality;
+    use crate::test_util::{calibrated, temp_path, tube_rate};
+
+    fn position(latitude: f64, longitude: f64) -> GeoPosition {
+        GeoPosition {
+            latitude,
+            longitude,
+            altitude: 500.0,
+        }
+    }
+
+    #[test]
+    fn test_binning() {
+        let mut map = RadiationMap::new(10.0, 20.0).unwrap();
+        assert!(map.add(&position(-25.0, -45.0), 100.0));
+        assert!(map.add(&position(-21.0, -59.0), 300.0));
+        assert!(map.add(&position(45.0, 170.0), 5.0));
+        assert!(map.add(&position(90.0, 180.0), 1.0));
+        assert!(!map.add(&position(95.0, 0.0), 1.0));
+
+        let cell = map.cell(-29.0, -41.0).unwrap();
+        assert_eq!(cell.samples, 2);
+        assert_eq!(cell.mean(), Some(200.0));
+        assert_eq!(cell.max, 300.0);
+        assert_eq!(map.index(0.0, 180.0), map.index(0.0, -180.0));
+        assert_eq!(map.visited(), 3);
+    }
+
+    #[test]
+    fn test_export() {
+        let mut map = RadiationMap::new(2.5, 5.0).unwrap();
+        map.add(&position(-25.0, -45.0), 100.0);
+        map.add(&position(10.0, 10.0), 7.5);
+
+        let bytes = map.export().to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 2 * RECORD_LEN);
+        let product = MapProduct::from_bytes(&bytes).unwrap();
+        assert_eq!(product, map.export());
+        assert_eq!(product.lat_resolution, 2.5);
+        assert_eq!(product.cells[1].mean, 7.5);
+
+        assert_eq!(
+            MapProduct::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(CounterError::parsing_failure("Map Product"))
+        );
+    }
+
+    #[test]
+    fn test_persistence() {
+        let path = temp_path("map.bin");
+
+        let mut map = RadiationMap::open(&path, 5.0, 5.0).unwrap();
+        map.add(&position(-25.0, -45.0), 100.0);
+        map.save(&path).unwrap();
+
+        let restored = RadiationMap::open(&path, 5.0, 5.0).unwrap();
+        assert_eq!(restored, map);
+        // A map of another resolution is not replaced by an empty one
+        assert_eq!(
+            RadiationMap::open(&path, 1.0, 1.0),
+            Err(CounterError::GenericError)
+        );
+        assert_eq!(RadiationMap::load(&path).unwrap(), map);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_resolution_limits() {
+        assert!(RadiationMap::new(0.001, 1.0).is_err());
+        assert!(RadiationMap::new(1.0, 61.0).is_err());
+        // The product carries whole millidegrees
+        assert!(RadiationMap::new(0.0125, 1.0).is_err());
+        let map = RadiationMap::new(0.013, 2.5).unwrap();
+        let product = MapProduct::from_bytes(&map.export().to_bytes()).unwrap();
+        assert_eq!(
+            (product.lat_resolution, product.lon_resolution),
+            map.resolution()
+        );
+
+        // Only visited cells are stored
+        let path = temp_path("map-fine.bin");
+        let mut map = RadiationMap::new(0.01, 0.01).unwrap();
+        map.add(&position(89.995, 179.995), 1.0);
+        map.save(&path).unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < 1000);
+        let restored = RadiationMap::load(&path).unwrap();
+        assert_eq!(restored.export().cells[0].index, 18_000 * 36_000 - 1);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_add_suspect_and_saturated_samples() {
+        let mut map = RadiationMap::new(10.0, 10.0).unwrap();
+        let tube = tube_rate(10, 10.0, 5.0);
+        let sample = calibrated(1000, 1000, [tube.clone(), tube.clone(), tube]);
+        let mut suspect = sample.clone();
+        suspect.sample.quality.insert(SampleQuality::SUSPECT);
+        assert!(!map.add_sample(&suspect, &position(0.0, 0.0)));
+        assert_eq!(map.visited(), 0);
+
+        assert!(map.add_sample(&sample, &position(0.0, 0.0)));
+        let mut saturated = sample.clone();
+        saturated.tubes[0].reliable = false;
+        saturated.tubes[0].corrected_rate.value = 40.0;
+        assert!(map.add_sample(&saturated, &position(0.0, 0.0)));
+
+        let cell = map.cell(0.0, 0.0).unwrap();
+        assert_eq!(cell.samples, 2);
+        assert_eq!(cell.saturated, 1);
+        assert_eq!(cell.max, 20.0);
+        let product = MapProduct::from_bytes(&map.export().to_bytes()).unwrap();
+        assert_eq!(product.cells[0].saturated, 1);
+    }
+}
@@ -0,0 +1,27 @@
+mod alarm;
+mod background;
+mod calibration;
+mod coincidence;
+mod consistency;
+mod deadtime;
+mod dose;
+mod map;
+mod resets;
+mod saa;
+mod spe;
+mod stats;
+mod trend;
+
+pub use crate::analysis::alarm::*;
+pub use crate::analysis::background::*;
+pub use crate::analysis::calibration::*;
+pub use crate::analysis::coincidence::*;
+pub use crate::analysis::consistency::*;
+pub use crate::analysis::deadtime::*;
+pub use crate::analysis::dose::*;
+pub use crate::analysis::map::*;
+pub use crate::analysis::resets::*;
+pub use crate::analysis::saa::*;
+pub use crate::analysis::spe::*;
+pub use crate::analysis::stats::*;
+pub use crate::analysis::trend::*;
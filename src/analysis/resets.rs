@@ -0,0 +1,502 @@
+//! Reset Correlation
+//!
+//! Links resets of the board with the radiation environment in the window
+//! before each reset, as evidence for single-event effects on the board
+//! electronics. Resets are detected from increments of the board reset
+//! counters between two reads. Manual resets are commanded and therefore not
+//! correlated.
+//!
+//! The window ends before the reset, so it does not hold samples taken after
+//! the board restarted. A reset restarts the radiation counters as well, which
+//! gives a suspect sample, and the window ends at the last sample before the
+//! first suspect sample since the previous read of the reset counters. Without
+//! one it ends at the previous read, the last time the board was known not to
+//! have reset. Reading the reset counters at every sample keeps the two close.
+//!
+//! Suspect samples do not enter the window or the exposure. Samples with an
+//! unreliable dead-time correction, e.g. from saturating tubes, only give a
+//! lower bound of the rate and dose rate. They are kept and counted as high
+//! radiation, both for the reset record and for the exposure.
+//!
+//! Every sample also adds its integration time to the exposure of its dose
+//! rate bin, so the likelihood table gives the number of resets per day of
+//! exposure at each dose rate.
+
+use crate::analysis::calibration::CalibratedSample;
+use crate::objects::ResetCounters;
+use std::collections::VecDeque;
+
+const MS_PER_DAY: f64 = 86_400_000.0;
+
+/// Cause of an uncommanded reset
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ResetCause {
+    /// Supply voltage dropped below the brown-out level
+    BrownOut,
+    /// Microcontroller malfunction detected by the firmware
+    AutomaticSoftware,
+    /// Communications watchdog timed out
+    Watchdog,
+}
+
+/// Reset correlation configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResetCorrelationConfig {
+    /// Length of the window before a reset (ms)
+    pub window_ms: u64,
+    /// Flux to dose conversion per tube (uGy per particle / (cm^2 sr))
+    pub conversion: [f64; 3],
+    /// Dose rate above which a reset counts as during high radiation (uGy/h)
+    pub high_dose_rate: f64,
+    /// Upper edges of the dose rate bins, ascending (uGy/h). A last bin
+    /// covers everything above the highest edge.
+    pub dose_rate_bins: Vec<f64>,
+}
+
+impl Default for ResetCorrelationConfig {
+    fn default() -> Self {
+        ResetCorrelationConfig {
+            window_ms: 300_000,
+            conversion: [1.0; 3],
+            high_dose_rate: 100.0,
+            dose_rate_bins: vec![1.0, 10.0, 100.0, 1000.0],
+        }
+    }
+}
+
+/// Radiation environment before a reset
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResetRecord {
+    /// UTC time the reset was detected (ms)
+    pub utc_ms: u64,
+    /// End of the window before the reset, exclusive, UTC (ms)
+    pub window_end_ms: u64,
+    /// Cause of the reset
+    pub cause: ResetCause,
+    /// Number of resets since the previous read of the counters
+    pub count: u8,
+    /// Samples in the window before the reset
+    pub samples: usize,
+    /// Mean combined count rate in the window (cps)
+    pub mean_rate: f64,
+    /// Highest combined count rate in the window (cps)
+    pub peak_rate: f64,
+    /// Mean dose rate in the window (uGy/h)
+    pub dose_rate: f64,
+    /// Samples in the window with an unreliable dead-time correction, which
+    /// make the rates above lower bounds
+    pub saturated: usize,
+    /// Whether the dose rate was above the high radiation level, or the
+    /// window holds saturated samples
+    pub high_radiation: bool,
+}
+
+/// Reset likelihood at a range of dose rates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LikelihoodBin {
+    /// Lower edge of the dose rate range (uGy/h)
+    pub lower: f64,
+    /// Upper edge of the dose rate range, infinite for the last bin (uGy/h)
+    pub upper: f64,
+    /// Time spent in the range (ms)
+    pub exposure_ms: u64,
+    /// Resets with a window dose rate in the range
+    pub resets: u64,
+}
+
+impl LikelihoodBin {
+    /// Resets per day of exposure, once there is exposure
+    pub fn resets_per_day(&self) -> Option<f64> {
+        if self.exposure_ms == 0 {
+            None
+        } else {
+            Some(self.resets as f64 * MS_PER_DAY / self.exposure_ms as f64)
+        }
+    }
+}
+
+struct WindowSample {
+    utc_ms: u64,
+    interval_ms: u64,
+    rate: f64,
+    dose_rate: f64,
+    saturated: bool,
+}
+
+/// Correlates board resets with the radiation level
+pub struct ResetCorrelator {
+    config: ResetCorrelationConfig,
+    window: VecDeque<WindowSample>,
+    counters: Option<(ResetCounters, u64)>,
+    // UTC time of the first suspect sample since the counters were read
+    break_ms: Option<u64>,
+    records: Vec<ResetRecord>,
+    table: Vec<LikelihoodBin>,
+}
+
+impl ResetCorrelator {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// `config` - Correlation configuration
+    pub fn new(config: ResetCorrelationConfig) -> Self {
+        let mut table = Vec::new();
+        let mut lower = 0.0;
+        for upper in config
+            .dose_rate_bins
+            .iter()
+            .copied()
+            .chain(std::iter::once(f64::INFINITY))
+        {
+            table.push(LikelihoodBin {
+                lower,
+                upper,
+                exposure_ms: 0,
+                resets: 0,
+            });
+            lower = upper;
+        }
+        ResetCorrelator {
+            config,
+            window: VecDeque::new(),
+            counters: None,
+            break_ms: None,
+            records: Vec::new(),
+            table,
+        }
+    }
+
+    // Dose rate bin, at least the bin above the high radiation level for a
+    // saturated dose rate
+    fn bin(&self, dose_rate: f64, saturated: bool) -> usize {
+        let bin = |dose_rate: f64| {
+            self.table
+                .iter()
+                .position(|bin| dose_rate < bin.upper)
+                .unwrap_or(self.table.len() - 1)
+        };
+        let high = self
+            .table
+            .iter()
+            .position(|bin| self.config.high_dose_rate < bin.upper)
+            .unwrap_or(self.table.len() - 1);
+        if saturated {
+            bin(dose_rate).max(high)
+        } else {
+            bin(dose_rate)
+        }
+    }
+
+    /// Update
+    ///
+    /// Adds a sample to the window and its integration time to the exposure
+    /// of its dose rate. A suspect sample marks a possible reset, and a
+    /// saturated sample counts as high radiation.
+    ///
+    /// # Arguments
+    /// `sample` - Calibrated sample
+    pub fn update(&mut self, sample: &CalibratedSample) {
+        let utc_ms = sample.sample.utc_ms;
+        if !sample.sample.quality.is_usable() {
+            self.break_ms.get_or_insert(utc_ms);
+            return;
+        }
+        let interval_ms = u64::from(sample.sample.interval_ms);
+        if interval_ms == 0 {
+            return;
+        }
+        let saturated = !sample.is_reliable();
+        let mut rate = 0.0;
+        let mut dose_rate = 0.0;
+        for (tube, conversion) in sample.tubes.iter().zip(self.config.conversion.iter()) {
+            rate += tube.corrected_rate.value / 3.0;
+            // uGy/s to uGy/h
+            dose_rate += tube.flux.value.max(0.0) * conversion * 3600.0 / 3.0;
+        }
+
+        let bin = self.bin(dose_rate, saturated);
+        self.table[bin].exposure_ms += interval_ms;
+
+        self.window.push_back(WindowSample {
+            utc_ms,
+            interval_ms,
+            rate,
+            dose_rate,
+            saturated,
+        });
+        // Keep the window before the last read of the counters
+        let read_ms = self.counters.map_or(utc_ms, |(_, read_ms)| read_ms);
+        let oldest = utc_ms.min(read_ms).saturating_sub(self.config.window_ms);
+        while self
+            .window
+            .front()
+            .is_some_and(|sample| sample.utc_ms < oldest)
+        {
+            self.window.pop_front();
+        }
+    }
+
+    /// Check Counters
+    ///
+    /// Compares the reset counters with the previous read and records every
+    /// uncommanded reset in between. The first read only sets the reference.
+    ///
+    /// # Arguments
+    /// `counters` - Reset counters read from the board
+    /// `utc_ms` - UTC time of the read (ms)
+    pub fn check(&mut self, counters: ResetCounters, utc_ms: u64) -> Vec<ResetRecord> {
+        let break_ms = self.break_ms.take();
+        let (previous, previous_ms) = match self.counters.replace((counters, utc_ms)) {
+            Some(previous) => previous,
+            None => return Vec::new(),
+        };
+
+        let resets = [
+            (
+                ResetCause::BrownOut,
+                counters.brown_out.wrapping_sub(previous.brown_out),
+            ),
+            (
+                ResetCause::AutomaticSoftware,
+                counters
+                    .automatic_software
+                    .wrapping_sub(previous.automatic_software),
+            ),
+            (
+                ResetCause::Watchdog,
+                counters.watchdog.wrapping_sub(previous.watchdog),
+            ),
+        ];
+
+        let window_end_ms = break_ms.unwrap_or(previous_ms + 1);
+        let oldest = window_end_ms.saturating_sub(self.config.window_ms);
+        let window: Vec<&WindowSample> = self
+            .window
+            .iter()
+            .filter(|sample| sample.utc_ms >= oldest && sample.utc_ms < window_end_ms)
+            .collect();
+        let live_ms: u64 = window.iter().map(|sample| sample.interval_ms).sum();
+        let weighted = |value: fn(&WindowSample) -> f64| {
+            if live_ms == 0 {
+                0.0
+            } else {
+                window
+                    .iter()
+                    .map(|sample| value(sample) * sample.interval_ms as f64)
+                    .sum::<f64>()
+                    / live_ms as f64
+            }
+        };
+        let mean_rate = weighted(|sample| sample.rate);
+        let dose_rate = weighted(|sample| sample.dose_rate);
+        let peak_rate = window.iter().map(|sample| sample.rate).fold(0.0, f64::max);
+        let saturated = window.iter().filter(|sample| sample.saturated).count();
+
+        let mut records = Vec::new();
+        for (cause, count) in resets.iter() {
+            if *count == 0 {
+                continue;
+            }
+            if !window.is_empty() {
+                let bin = self.bin(dose_rate, saturated > 0);
+                self.table[bin].resets += u64::from(*count);
+            }
+            records.push(ResetRecord {
+                utc_ms,
+                window_end_ms,
+                cause: *cause,
+                count: *count,
+                samples: window.len(),
+                mean_rate,
+                peak_rate,
+                dose_rate,
+                saturated,
+                high_radiation: saturated > 0 || dose_rate > self.config.high_dose_rate,
+            });
+        }
+        self.records.extend(records.iter().cloned());
+        records
+    }
+
+    /// Resets recorded so far
+    pub fn records(&self) -> &[ResetRecord] {
+        &self.records
+    }
+
+    /// Removes and returns the recorded resets
+    pub fn take_records(&mut self) -> Vec<ResetRecord> {
+        std::mem::take(&mut self.records)
+    }
+
+    /// Reset likelihood against dose rate
+    pub fn table(&self) -> &[LikelihoodBin] {
+        &self.table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SampleQuality;
+    use crate::test_util::{calibrated, tube_rate};
+
+    // Flux given in particles / (cm^2 sr s), i.e. uGy/s with unit conversion
+    fn sample(second: u64, rate: f64, flux: f64) -> CalibratedSample {
+        let tube = tube_rate(rate as u16, rate, flux);
+        calibrated(second * 1000, 10_000, [tube.clone(), tube.clone(), tube])
+    }
+
+    #[test]
+    fn test_records_resets() {
+        let mut correlator = ResetCorrelator::new(ResetCorrelationConfig::default());
+        assert!(correlator.check(ResetCounters::default(), 0).is_empty());
+
+        // 0.1 uGy/s = 360 uGy/h, counters read with every sample
+        for second in (10..600).step_by(10) {
+            correlator.update(&sample(second, 20.0, 0.1));
+            assert!(correlator
+                .check(ResetCounters::default(), second * 1000)
+                .is_empty());
+        }
+        // Taken after the reset
+        correlator.update(&sample(600, 500.0, 10.0));
+        let counters = ResetCounters {
+            brown_out: 0,
+            automatic_software: 1,
+            manual: 3,
+            watchdog: 0,
+        };
+        let records = correlator.check(counters, 600_000);
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.cause, ResetCause::AutomaticSoftware);
+        assert_eq!(record.window_end_ms, 590_001);
+        assert_eq!(record.samples, 30);
+        assert_eq!(record.peak_rate, 20.0);
+        assert_eq!(record.mean_rate, 20.0);
+        assert!((record.dose_rate - 360.0).abs() < 1e-9);
+        assert!(record.high_radiation);
+
+        // No further resets
+        assert!(correlator.check(counters, 610_000).is_empty());
+        assert_eq!(correlator.records().len(), 1);
+    }
+
+    #[test]
+    fn test_counter_roll_over() {
+        let mut correlator = ResetCorrelator::new(ResetCorrelationConfig::default());
+        correlator.check(
+            ResetCounters {
+                watchdog: 254,
+                ..ResetCounters::default()
+            },
+            0,
+        );
+        correlator.update(&sample(10, 1.0, 0.0));
+        let records = correlator.check(
+            ResetCounters {
+                watchdog: 1,
+                ..ResetCounters::default()
+            },
+            10_000,
+        );
+        assert_eq!(records[0].cause, ResetCause::Watchdog);
+        assert_eq!(records[0].count, 3);
+        assert!(!records[0].high_radiation);
+    }
+
+    #[test]
+    fn test_likelihood_table() {
+        let mut correlator = ResetCorrelator::new(ResetCorrelationConfig::default());
+        correlator.check(ResetCounters::default(), 0);
+
+        // A day at low dose rate without resets, then an hour at high dose
+        // rate with two brown-outs
+        for second in (0..86_400).step_by(10) {
+            correlator.update(&sample(second, 1.0, 0.0));
+        }
+        for second in (86_400..90_000).step_by(10) {
+            correlator.update(&sample(second, 50.0, 1.0));
+        }
+        correlator.check(ResetCounters::default(), 89_990_000);
+        correlator.check(
+            ResetCounters {
+                brown_out: 2,
+                ..ResetCounters::default()
+            },
+            90_000_000,
+        );
+
+        let table = correlator.table();
+        assert_eq!(table.len(), 5);
+        assert_eq!(table[0].resets_per_day(), Some(0.0));
+        assert_eq!(table[2].resets_per_day(), None);
+        // 3600 uGy/h falls in the open last bin
+        assert_eq!(table[4].lower, 1000.0);
+        assert_eq!(table[4].resets, 2);
+        assert_eq!(table[4].resets_per_day(), Some(48.0));
+    }
+
+    #[test]
+    fn test_window_ends_at_suspect_sample() {
+        let mut correlator = ResetCorrelator::new(ResetCorrelationConfig::default());
+        correlator.check(ResetCounters::default(), 0);
+        for second in (10..=300).step_by(10) {
+            correlator.update(&sample(second, 20.0, 0.0));
+        }
+        // The board reset, restarting the radiation counters
+        let mut suspect = sample(310, 0.0, 0.0);
+        suspect.sample.quality.insert(SampleQuality::SUSPECT);
+        correlator.update(&suspect);
+        for second in (320..=400).step_by(10) {
+            correlator.update(&sample(second, 500.0, 0.0));
+        }
+
+        let records = correlator.check(
+            ResetCounters {
+                watchdog: 1,
+                ..ResetCounters::default()
+            },
+            400_000,
+        );
+        assert_eq!(records[0].window_end_ms, 310_000);
+        assert_eq!(records[0].samples, 30);
+        assert_eq!(records[0].peak_rate, 20.0);
+    }
+
+    #[test]
+    fn test_saturated_samples() {
+        let mut correlator = ResetCorrelator::new(ResetCorrelationConfig::default());
+        correlator.check(ResetCounters::default(), 0);
+        for second in (10..=290).step_by(10) {
+            correlator.update(&sample(second, 20.0, 0.0));
+        }
+        // Heavy radiation saturates the tubes, so the lower-bound dose rate
+        // is low, and the board resets
+        let mut saturated = sample(300, 900.0, 0.001);
+        saturated.tubes[1].reliable = false;
+        correlator.update(&saturated);
+        let mut suspect = sample(310, 0.0, 0.0);
+        suspect.sample.quality.insert(SampleQuality::SUSPECT);
+        correlator.update(&suspect);
+
+        let records = correlator.check(
+            ResetCounters {
+                brown_out: 1,
+                ..ResetCounters::default()
+            },
+            320_000,
+        );
+        assert_eq!(records[0].samples, 30);
+        assert_eq!(records[0].saturated, 1);
+        assert_eq!(records[0].peak_rate, 900.0);
+        assert!(records[0].dose_rate < 100.0);
+        assert!(records[0].high_radiation);
+
+        // Both the reset and the saturated exposure count above 100 uGy/h
+        let table = correlator.table();
+        assert_eq!(table[3].resets, 1);
+        assert_eq!(table[3].exposure_ms, 10_000);
+        assert_eq!(table[0].exposure_ms, 290_000);
+    }
+}
@@ -0,0 +1,417 @@
+//! South Atlantic Anomaly
+//!
+//! Tags samples with South Atlantic Anomaly (SAA) entry and exit and records
+//! a [`SaaPassage`] summary for every passage.
+//!
+//! When the spacecraft position is supplied with a sample and a boundary is
+//! configured, the passage follows the geographic boundary. Otherwise it is
+//! detected from the count-rate signature: the combined rate rising above a
+//! multiple of the background learned outside the anomaly.
+
+use crate::analysis::calibration::CalibratedSample;
+use crate::objects::GeoPosition;
+
+/// Geographic boundary of the anomaly
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaaBoundary {
+    /// Polygon vertices as (latitude, longitude) pairs (deg)
+    pub vertices: Vec<(f64, f64)>,
+    /// Altitude below which the boundary does not apply (km)
+    pub min_altitude: f64,
+}
+
+impl SaaBoundary {
+    /// Approximate extent of the anomaly at 400 to 600 km altitude
+    pub fn south_atlantic() -> Self {
+        SaaBoundary {
+            vertices: vec![
+                (-5.0, -90.0),
+                (0.0, -60.0),
+                (0.0, -20.0),
+                (-10.0, 20.0),
+                (-30.0, 40.0),
+                (-50.0, 30.0),
+                (-55.0, 0.0),
+                (-50.0, -50.0),
+                (-40.0, -80.0),
+            ],
+            min_altitude: 0.0,
+        }
+    }
+
+    /// Whether a position lies inside the boundary
+    ///
+    /// # Arguments
+    /// `position` - Spacecraft position
+    pub fn contains(&self, position: &GeoPosition) -> bool {
+        if position.altitude < self.min_altitude {
+            return false;
+        }
+        // Ray casting along the longitude axis
+        let (y, x) = (position.latitude, position.longitude);
+        let mut inside = false;
+        let mut previous = match self.vertices.last() {
+            Some(vertex) => *vertex,
+            None => return false,
+        };
+        for &(lat, lon) in &self.vertices {
+            let (prev_lat, prev_lon) = previous;
+            if (lat > y) != (prev_lat > y)
+                && x < (prev_lon - lon) * (y - lat) / (prev_lat - lat) + lon
+            {
+                inside = !inside;
+            }
+            previous = (lat, lon);
+        }
+        inside
+    }
+}
+
+/// How a passage was detected
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SaaDetection {
+    /// From the count-rate signature
+    Signature,
+    /// From the spacecraft position and the geographic boundary
+    Boundary,
+}
+
+/// Tag of a sample
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SaaTag {
+    /// Outside the anomaly
+    Outside,
+    /// First sample inside the anomaly
+    Entry,
+    /// Inside the anomaly
+    Inside,
+    /// First sample after leaving the anomaly
+    Exit,
+}
+
+/// Summary of a passage through the anomaly
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaaPassage {
+    /// UTC time of the entry sample (ms)
+    pub entry_utc_ms: u64,
+    /// UTC time of the exit sample (ms)
+    pub exit_utc_ms: u64,
+    /// UTC time of the highest rate (ms)
+    pub peak_utc_ms: u64,
+    /// Highest combined rate (cps)
+    pub peak_rate: f64,
+    /// Counts per tube registered inside the anomaly
+    pub integral_counts: [u64; 3],
+    /// How the entry was detected
+    pub detection: SaaDetection,
+}
+
+impl SaaPassage {
+    /// Duration of the passage (ms)
+    pub fn duration_ms(&self) -> u64 {
+        self.exit_utc_ms.saturating_sub(self.entry_utc_ms)
+    }
+}
+
+/// SAA detector configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaaConfig {
+    /// Geographic boundary used when a position is supplied
+    pub boundary: Option<SaaBoundary>,
+    /// Samples used to learn the background before signature detection starts
+    pub learning_samples: u32,
+    /// Weight of a new quiet sample in the background moving average
+    pub background_weight: f64,
+    /// Entry level as a multiple of the background rate
+    pub entry_factor: f64,
+    /// Exit level as a multiple of the background rate
+    pub exit_factor: f64,
+    /// Consecutive samples beyond a level needed to enter or leave
+    pub consecutive: u32,
+}
+
+impl Default for SaaConfig {
+    fn default() -> Self {
+        SaaConfig {
+            boundary: Some(SaaBoundary::south_atlantic()),
+            learning_samples: 30,
+            background_weight: 0.05,
+            entry_factor: 4.0,
+            exit_factor: 2.0,
+            consecutive: 2,
+        }
+    }
+}
+
+/// South Atlantic Anomaly passage detector
+pub struct SaaDetector {
+    config: SaaConfig,
+    background: Option<f64>,
+    learned: u32,
+    signature_inside: bool,
+    streak: u32,
+    passage: Option<SaaPassage>,
+    passages: Vec<SaaPassage>,
+}
+
+impl SaaDetector {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// `config` - Detector configuration
+    pub fn new(config: SaaConfig) -> Self {
+        SaaDetector {
+            config,
+            background: None,
+            learned: 0,
+            signature_inside: false,
+            streak: 0,
+            passage: None,
+            passages: Vec::new(),
+        }
+    }
+
+    /// Whether the spacecraft is inside the anomaly
+    pub fn in_saa(&self) -> bool {
+        self.passage.is_some()
+    }
+
+    /// Learned background rate outside the anomaly (cps)
+    pub fn background(&self) -> Option<f64> {
+        self.background
+            .filter(|_| self.learned >= self.config.learning_samples)
+    }
+
+    /// Completed passages, oldest first
+    pub fn passages(&self) -> &[SaaPassage] {
+        &self.passages
+    }
+
+    /// Removes and returns the completed passages
+    pub fn take_passages(&mut self) -> Vec<SaaPassage> {
+        std::mem::take(&mut self.passages)
+    }
+
+    /// Update
+    ///
+    /// Adds a calibrated sample and returns its tag. A suspect sample leaves
+    /// the detector unchanged and is tagged as inside or outside the current
+    /// passage. Unreliable samples are kept, as the dead-time correction is
+    /// expected to saturate in the anomaly.
+    ///
+    /// # Arguments
+    /// `sample` - Calibrated sample
+    /// `position` - Spacecraft position at the time of the sample, if known
+    pub fn update(&mut self, sample: &CalibratedSample, position: Option<&GeoPosition>) -> SaaTag {
+        if !sample.sample.quality.is_usable() {
+            return if self.in_saa() {
+                SaaTag::Inside
+            } else {
+                SaaTag::Outside
+            };
+        }
+        let rate = sample
+            .tubes
+            .iter()
+            .map(|tube| tube.corrected_rate.value)
+            .sum::<f64>()
+            / 3.0;
+        let signature = self.signature(rate);
+
+        let (inside, detection) = match (&self.config.boundary, position) {
+            (Some(boundary), Some(position)) => {
+                (boundary.contains(position), SaaDetection::Boundary)
+            }
+            _ => (signature, SaaDetection::Signature),
+        };
+
+        let utc_ms = sample.sample.utc_ms;
+        match (self.passage.take(), inside) {
+            (None, false) => SaaTag::Outside,
+            (None, true) => {
+                let mut passage = SaaPassage {
+                    entry_utc_ms: utc_ms,
+                    exit_utc_ms: utc_ms,
+                    peak_utc_ms: utc_ms,
+                    peak_rate: rate,
+                    integral_counts: [0; 3],
+                    detection,
+                };
+                Self::add(&mut passage, sample, rate);
+                self.passage = Some(passage);
+                SaaTag::Entry
+            }
+            (Some(mut passage), true) => {
+                Self::add(&mut passage, sample, rate);
+                self.passage = Some(passage);
+                SaaTag::Inside
+            }
+            (Some(mut passage), false) => {
+                passage.exit_utc_ms = utc_ms;
+                self.passages.push(passage);
+                SaaTag::Exit
+            }
+        }
+    }
+
+    fn add(passage: &mut SaaPassage, sample: &CalibratedSample, rate: f64) {
+        for (total, counts) in passage
+            .integral_counts
+            .iter_mut()
+            .zip(sample.sample.counts.iter())
+        {
+            *total += u64::from(*counts);
+        }
+        if rate > passage.peak_rate {
+            passage.peak_rate = rate;
+            passage.peak_utc_ms = sample.sample.utc_ms;
+        }
+    }
+
+    // Signature verdict, learning the background from quiet samples
+    fn signature(&mut self, rate: f64) -> bool {
+        let background = match self.background() {
+            Some(background) => background,
+            None => {
+                self.learned += 1;
+                let weight = 1.0 / f64::from(self.learned);
+                self.background = Some(match self.background {
+                    Some(background) => background + (rate - background) * weight,
+                    None => rate,
+                });
+                return false;
+            }
+        };
+
+        let beyond = if self.signature_inside {
+            rate < background * self.config.exit_factor
+        } else {
+            rate > background * self.config.entry_factor
+        };
+        self.streak = if beyond { self.streak + 1 } else { 0 };
+        if self.streak >= self.config.consecutive.max(1) {
+            self.signature_inside = !self.signature_inside;
+            self.streak = 0;
+        }
+
+        if !self.signature_inside && !beyond {
+            self.background =
+                Some(background + (rate - background) * self.config.background_weight);
+        }
+        self.signature_inside
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SampleQuality;
+    use crate::test_util::{calibrated, tube_rate};
+
+    fn sample(second: u64, rate: f64) -> CalibratedSample {
+        let tube = tube_rate(rate as u16, rate, rate);
+        calibrated(second * 1000, 1000, [tube.clone(), tube.clone(), tube])
+    }
+
+    fn position(latitude: f64, longitude: f64) -> GeoPosition {
+        GeoPosition {
+            latitude,
+            longitude,
+            altitude: 500.0,
+        }
+    }
+
+    #[test]
+    fn test_boundary() {
+        let boundary = SaaBoundary::south_atlantic();
+        assert!(boundary.contains(&position(-25.0, -45.0)));
+        assert!(!boundary.contains(&position(45.0, -45.0)));
+        assert!(!boundary.contains(&position(-25.0, 120.0)));
+    }
+
+    #[test]
+    fn test_signature_passage() {
+        let mut detector = SaaDetector::new(SaaConfig {
+            boundary: None,
+            learning_samples: 5,
+            ..SaaConfig::default()
+        });
+        let rates = [
+            10.0, 10.0, 10.0, 10.0, 10.0, 10.0, 50.0, 80.0, 120.0, 90.0, 15.0, 12.0, 10.0,
+        ];
+        let tags: Vec<SaaTag> = rates
+            .iter()
+            .enumerate()
+            .map(|(second, rate)| detector.update(&sample(second as u64, *rate), None))
+            .collect();
+
+        assert_eq!(tags[7], SaaTag::Entry);
+        assert_eq!(tags[8], SaaTag::Inside);
+        assert_eq!(tags[11], SaaTag::Exit);
+        assert_eq!(tags[12], SaaTag::Outside);
+
+        let passage = &detector.passages()[0];
+        assert_eq!(passage.detection, SaaDetection::Signature);
+        assert_eq!(passage.duration_ms(), 4000);
+        assert_eq!(passage.peak_rate, 120.0);
+        assert_eq!(passage.peak_utc_ms, 8000);
+        assert_eq!(passage.integral_counts, [80 + 120 + 90 + 15; 3]);
+    }
+
+    #[test]
+    fn test_suspect_samples() {
+        let mut detector = SaaDetector::new(SaaConfig {
+            boundary: None,
+            learning_samples: 5,
+            ..SaaConfig::default()
+        });
+        let mut suspect = sample(0, 0.0);
+        suspect.sample.quality.insert(SampleQuality::SUSPECT);
+        for second in 0..6 {
+            detector.update(&sample(second, 10.0), None);
+        }
+        assert_eq!(detector.update(&suspect, None), SaaTag::Outside);
+        assert_eq!(detector.background(), Some(10.0));
+
+        for second in 6..8 {
+            detector.update(&sample(second, 100.0), None);
+        }
+        assert!(detector.in_saa());
+        for _ in 0..3 {
+            assert_eq!(detector.update(&suspect, None), SaaTag::Inside);
+        }
+        let mut saturated = sample(8, 200.0);
+        saturated.tubes[0].reliable = false;
+        assert_eq!(detector.update(&saturated, None), SaaTag::Inside);
+        assert_eq!(detector.update(&sample(9, 10.0), None), SaaTag::Inside);
+        assert_eq!(detector.update(&sample(10, 10.0), None), SaaTag::Exit);
+
+        let passage = &detector.passages()[0];
+        assert_eq!(passage.peak_rate, 200.0);
+        assert_eq!(passage.integral_counts, [100 + 200 + 10; 3]);
+    }
+
+    #[test]
+    fn test_boundary_passage() {
+        let mut detector = SaaDetector::new(SaaConfig::default());
+        let track = [
+            (-20.0, -100.0),
+            (-20.0, -60.0),
+            (-20.0, -20.0),
+            (-20.0, 60.0),
+        ];
+        let tags: Vec<SaaTag> = track
+            .iter()
+            .enumerate()
+            .map(|(second, (lat, lon))| {
+                detector.update(&sample(second as u64, 10.0), Some(&position(*lat, *lon)))
+            })
+            .collect();
+        assert_eq!(
+            tags,
+            vec![SaaTag::Outside, SaaTag::Entry, SaaTag::Inside, SaaTag::Exit]
+        );
+        assert_eq!(detector.passages()[0].detection, SaaDetection::Boundary);
+        assert_eq!(detector.passages()[0].integral_counts, [20; 3]);
+    }
+}
@@ -0,0 +1,460 @@
+//! Solar Particle Events
+//!
+//! Detects solar particle events (SPEs) as a sustained rise of the combined
+//! count rate over a rolling background. The background is the mean rate of
+//! the recent quiet samples and is frozen while an event is in progress.
+//!
+//! An event starts once the rate stays above the onset level for a number of
+//! consecutive samples, and ends once it stays below the lower end level for
+//! a number of samples. Both levels are the larger of a multiple of the
+//! background and a number of standard deviations of its Poisson noise.
+//!
+//! Suspect samples are skipped. A sample with an unreliable dead-time
+//! correction, e.g. from saturating tubes, only gives a lower bound of the
+//! rate. It is taken as above the onset level, adds its lower bound to the
+//! peak and the fluence, and never enters the background or ends an event.
+//!
+//! [`SpeSampler`] ties the detector to a [`CountSampler`], switching it to a
+//! fast polling period for the duration of an event.
+
+use crate::analysis::calibration::{CalibratedSample, CalibrationProfile};
+use crate::radiation_counter::CuavaRadiationCounter;
+use crate::sample::CountSampler;
+use crate::CounterResult;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Solar particle event detector configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpeConfig {
+    /// Number of quiet samples in the rolling background
+    pub background_window: usize,
+    /// Onset level as a multiple of the background rate
+    pub onset_factor: f64,
+    /// Onset level in standard deviations above the background rate
+    pub onset_sigma: f64,
+    /// Consecutive samples above the onset level which start an event
+    pub onset_samples: u32,
+    /// End level as a multiple of the background rate
+    pub end_factor: f64,
+    /// End level in standard deviations above the background rate
+    pub end_sigma: f64,
+    /// Consecutive samples below the end level which end an event
+    pub end_samples: u32,
+    /// Polling period outside events
+    pub nominal_period: Duration,
+    /// Polling period during events
+    pub fast_period: Duration,
+}
+
+impl Default for SpeConfig {
+    fn default() -> Self {
+        SpeConfig {
+            background_window: 360,
+            onset_factor: 3.0,
+            onset_sigma: 5.0,
+            onset_samples: 3,
+            end_factor: 1.5,
+            end_sigma: 3.0,
+            end_samples: 10,
+            nominal_period: Duration::from_secs(10),
+            fast_period: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Logged solar particle event
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpeEvent {
+    /// UTC time of the first sample above the onset level (ms)
+    pub start_utc_ms: u64,
+    /// UTC time of the highest rate (ms)
+    pub peak_utc_ms: u64,
+    /// Highest combined rate (cps)
+    pub peak_rate: f64,
+    /// UTC time of the first sample below the end level, `None` while the
+    /// event is in progress (ms)
+    pub end_utc_ms: Option<u64>,
+    /// Combined background rate before the event (cps)
+    pub background_rate: f64,
+    /// Background subtracted fluence, mean of the three tubes (1 / (cm^2 sr))
+    pub fluence: f64,
+}
+
+/// Change of the detector state
+#[derive(Clone, Debug, PartialEq)]
+pub enum SpeTransition {
+    /// An event started
+    Onset(SpeEvent),
+    /// The event ended
+    End(SpeEvent),
+}
+
+/// Solar particle event detector
+pub struct SpeDetector {
+    config: SpeConfig,
+    background: VecDeque<(f64, f64)>,
+    streak: u32,
+    current: Option<SpeEvent>,
+    active: bool,
+    events: Vec<SpeEvent>,
+}
+
+impl SpeDetector {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// `config` - Detector configuration
+    pub fn new(config: SpeConfig) -> Self {
+        SpeDetector {
+            background: VecDeque::with_capacity(config.background_window),
+            config,
+            streak: 0,
+            current: None,
+            active: false,
+            events: Vec::new(),
+        }
+    }
+
+    /// Whether an event is in progress
+    pub fn in_event(&self) -> bool {
+        self.active
+    }
+
+    /// Event in progress
+    pub fn current(&self) -> Option<&SpeEvent> {
+        self.current.as_ref().filter(|_| self.active)
+    }
+
+    /// Log of completed events, oldest first
+    pub fn events(&self) -> &[SpeEvent] {
+        &self.events
+    }
+
+    /// Removes and returns the completed events
+    pub fn take_events(&mut self) -> Vec<SpeEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Polling period for the current state
+    pub fn cadence(&self) -> Duration {
+        if self.active {
+            self.config.fast_period
+        } else {
+            self.config.nominal_period
+        }
+    }
+
+    /// Rolling background rate and flux, `None` until the window is full
+    pub fn background(&self) -> Option<(f64, f64)> {
+        if self.background.len() < self.config.background_window.max(1) {
+            return None;
+        }
+        let count = self.background.len() as f64;
+        let (rate, flux) = self
+            .background
+            .iter()
+            .fold((0.0, 0.0), |(rate, flux), (r, f)| (rate + r, flux + f));
+        Some((rate / count, flux / count))
+    }
+
+    // Larger of a multiple of the background and its Poisson noise level
+    fn level(background: f64, interval: f64, factor: f64, sigma: f64) -> f64 {
+        // Noise of the mean rate of three tubes
+        let noise = (background.max(1.0 / interval) / (3.0 * interval)).sqrt();
+        (background * factor).max(background + sigma * noise)
+    }
+
+    /// Update
+    ///
+    /// Adds a calibrated sample and returns the event onset or end it caused.
+    ///
+    /// # Arguments
+    /// `sample` - Calibrated sample
+    pub fn update(&mut self, sample: &CalibratedSample) -> Option<SpeTransition> {
+        let interval = sample.sample.interval_secs();
+        if interval <= 0.0 || !sample.sample.quality.is_usable() {
+            return None;
+        }
+        let saturated = !sample.is_reliable();
+        let utc_ms = sample.sample.utc_ms;
+        let rate = sample
+            .tubes
+            .iter()
+            .map(|tube| tube.corrected_rate.value)
+            .sum::<f64>()
+            / 3.0;
+        let flux = sample.tubes.iter().map(|tube| tube.flux.value).sum::<f64>() / 3.0;
+
+        let (background_rate, background_flux) = match self.background() {
+            Some(background) => background,
+            None => {
+                if !saturated {
+                    self.push_background(rate, flux);
+                }
+                return None;
+            }
+        };
+
+        if self.active {
+            let event = self.current.as_mut()?;
+            event.fluence += (flux - background_flux).max(0.0) * interval;
+            if rate > event.peak_rate {
+                event.peak_rate = rate;
+                event.peak_utc_ms = utc_ms;
+            }
+
+            let end = Self::level(
+                background_rate,
+                interval,
+                self.config.end_factor,
+                self.config.end_sigma,
+            );
+            if saturated || rate >= end {
+                self.streak = 0;
+                event.end_utc_ms = None;
+                return None;
+            }
+            if self.streak == 0 {
+                event.end_utc_ms = Some(utc_ms);
+            }
+            self.streak += 1;
+            if self.streak < self.config.end_samples.max(1) {
+                return None;
+            }
+
+            self.active = false;
+            self.streak = 0;
+            let event = self.current.take()?;
+            self.events.push(event.clone());
+            return Some(SpeTransition::End(event));
+        }
+
+        let onset = Self::level(
+            background_rate,
+            interval,
+            self.config.onset_factor,
+            self.config.onset_sigma,
+        );
+        if !saturated && rate <= onset {
+            self.streak = 0;
+            self.current = None;
+            self.push_background(rate, flux);
+            return None;
+        }
+
+        let event = self.current.get_or_insert(SpeEvent {
+            start_utc_ms: utc_ms,
+            peak_utc_ms: utc_ms,
+            peak_rate: rate,
+            end_utc_ms: None,
+            background_rate,
+            fluence: 0.0,
+        });
+        event.fluence += (flux - background_flux).max(0.0) * interval;
+        if rate > event.peak_rate {
+            event.peak_rate = rate;
+            event.peak_utc_ms = utc_ms;
+        }
+        self.streak += 1;
+        if self.streak < self.config.onset_samples.max(1) {
+            return None;
+        }
+
+        self.active = true;
+        self.streak = 0;
+        Some(SpeTransition::Onset(event.clone()))
+    }
+
+    fn push_background(&mut self, rate: f64, flux: f64) {
+        if self.background.len() == self.config.background_window.max(1) {
+            self.background.pop_front();
+        }
+        self.background.push_back((rate, flux));
+    }
+}
+
+/// Count sampler which polls faster during solar particle events
+pub struct SpeSampler {
+    sampler: CountSampler,
+    profile: CalibrationProfile,
+    detector: SpeDetector,
+}
+
+impl SpeSampler {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// `profile` - Calibration of the tubes
+    /// `config` - Detector configuration
+    pub fn new(profile: CalibrationProfile, config: SpeConfig) -> Self {
+        let mut sampler = CountSampler::new();
+        sampler.set_period(config.nominal_period);
+        SpeSampler {
+            sampler,
+            profile,
+            detector: SpeDetector::new(config),
+        }
+    }
+
+    /// Underlying count sampler
+    pub fn sampler(&self) -> &CountSampler {
+        &self.sampler
+    }
+
+    /// Event detector
+    pub fn detector(&self) -> &SpeDetector {
+        &self.detector
+    }
+
+    /// Mutable event detector, e.g. to take the event log
+    pub fn detector_mut(&mut self) -> &mut SpeDetector {
+        &mut self.detector
+    }
+
+    /// Time left until the next read is due
+    pub fn next_read_in(&self) -> Duration {
+        self.sampler.next_read_in()
+    }
+
+    /// Read Sample
+    ///
+    /// Reads and calibrates a sample, runs the event detector and adjusts the
+    /// polling period. Returns the calibrated sample, `None` for a sample
+    /// without an integration interval, and the transition it caused.
+    ///
+    /// # Arguments
+    /// `counter` - Radiation counter to read
+    pub fn read<C: CuavaRadiationCounter>(
+        &mut self,
+        counter: &mut C,
+    ) -> CounterResult<(Option<CalibratedSample>, Option<SpeTransition>)> {
+        let sample = self.sampler.read(counter)?;
+        let calibrated = self.profile.calibrate(&sample);
+        let transition = calibrated
+            .as_ref()
+            .and_then(|calibrated| self.detector.update(calibrated));
+        self.sampler.set_period(self.detector.cadence());
+        Ok((calibrated, transition))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SampleQuality;
+    use crate::test_util::{calibrated, tube_rate};
+
+    fn sample(second: u64, rate: f64) -> CalibratedSample {
+        let tube = tube_rate(0, rate, rate / 2.0);
+        calibrated(second * 1000, 1000, [tube.clone(), tube.clone(), tube])
+    }
+
+    fn detector() -> SpeDetector {
+        SpeDetector::new(SpeConfig {
+            background_window: 10,
+            onset_samples: 3,
+            end_samples: 2,
+            ..SpeConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_event() {
+        let mut detector = detector();
+        let rates = [
+            10.0, 12.0, 8.0, 10.0, 11.0, 9.0, 10.0, 10.0, 12.0, 8.0, // background
+            100.0, 300.0, 200.0, 100.0, 40.0, 12.0, 10.0, 10.0,
+        ];
+        let mut transitions = Vec::new();
+        for (second, rate) in rates.iter().enumerate() {
+            if let Some(transition) = detector.update(&sample(second as u64, *rate)) {
+                transitions.push((second, transition));
+            }
+        }
+
+        assert_eq!(transitions.len(), 2);
+        match &transitions[0] {
+            (12, SpeTransition::Onset(event)) => assert_eq!(event.start_utc_ms, 10_000),
+            other => panic!("unexpected {:?}", other),
+        }
+        let event = &detector.events()[0];
+        assert_eq!(transitions[1], (16, SpeTransition::End(event.clone())));
+        assert_eq!(event.peak_rate, 300.0);
+        assert_eq!(event.peak_utc_ms, 11_000);
+        assert_eq!(event.end_utc_ms, Some(15_000));
+        assert_eq!(event.background_rate, 10.0);
+        // Flux is half the rate, minus a background flux of 5
+        assert_eq!(event.fluence, 45.0 + 145.0 + 95.0 + 45.0 + 15.0 + 1.0);
+        assert!(!detector.in_event());
+    }
+
+    #[test]
+    fn test_short_spike_is_ignored() {
+        let mut detector = detector();
+        for second in 0..10 {
+            detector.update(&sample(second, 10.0));
+        }
+        assert_eq!(detector.update(&sample(10, 500.0)), None);
+        assert_eq!(detector.update(&sample(11, 500.0)), None);
+        assert_eq!(detector.update(&sample(12, 10.0)), None);
+        assert!(detector.events().is_empty());
+        assert!(detector.current().is_none());
+    }
+
+    #[test]
+    fn test_cadence() {
+        let mut detector = detector();
+        for second in 0..10 {
+            detector.update(&sample(second, 10.0));
+        }
+        assert_eq!(detector.cadence(), Duration::from_secs(10));
+        for second in 10..13 {
+            detector.update(&sample(second, 500.0));
+        }
+        assert!(detector.in_event());
+        assert_eq!(detector.cadence(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_suspect_and_saturated_samples() {
+        let mut detector = detector();
+        for second in 0..10 {
+            detector.update(&sample(second, 10.0));
+        }
+        let mut suspect = sample(10, 500.0);
+        suspect.sample.quality.insert(SampleQuality::SUSPECT);
+        for _ in 0..3 {
+            assert_eq!(detector.update(&suspect), None);
+        }
+        assert!(detector.current().is_none());
+
+        // Saturated samples start an event even with a low lower bound
+        let saturated = |second: u64, rate: f64| {
+            let mut sample = sample(second, rate);
+            sample.tubes[2].reliable = false;
+            sample
+        };
+        assert_eq!(detector.update(&saturated(10, 400.0)), None);
+        assert_eq!(detector.update(&saturated(11, 20.0)), None);
+        match detector.update(&saturated(12, 800.0)) {
+            Some(SpeTransition::Onset(event)) => assert_eq!(event.start_utc_ms, 10_000),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(detector.background(), Some((10.0, 5.0)));
+
+        // Nor do saturated or suspect samples end it
+        for second in 13..20 {
+            assert_eq!(detector.update(&saturated(second, 10.0)), None);
+            assert_eq!(detector.update(&suspect), None);
+        }
+        assert!(detector.in_event());
+        detector.update(&sample(20, 10.0));
+        assert!(detector.update(&sample(21, 10.0)).is_some());
+
+        let event = &detector.events()[0];
+        assert_eq!(event.peak_rate, 800.0);
+        assert_eq!(event.peak_utc_ms, 12_000);
+        assert_eq!(event.fluence, 195.0 + 5.0 + 395.0);
+    }
+}
@@ -0,0 +1,365 @@
+//! Windowed Statistics
+//!
+//! Summary statistics of the counts of each tube over a sliding window of
+//! samples, together with a chi-square dispersion test of whether the counts
+//! are consistent with Poisson statistics.
+//!
+//! Counts from a steady radiation environment are Poisson distributed, so
+//! their variance equals their mean (Fano factor of 1). Electronic noise
+//! usually shows up as excess variance, while a stuck or periodically
+//! triggered channel shows too little. The test assumes every sample in the
+//! window covers the same integration interval.
+
+use crate::objects::CountSample;
+use std::collections::VecDeque;
+
+/// Result of the Poisson dispersion test
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoissonTest {
+    /// Chi-square statistic, sum of (count - mean)^2 / mean
+    pub chi_square: f64,
+    /// Degrees of freedom, one less than the number of samples
+    pub degrees_of_freedom: usize,
+    /// Two-sided p-value of the statistic under the Poisson hypothesis
+    pub p_value: f64,
+}
+
+impl PoissonTest {
+    /// Whether the counts are consistent with Poisson statistics
+    ///
+    /// # Arguments
+    /// `significance` - Significance level of the test, e.g. 0.01
+    pub fn is_consistent(&self, significance: f64) -> bool {
+        self.p_value >= significance
+    }
+}
+
+/// Statistics of one tube over the window
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelStats {
+    /// Number of samples in the window
+    pub samples: usize,
+    /// Mean counts per sample
+    pub mean: f64,
+    /// Sample variance of the counts
+    pub variance: f64,
+    /// Fewest counts in a sample
+    pub min: u16,
+    /// Most counts in a sample
+    pub max: u16,
+    /// Variance to mean ratio, `None` when the mean is zero
+    pub fano: Option<f64>,
+    /// Poisson dispersion test, `None` when the mean is zero
+    pub poisson: Option<PoissonTest>,
+}
+
+/// Sliding window of the counts of the three tubes
+pub struct StatsWindow {
+    capacity: usize,
+    counts: VecDeque<[u16; 3]>,
+}
+
+impl StatsWindow {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// `capacity` - Number of samples kept in the window
+    pub fn new(capacity: usize) -> Self {
+        StatsWindow {
+            capacity: capacity.max(1),
+            counts: VecDeque::with_capacity(capacity.max(1)),
+        }
+    }
+
+    /// Add Sample
+    ///
+    /// Adds a sample to the window, dropping the oldest one once the window
+    /// is full. Suspect samples and samples without an integration interval
+    /// are ignored.
+    ///
+    /// # Arguments
+    /// `sample` - Sample to add
+    pub fn push(&mut self, sample: &CountSample) {
+        if sample.interval_ms == 0 || !sample.quality.is_usable() {
+            return;
+        }
+        if self.counts.len() == self.capacity {
+            self.counts.pop_front();
+        }
+        self.counts.push_back(sample.counts);
+    }
+
+    /// Number of samples in the window
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Whether the window holds no samples
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Whether the window holds `capacity` samples
+    pub fn is_full(&self) -> bool {
+        self.counts.len() == self.capacity
+    }
+
+    /// Removes every sample from the window
+    pub fn clear(&mut self) {
+        self.counts.clear();
+    }
+
+    // Counts of a tube, `None` for a tube which does not exist
+    fn channel(&self, tube: usize) -> Option<Vec<u16>> {
+        self.counts
+            .iter()
+            .map(|counts| counts.get(tube).copied())
+            .collect()
+    }
+
+    /// Statistics of a tube
+    ///
+    /// Returns `None` while the window holds fewer than two samples, or for a
+    /// tube which does not exist.
+    ///
+    /// # Arguments
+    /// `tube` - Tube index, 0 to 2
+    pub fn stats(&self, tube: usize) -> Option<ChannelStats> {
+        let channel = self.channel(tube)?;
+        let samples = channel.len();
+        if samples < 2 {
+            return None;
+        }
+
+        let mean = channel.iter().copied().map(f64::from).sum::<f64>() / samples as f64;
+        let sum_squares: f64 = channel
+            .iter()
+            .map(|count| (f64::from(*count) - mean).powi(2))
+            .sum();
+        let variance = sum_squares / (samples - 1) as f64;
+
+        let (fano, poisson) = if mean > 0.0 {
+            let chi_square = sum_squares / mean;
+            let degrees_of_freedom = samples - 1;
+            let lower = gamma_p(degrees_of_freedom as f64 / 2.0, chi_square / 2.0);
+            (
+                Some(variance / mean),
+                Some(PoissonTest {
+                    chi_square,
+                    degrees_of_freedom,
+                    p_value: (2.0 * lower.min(1.0 - lower)).min(1.0),
+                }),
+            )
+        } else {
+            (None, None)
+        };
+
+        Some(ChannelStats {
+            samples,
+            mean,
+            variance,
+            min: *channel.iter().min()?,
+            max: *channel.iter().max()?,
+            fano,
+            poisson,
+        })
+    }
+
+    /// Percentile of the counts of a tube
+    ///
+    /// Interpolates linearly between the closest ranks. Returns `None` for an
+    /// empty window, or for a tube which does not exist.
+    ///
+    /// # Arguments
+    /// `tube` - Tube index, 0 to 2
+    /// `percentile` - Percentile, between 0 and 100
+    pub fn percentile(&self, tube: usize, percentile: f64) -> Option<f64> {
+        let mut sorted = self.channel(tube)?;
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_unstable();
+
+        let rank = percentile.clamp(0.0, 100.0) / 100.0 * (sorted.len() - 1) as f64;
+        let below = rank.floor() as usize;
+        let above = rank.ceil() as usize;
+        let fraction = rank - below as f64;
+        Some(f64::from(sorted[below]) * (1.0 - fraction) + f64::from(sorted[above]) * fraction)
+    }
+}
+
+// Natural logarithm of the gamma function (Lanczos approximation)
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.120_865_097_386_617_9e-2,
+        -0.539_523_938_495_3e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut series = 1.000_000_000_190_015;
+    for (i, coefficient) in COEFFICIENTS.iter().enumerate() {
+        series += coefficient / (x + 1.0 + i as f64);
+    }
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+// Regularized lower incomplete gamma function P(a, x)
+fn gamma_p(a: f64, x: f64) -> f64 {
+    const ITERATIONS: usize = 500;
+    const EPSILON: f64 = 1e-14;
+
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let prefactor = (-x + a * x.ln() - ln_gamma(a)).exp();
+
+    if x < a + 1.0 {
+        // Series expansion
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..ITERATIONS {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+        (sum * prefactor).min(1.0)
+    } else {
+        // Continued fraction for Q(a, x), evaluated with Lentz's method
+        let tiny = f64::MIN_POSITIVE / EPSILON;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for n in 1..ITERATIONS {
+            let an = -(n as f64) * (n as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < EPSILON {
+                break;
+            }
+        }
+        (1.0 - prefactor * h).max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SampleQuality;
+    use crate::test_util::count_sample;
+
+    fn sample(counts: [u16; 3]) -> CountSample {
+        count_sample(1, 0, 0, 1000, counts)
+    }
+
+    fn window(counts: &[[u16; 3]]) -> StatsWindow {
+        let mut window = StatsWindow::new(counts.len());
+        for counts in counts {
+            window.push(&sample(*counts));
+        }
+        window
+    }
+
+    #[test]
+    fn test_gamma_p() {
+        // Chi-square CDF with 2 degrees of freedom is 1 - exp(-x / 2)
+        assert!((gamma_p(1.0, 1.5) - (1.0 - (-1.5f64).exp())).abs() < 1e-12);
+        assert!((gamma_p(1.0, 0.5) - (1.0 - (-0.5f64).exp())).abs() < 1e-12);
+        // Chi-square 95th percentile with 10 degrees of freedom is 18.307
+        assert!((gamma_p(5.0, 18.307 / 2.0) - 0.95).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_stats() {
+        let window = window(&[[2, 10, 0], [4, 10, 0], [6, 10, 0], [8, 10, 0]]);
+
+        let stats = window.stats(0).unwrap();
+        assert_eq!(stats.mean, 5.0);
+        assert!((stats.variance - 20.0 / 3.0).abs() < 1e-12);
+        assert_eq!((stats.min, stats.max), (2, 8));
+        assert!((stats.fano.unwrap() - 4.0 / 3.0).abs() < 1e-12);
+        assert_eq!(stats.poisson.unwrap().chi_square, 4.0);
+        assert_eq!(stats.poisson.unwrap().degrees_of_freedom, 3);
+
+        let constant = window.stats(1).unwrap();
+        assert_eq!(constant.fano, Some(0.0));
+        assert!(!constant.poisson.unwrap().is_consistent(0.01));
+
+        assert_eq!(window.stats(2).unwrap().poisson, None);
+        assert_eq!(window.percentile(0, 50.0), Some(5.0));
+        assert_eq!(window.percentile(0, 100.0), Some(8.0));
+
+        assert_eq!(window.stats(3), None);
+        assert_eq!(window.percentile(3, 50.0), None);
+    }
+
+    #[test]
+    fn test_skips_suspect_samples() {
+        let mut window = window(&[[2, 10, 0], [4, 10, 0]]);
+        let mut suspect = sample([0, 0, 0]);
+        suspect.quality.insert(SampleQuality::SUSPECT);
+        window.push(&suspect);
+        let mut wrapped = sample([6, 10, 0]);
+        wrapped.quality.insert(SampleQuality::WRAPPED);
+        window.push(&wrapped);
+
+        assert_eq!(window.len(), 2);
+        assert_eq!(window.stats(0).unwrap().min, 4);
+    }
+
+    #[test]
+    fn test_poisson_dispersion() {
+        // Poisson-like counts around 100, and noisy counts with the same mean
+        let poisson = [
+            97, 104, 92, 110, 101, 95, 99, 108, 88, 103, 100, 96, 112, 94, 102, 105,
+        ];
+        let noisy = [
+            40, 160, 55, 150, 20, 180, 100, 60, 140, 90, 170, 30, 110, 45, 155, 95,
+        ];
+        let counts: Vec<[u16; 3]> = poisson
+            .iter()
+            .zip(noisy.iter())
+            .map(|(poisson, noisy)| [*poisson, *noisy, 0])
+            .collect();
+        let window = window(&counts);
+
+        assert!(window
+            .stats(0)
+            .unwrap()
+            .poisson
+            .unwrap()
+            .is_consistent(0.01));
+        assert!(!window
+            .stats(1)
+            .unwrap()
+            .poisson
+            .unwrap()
+            .is_consistent(0.01));
+    }
+
+    #[test]
+    fn test_window_slides() {
+        let mut window = StatsWindow::new(2);
+        window.push(&sample([1, 0, 0]));
+        window.push(&sample([2, 0, 0]));
+        window.push(&sample([3, 0, 0]));
+        assert!(window.is_full());
+        assert_eq!(window.stats(0).unwrap().mean, 2.5);
+    }
+}
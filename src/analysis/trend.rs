@@ -0,0 +1,582 @@
+//! Long-Term Trend
+//!
+//! Tracks the slow degradation of the tubes over the mission. Samples are
+//! summarised per UTC day, and the quiet-time baseline rate of each tube and
+//! its ratio to the mean of the three tubes are compared against a reference
+//! taken from the first days of operation. The current level is a linear
+//! least-squares fit over the most recent days, so a single unusual day does
+//! not raise a warning by itself.
+//!
+//! The tracker state is persisted with atomic file replacement, and the daily
+//! summaries can be exported for the ground to follow the end-of-life trend.
+
+use crate::analysis::calibration::{CalibratedSample, Measurement};
+use crate::storage::write_atomic;
+use crate::{CounterError, CounterResult};
+use serde::*;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+const MS_PER_DAY: u64 = 86_400_000;
+
+/// Trend tracker configuration
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrendConfig {
+    /// Number of daily summaries kept
+    pub max_days: usize,
+    /// Days with quiet data averaged into the reference
+    pub reference_days: usize,
+    /// Most recent days used for the drift fit
+    pub fit_days: usize,
+    /// Fractional baseline change that raises a warning
+    pub baseline_limit: f64,
+    /// Fractional change of the tube ratio that raises a warning
+    pub ratio_limit: f64,
+}
+
+impl Default for TrendConfig {
+    fn default() -> Self {
+        TrendConfig {
+            max_days: 1000,
+            reference_days: 7,
+            fit_days: 30,
+            baseline_limit: 0.25,
+            ratio_limit: 0.1,
+        }
+    }
+}
+
+/// Summary statistics of one UTC day
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DailySummary {
+    /// Day index since the Unix epoch
+    pub day: u64,
+    /// Number of samples
+    pub samples: u64,
+    /// Total integration time (ms)
+    pub live_ms: u64,
+    /// Counts per tube
+    pub counts: [u64; 3],
+    /// Integration time of the quiet samples (ms)
+    pub quiet_live_ms: u64,
+    /// Counts per tube during quiet samples
+    pub quiet_counts: [u64; 3],
+    /// Highest single-sample rate per tube (cps)
+    pub max_rate: [f64; 3],
+}
+
+impl DailySummary {
+    /// Mean count rate of a tube over the day (cps)
+    ///
+    /// # Arguments
+    /// `tube` - Tube index, 0 to 2
+    pub fn rate(&self, tube: usize) -> Option<Measurement> {
+        rate(*self.counts.get(tube)?, self.live_ms)
+    }
+
+    /// Quiet-time baseline rate of a tube (cps)
+    ///
+    /// # Arguments
+    /// `tube` - Tube index, 0 to 2
+    pub fn baseline(&self, tube: usize) -> Option<Measurement> {
+        rate(*self.quiet_counts.get(tube)?, self.quiet_live_ms)
+    }
+
+    /// Ratio of a tube's baseline to the mean baseline of the three tubes
+    ///
+    /// # Arguments
+    /// `tube` - Tube index, 0 to 2
+    pub fn ratio(&self, tube: usize) -> Option<f64> {
+        let counts = *self.quiet_counts.get(tube)?;
+        let total: u64 = self.quiet_counts.iter().sum();
+        if self.quiet_live_ms == 0 || total == 0 {
+            return None;
+        }
+        Some(3.0 * counts as f64 / total as f64)
+    }
+
+    /// Decodes daily summaries exported with [`TrendTracker::export`]
+    ///
+    /// # Arguments
+    /// `data` - Exported summaries
+    pub fn decode(data: &[u8]) -> CounterResult<Vec<DailySummary>> {
+        bincode::deserialize(data).map_err(|_| CounterError::parsing_failure("Trend Export"))
+    }
+}
+
+fn rate(counts: u64, live_ms: u64) -> Option<Measurement> {
+    if live_ms == 0 {
+        return None;
+    }
+    let live = live_ms as f64 / 1000.0;
+    let counts = counts as f64;
+    Some(Measurement::new(counts / live, counts.sqrt() / live))
+}
+
+/// Linear drift of a quantity over the fit window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DriftFit {
+    /// Fitted value at the most recent day
+    pub level: f64,
+    /// Change per day
+    pub slope: f64,
+    /// Number of days in the fit
+    pub days: usize,
+}
+
+impl DriftFit {
+    /// Change per day relative to the current level
+    pub fn relative_slope(&self) -> f64 {
+        if self.level == 0.0 {
+            0.0
+        } else {
+            self.slope / self.level
+        }
+    }
+}
+
+/// Quantity that moved beyond its limit
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TrendWarningKind {
+    /// Quiet-time baseline rate
+    Baseline,
+    /// Ratio to the mean of the three tubes
+    Ratio,
+}
+
+/// Warning about a tube drifting away from its reference
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrendWarning {
+    /// Tube index
+    pub tube: usize,
+    /// Quantity that drifted
+    pub kind: TrendWarningKind,
+    /// Reference value
+    pub reference: f64,
+    /// Current fitted value
+    pub current: f64,
+    /// Fractional change from the reference
+    pub change: f64,
+}
+
+/// Reference taken from the first days of operation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrendReference {
+    /// Quiet-time baseline rate per tube (cps)
+    pub baseline: [f64; 3],
+    /// Ratio of each tube to the mean of the three
+    pub ratio: [f64; 3],
+}
+
+/// Trend tracker state, as stored in the state file
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrendState {
+    /// Day currently being accumulated
+    pub current: Option<DailySummary>,
+    /// Completed days, oldest first
+    pub summaries: VecDeque<DailySummary>,
+    /// Reference, once enough quiet days were summarised
+    pub reference: Option<TrendReference>,
+}
+
+/// Tracks daily statistics and long-term drift of the tubes
+pub struct TrendTracker {
+    config: TrendConfig,
+    state: TrendState,
+}
+
+impl TrendTracker {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// `config` - Tracker configuration
+    /// `state` - Previously persisted state to resume from, if any
+    pub fn new(config: TrendConfig, state: Option<TrendState>) -> Self {
+        TrendTracker {
+            config,
+            state: state.unwrap_or_default(),
+        }
+    }
+
+    /// Loads a state saved with [`TrendTracker::save`]
+    ///
+    /// # Arguments
+    /// `path` - Path of the state file
+    pub fn load<P: AsRef<Path>>(path: P) -> CounterResult<TrendState> {
+        let data = fs::read(&path).map_err(|error| CounterError::file_error(&path, &error))?;
+        bincode::deserialize(&data).map_err(|_| CounterError::parsing_failure("Trend State"))
+    }
+
+    /// Save
+    ///
+    /// Writes the tracker state to a file, replacing any previous state
+    /// atomically.
+    ///
+    /// # Arguments
+    /// `path` - Path of the state file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> CounterResult<()> {
+        let path = path.as_ref();
+        let data = bincode::serialize(&self.state)
+            .map_err(|_| CounterError::parsing_failure("Trend State"))?;
+        write_atomic(path, &data)
+    }
+
+    /// Current tracker state
+    pub fn state(&self) -> &TrendState {
+        &self.state
+    }
+
+    /// Completed daily summaries, oldest first
+    pub fn summaries(&self) -> &VecDeque<DailySummary> {
+        &self.state.summaries
+    }
+
+    /// Reference the drift is measured against
+    pub fn reference(&self) -> Option<&TrendReference> {
+        self.state.reference.as_ref()
+    }
+
+    /// Discards the reference, so it is taken again from the next days
+    pub fn reset_reference(&mut self) {
+        self.state.reference = None;
+    }
+
+    /// Update
+    ///
+    /// Adds a sample to the summary of its UTC day. Suspect samples and
+    /// samples from a day before the current one are ignored, and a sample
+    /// with an unreliable tube does not count towards the quiet baseline.
+    /// Returns the summary of the previous day once a sample of a new day
+    /// arrives.
+    ///
+    /// # Arguments
+    /// `sample` - Calibrated sample
+    /// `quiet` - Whether the sample was taken in a quiet period
+    pub fn update(&mut self, sample: &CalibratedSample, quiet: bool) -> Option<DailySummary> {
+        let quiet = quiet && sample.is_reliable();
+        let sample = &sample.sample;
+        if !sample.quality.is_usable() {
+            return None;
+        }
+        let day = sample.utc_ms / MS_PER_DAY;
+        let mut completed = None;
+        match &self.state.current {
+            Some(current) if day < current.day => return None,
+            Some(current) if day > current.day => {
+                completed = self.state.current.take();
+            }
+            _ => {}
+        }
+
+        let current = self.state.current.get_or_insert(DailySummary {
+            day,
+            ..DailySummary::default()
+        });
+        current.samples += 1;
+        current.live_ms += u64::from(sample.interval_ms);
+        if quiet {
+            current.quiet_live_ms += u64::from(sample.interval_ms);
+        }
+        let interval = sample.interval_secs();
+        for tube in 0..3 {
+            let counts = u64::from(sample.counts[tube]);
+            current.counts[tube] += counts;
+            if quiet {
+                current.quiet_counts[tube] += counts;
+            }
+            if interval > 0.0 {
+                current.max_rate[tube] = current.max_rate[tube].max(counts as f64 / interval);
+            }
+        }
+
+        if let Some(summary) = &completed {
+            self.complete(summary.clone());
+        }
+        completed
+    }
+
+    fn complete(&mut self, summary: DailySummary) {
+        self.state.summaries.push_back(summary);
+        while self.state.summaries.len() > self.config.max_days.max(1) {
+            self.state.summaries.pop_front();
+        }
+
+        if self.state.reference.is_none() {
+            let days: Vec<&DailySummary> = self
+                .state
+                .summaries
+                .iter()
+                .filter(|summary| summary.ratio(0).is_some())
+                .take(self.config.reference_days.max(1))
+                .collect();
+            if days.len() >= self.config.reference_days.max(1) {
+                let mut reference = TrendReference::default();
+                for tube in 0..3 {
+                    let n = days.len() as f64;
+                    reference.baseline[tube] = days
+                        .iter()
+                        .filter_map(|day| day.baseline(tube))
+                        .map(|rate| rate.value)
+                        .sum::<f64>()
+                        / n;
+                    reference.ratio[tube] =
+                        days.iter().filter_map(|day| day.ratio(tube)).sum::<f64>() / n;
+                }
+                self.state.reference = Some(reference);
+            }
+        }
+    }
+
+    fn fit(&self, value: impl Fn(&DailySummary) -> Option<f64>) -> Option<DriftFit> {
+        let points: Vec<(f64, f64)> = self
+            .state
+            .summaries
+            .iter()
+            .rev()
+            .take(self.config.fit_days.max(2))
+            .filter_map(|summary| value(summary).map(|value| (summary.day as f64, value)))
+            .collect();
+        if points.len() < 2 {
+            return None;
+        }
+
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let sxx: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        let sxy: f64 = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let slope = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+        // Points are collected newest first
+        let latest = points[0].0;
+        Some(DriftFit {
+            level: mean_y + slope * (latest - mean_x),
+            slope,
+            days: points.len(),
+        })
+    }
+
+    /// Drift of a tube's quiet-time baseline rate (cps)
+    ///
+    /// Returns `None` with fewer than two quiet days in the fit window, or for
+    /// a tube which does not exist.
+    ///
+    /// # Arguments
+    /// `tube` - Tube index, 0 to 2
+    pub fn baseline_drift(&self, tube: usize) -> Option<DriftFit> {
+        self.fit(|summary| summary.baseline(tube).map(|rate| rate.value))
+    }
+
+    /// Drift of a tube's ratio to the mean of the three tubes
+    ///
+    /// Returns `None` with fewer than two quiet days in the fit window, or for
+    /// a tube which does not exist.
+    ///
+    /// # Arguments
+    /// `tube` - Tube index, 0 to 2
+    pub fn ratio_drift(&self, tube: usize) -> Option<DriftFit> {
+        self.fit(|summary| summary.ratio(tube))
+    }
+
+    /// Warnings
+    ///
+    /// Compares the fitted baseline and ratio of each tube against the
+    /// reference and returns those beyond their limits.
+    pub fn warnings(&self) -> Vec<TrendWarning> {
+        let reference = match &self.state.reference {
+            Some(reference) => reference,
+            None => return Vec::new(),
+        };
+
+        let mut warnings = Vec::new();
+        for tube in 0..3 {
+            let checks = [
+                (
+                    TrendWarningKind::Baseline,
+                    reference.baseline[tube],
+                    self.baseline_drift(tube),
+                    self.config.baseline_limit,
+                ),
+                (
+                    TrendWarningKind::Ratio,
+                    reference.ratio[tube],
+                    self.ratio_drift(tube),
+                    self.config.ratio_limit,
+                ),
+            ];
+            for (kind, reference, fit, limit) in checks.iter() {
+                if let Some(fit) = fit {
+                    if *reference <= 0.0 {
+                        continue;
+                    }
+                    let change = fit.level / reference - 1.0;
+                    if change.abs() > *limit {
+                        warnings.push(TrendWarning {
+                            tube,
+                            kind: *kind,
+                            reference: *reference,
+                            current: fit.level,
+                            change,
+                        });
+                    }
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Export
+    ///
+    /// Encodes the completed daily summaries from a day onwards for the
+    /// downlink. Decode with [`DailySummary::decode`].
+    ///
+    /// # Arguments
+    /// `since_day` - First day index to export
+    pub fn export(&self, since_day: u64) -> CounterResult<Vec<u8>> {
+        let summaries: Vec<&DailySummary> = self
+            .state
+            .summaries
+            .iter()
+            .filter(|summary| summary.day >= since_day)
+            .collect();
+        bincode::serialize(&summaries).map_err(|_| CounterError::parsing_failure("Trend Export"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SampleQuality;
+    use crate::test_util::{calibrated, temp_path, tube_rate};
+
+    fn sample(utc_ms: u64, counts: [u16; 3]) -> CalibratedSample {
+        let tube = |counts: u16| tube_rate(counts, 0.0, 0.0);
+        calibrated(
+            utc_ms,
+            10_000,
+            [tube(counts[0]), tube(counts[1]), tube(counts[2])],
+        )
+    }
+
+    fn config() -> TrendConfig {
+        TrendConfig {
+            max_days: 100,
+            reference_days: 3,
+            fit_days: 5,
+            baseline_limit: 0.25,
+            ratio_limit: 0.1,
+        }
+    }
+
+    // Feeds one quiet day of ten samples with the given counts per sample,
+    // plus a noisy sample that only counts towards the daily totals
+    fn feed_day(tracker: &mut TrendTracker, day: u64, counts: [u16; 3]) {
+        for index in 0..10 {
+            tracker.update(&sample(day * MS_PER_DAY + index * 10_000, counts), true);
+        }
+        tracker.update(&sample(day * MS_PER_DAY + 200_000, [500; 3]), false);
+    }
+
+    #[test]
+    fn test_daily_summary() {
+        let mut tracker = TrendTracker::new(config(), None);
+        feed_day(&mut tracker, 0, [100, 100, 100]);
+        assert!(tracker.summaries().is_empty());
+
+        let summary = tracker.update(&sample(MS_PER_DAY, [0; 3]), true).unwrap();
+        assert_eq!(summary.day, 0);
+        assert_eq!(summary.samples, 11);
+        assert_eq!(summary.counts[0], 1500);
+        assert_eq!(summary.baseline(0).unwrap().value, 10.0);
+        assert_eq!(summary.rate(0).unwrap().value, 1500.0 / 110.0);
+        assert_eq!(summary.max_rate[0], 50.0);
+        assert_eq!(summary.ratio(1), Some(1.0));
+        assert!(summary.rate(3).is_none());
+        assert!(summary.baseline(3).is_none());
+        assert!(summary.ratio(3).is_none());
+
+        // Samples of an earlier day are ignored
+        assert!(tracker.update(&sample(0, [1; 3]), true).is_none());
+        assert_eq!(tracker.state().current.as_ref().unwrap().samples, 1);
+    }
+
+    #[test]
+    fn test_suspect_and_unreliable() {
+        let mut tracker = TrendTracker::new(config(), None);
+        let mut suspect = sample(10_000, [0; 3]);
+        suspect.sample.quality.insert(SampleQuality::SUSPECT);
+        assert!(tracker.update(&suspect, true).is_none());
+        assert!(tracker.state().current.is_none());
+
+        let mut wrapped = sample(0, [100; 3]);
+        wrapped.sample.quality.insert(SampleQuality::WRAPPED);
+        tracker.update(&wrapped, true);
+        let mut unreliable = sample(20_000, [300; 3]);
+        unreliable.tubes[0].reliable = false;
+        tracker.update(&unreliable, true);
+        tracker.update(&suspect, true);
+
+        let summary = tracker.update(&sample(MS_PER_DAY, [0; 3]), true).unwrap();
+        assert_eq!(summary.samples, 2);
+        assert_eq!(summary.counts[0], 400);
+        assert_eq!(summary.baseline(0).unwrap().value, 10.0);
+        assert_eq!(summary.max_rate[0], 30.0);
+    }
+
+    #[test]
+    fn test_drift_warnings() {
+        let mut tracker = TrendTracker::new(config(), None);
+        for day in 0..4 {
+            feed_day(&mut tracker, day, [100, 100, 100]);
+        }
+        feed_day(&mut tracker, 4, [100, 100, 100]);
+        let reference = *tracker.reference().unwrap();
+        assert_eq!(reference.baseline, [10.0; 3]);
+        assert_eq!(reference.ratio, [1.0; 3]);
+        assert!(tracker.warnings().is_empty());
+
+        // Tube 2 loses gain by 10 % of its reference per day
+        for day in 5..12 {
+            let counts = 100 - 10 * (day as u16 - 4);
+            feed_day(&mut tracker, day, [100, 100, counts]);
+        }
+        assert!(tracker.baseline_drift(3).is_none());
+        assert!(tracker.ratio_drift(3).is_none());
+        let fit = tracker.baseline_drift(2).unwrap();
+        assert_eq!(fit.days, 5);
+        assert!((fit.slope + 1.0).abs() < 1e-9);
+        // Day 11 is still being accumulated, so the fit ends at day 10
+        assert!((fit.level - 4.0).abs() < 1e-9);
+
+        let warnings = tracker.warnings();
+        assert!(warnings
+            .iter()
+            .all(|warning| warning.tube == 2 || warning.kind == TrendWarningKind::Ratio));
+        assert!(warnings
+            .iter()
+            .any(|warning| warning.tube == 2 && warning.kind == TrendWarningKind::Baseline));
+        assert!(warnings
+            .iter()
+            .any(|warning| warning.tube == 2 && warning.kind == TrendWarningKind::Ratio));
+    }
+
+    #[test]
+    fn test_export_and_persistence() {
+        let mut tracker = TrendTracker::new(config(), None);
+        for day in 0..5 {
+            feed_day(&mut tracker, day, [100, 90, 80]);
+        }
+        let exported = DailySummary::decode(&tracker.export(2).unwrap()).unwrap();
+        assert_eq!(exported.len(), 2);
+        assert_eq!(exported[0], tracker.summaries()[2]);
+
+        let path = temp_path("trend.bin");
+        tracker.save(&path).unwrap();
+        let resumed = TrendTracker::new(config(), Some(TrendTracker::load(&path).unwrap()));
+        assert_eq!(resumed.state(), tracker.state());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,417 @@
+//! Counter Array
+//!
+//! Drives several radiation counter boards sharing a bus. Boards are polled
+//! in a round-robin whose starting board rotates every cycle. Each driver
+//! only waits for its own inter-command delay, so polling one board does not
+//! hold up the next, and a failing board is recorded without stopping the
+//! remaining boards from being polled.
+
+use crate::objects::RCHk;
+use crate::radiation_counter::{CuavaRadiationCounter, RadiationCounter};
+use crate::CounterError;
+use i2c_rs::Connection;
+use std::time::Instant;
+
+// Consecutive failures after which a board is considered failed
+const FAILED_THRESHOLD: u32 = 3;
+
+/// Health of a board in the array
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BoardStatus {
+    /// Board has not been polled yet
+    Unknown,
+    /// Last poll succeeded
+    Nominal,
+    /// Last poll failed
+    Degraded,
+    /// Several consecutive polls failed
+    Failed,
+}
+
+/// Poll statistics of a board in the array
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoardHealth {
+    /// Overall status derived from the poll history
+    pub status: BoardStatus,
+    /// Number of failed polls since the last successful one
+    pub consecutive_failures: u32,
+    /// Total number of polls
+    pub polls: u64,
+    /// Total number of failed polls
+    pub failures: u64,
+    /// Time of the last successful poll
+    pub last_success: Option<Instant>,
+}
+
+impl Default for BoardHealth {
+    fn default() -> Self {
+        BoardHealth {
+            status: BoardStatus::Unknown,
+            consecutive_failures: 0,
+            polls: 0,
+            failures: 0,
+            last_success: None,
+        }
+    }
+}
+
+impl BoardHealth {
+    fn record_success(&mut self) {
+        self.polls += 1;
+        self.consecutive_failures = 0;
+        self.last_success = Some(Instant::now());
+        self.status = BoardStatus::Nominal;
+    }
+
+    fn record_failure(&mut self) {
+        self.polls += 1;
+        self.failures += 1;
+        self.consecutive_failures += 1;
+        self.status = if self.consecutive_failures >= FAILED_THRESHOLD {
+            BoardStatus::Failed
+        } else {
+            BoardStatus::Degraded
+        };
+    }
+}
+
+/// Result of polling a single board
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoardSnapshot {
+    /// Name of the board in the array
+    pub name: String,
+    /// Counter values, if the poll succeeded
+    pub reading: Option<RCHk>,
+    /// Error returned by the poll, if it failed
+    pub error: Option<CounterError>,
+    /// Board health after the poll
+    pub health: BoardHealth,
+}
+
+/// Combined result of polling every board in the array
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArraySnapshot {
+    /// Time the poll cycle started
+    pub timestamp: Instant,
+    /// Per-board results, in the order the boards were added
+    pub boards: Vec<BoardSnapshot>,
+}
+
+impl ArraySnapshot {
+    /// Snapshot of a single board
+    ///
+    /// # Arguments
+    /// `name` - Name of the board
+    pub fn board(&self, name: &str) -> Option<&BoardSnapshot> {
+        self.boards.iter().find(|board| board.name == name)
+    }
+}
+
+struct Board<C> {
+    name: String,
+    counter: C,
+    health: BoardHealth,
+}
+
+/// Collection of radiation counters polled together
+pub struct CounterArray<C: CuavaRadiationCounter = RadiationCounter> {
+    boards: Vec<Board<C>>,
+    next: usize,
+}
+
+impl<C: CuavaRadiationCounter> Default for CounterArray<C> {
+    fn default() -> Self {
+        CounterArray {
+            boards: Vec::new(),
+            next: 0,
+        }
+    }
+}
+
+impl CounterArray<RadiationCounter> {
+    /// Add Board by Address
+    ///
+    /// Connects to the board at `address` on `bus`, identifies it and adds it
+    /// to the array, named after its address (e.g. `0x4A`).
+    ///
+    /// # Arguments
+    /// `bus` - Path of the I2C bus, e.g. `/dev/i2c-1`
+    /// `address` - I2C address of the board
+    pub fn add_address(&mut self, bus: &str, address: u16) -> Option<RadiationCounter> {
+        self.add(
+            &format!("0x{:02X}", address),
+            RadiationCounter::identified(Connection::from_path(bus, address)),
+        )
+    }
+}
+
+impl<C: CuavaRadiationCounter> CounterArray<C> {
+    /// Constructor
+    ///
+    /// Creates an empty array.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add Board
+    ///
+    /// Adds a board to the array. A board already in the array under the same
+    /// name is replaced and returned.
+    ///
+    /// # Arguments
+    /// `name` - Name of the board
+    /// `counter` - Driver of the board
+    pub fn add(&mut self, name: &str, counter: C) -> Option<C> {
+        let board = Board {
+            name: name.to_string(),
+            counter,
+            health: BoardHealth::default(),
+        };
+        match self.boards.iter_mut().find(|board| board.name == name) {
+            Some(existing) => Some(std::mem::replace(existing, board).counter),
+            None => {
+                self.boards.push(board);
+                None
+            }
+        }
+    }
+
+    /// Remove Board
+    ///
+    /// # Arguments
+    /// `name` - Name of the board
+    pub fn remove(&mut self, name: &str) -> Option<C> {
+        let index = self.boards.iter().position(|board| board.name == name)?;
+        let board = self.boards.remove(index);
+        if self.next > index {
+            self.next -= 1;
+        }
+        if self.next >= self.boards.len() {
+            self.next = 0;
+        }
+        Some(board.counter)
+    }
+
+    /// Driver of a board
+    ///
+    /// # Arguments
+    /// `name` - Name of the board
+    pub fn get(&self, name: &str) -> Option<&C> {
+        self.boards
+            .iter()
+            .find(|board| board.name == name)
+            .map(|board| &board.counter)
+    }
+
+    /// Mutable driver of a board
+    ///
+    /// # Arguments
+    /// `name` - Name of the board
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut C> {
+        self.boards
+            .iter_mut()
+            .find(|board| board.name == name)
+            .map(|board| &mut board.counter)
+    }
+
+    /// Names of the boards, in the order they were added
+    pub fn names(&self) -> Vec<&str> {
+        self.boards
+            .iter()
+            .map(|board| board.name.as_str())
+            .collect()
+    }
+
+    /// Number of boards in the array
+    pub fn len(&self) -> usize {
+        self.boards.len()
+    }
+
+    /// Whether the array holds no boards
+    pub fn is_empty(&self) -> bool {
+        self.boards.is_empty()
+    }
+
+    /// Health of a board
+    ///
+    /// # Arguments
+    /// `name` - Name of the board
+    pub fn health(&self, name: &str) -> Option<&BoardHealth> {
+        self.boards
+            .iter()
+            .find(|board| board.name == name)
+            .map(|board| &board.health)
+    }
+
+    /// Poll
+    ///
+    /// Reads the counter values of every board once. The board polled first
+    /// rotates every cycle so no board is always read last. Failures are
+    /// recorded in the board's snapshot and health.
+    pub fn poll(&mut self) -> ArraySnapshot {
+        let timestamp = Instant::now();
+        let count = self.boards.len();
+        let mut results: Vec<Option<BoardSnapshot>> = vec![None; count];
+
+        for offset in 0..count {
+            let index = (self.next + offset) % count;
+            let board = &mut self.boards[index];
+            let (reading, error) = match board.counter.get_radiation_count() {
+                Ok(reading) => {
+                    board.health.record_success();
+                    (Some(reading), None)
+                }
+                Err(error) => {
+                    board.health.record_failure();
+                    (None, Some(error))
+                }
+            };
+            results[index] = Some(BoardSnapshot {
+                name: board.name.clone(),
+                reading,
+                error,
+                health: board.health.clone(),
+            });
+        }
+
+        if count > 0 {
+            self.next = (self.next + 1) % count;
+        }
+
+        ArraySnapshot {
+            timestamp,
+            boards: results.into_iter().flatten().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::last_error::ErrorCode;
+    use crate::objects::{BoardHk, BoardInfo, ResetCounters};
+    use crate::CounterResult;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct MockCounter {
+        id: i16,
+        fail: bool,
+        log: Rc<RefCell<Vec<i16>>>,
+    }
+
+    impl CuavaRadiationCounter for MockCounter {
+        fn get_last_error(&self) -> CounterResult<ErrorCode> {
+            Ok(ErrorCode::None)
+        }
+        fn manual_reset(&self) -> CounterResult<()> {
+            Ok(())
+        }
+        fn reset_comms_watchdog(&self) -> CounterResult<()> {
+            Ok(())
+        }
+        fn set_comms_watchdog_period(&self, _period: u8) -> CounterResult<()> {
+            Ok(())
+        }
+        fn get_comms_watchdog_period(&self) -> CounterResult<u8> {
+            Ok(4)
+        }
+        fn get_radiation_count(&mut self) -> CounterResult<RCHk> {
+            self.log.borrow_mut().push(self.id);
+            if self.fail {
+                Err(CounterError::I2CError(std::io::ErrorKind::TimedOut))
+            } else {
+                Ok(RCHk {
+                    rc1_reading: self.id,
+                    rc2_reading: self.id,
+                    rc3_reading: self.id,
+                })
+            }
+        }
+        fn get_board_housekeeping(&self) -> CounterResult<BoardHk> {
+            Err(CounterError::GenericError)
+        }
+        fn get_board_info(&self) -> CounterResult<BoardInfo> {
+            Err(CounterError::GenericError)
+        }
+        fn get_reset_counters(&self) -> CounterResult<ResetCounters> {
+            Ok(ResetCounters::default())
+        }
+    }
+
+    fn array(fail: &[bool]) -> (CounterArray<MockCounter>, Rc<RefCell<Vec<i16>>>) {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut array = CounterArray::new();
+        for (id, fail) in fail.iter().enumerate() {
+            array.add(
+                &format!("rc{}", id),
+                MockCounter {
+                    id: id as i16,
+                    fail: *fail,
+                    log: log.clone(),
+                },
+            );
+        }
+        (array, log)
+    }
+
+    #[test]
+    fn test_poll_isolates_failures() {
+        let (mut array, _log) = array(&[false, true, false]);
+        for _ in 0..FAILED_THRESHOLD {
+            array.poll();
+        }
+        let snapshot = array.poll();
+
+        assert_eq!(snapshot.boards.len(), 3);
+        assert_eq!(
+            snapshot
+                .board("rc0")
+                .unwrap()
+                .reading
+                .as_ref()
+                .unwrap()
+                .rc1_reading,
+            0
+        );
+        assert_eq!(
+            snapshot.board("rc2").unwrap().health.status,
+            BoardStatus::Nominal
+        );
+
+        let failed = snapshot.board("rc1").unwrap();
+        assert!(failed.reading.is_none());
+        assert_eq!(
+            failed.error,
+            Some(CounterError::I2CError(std::io::ErrorKind::TimedOut))
+        );
+        assert_eq!(failed.health.status, BoardStatus::Failed);
+        assert_eq!(failed.health.failures, 4);
+    }
+
+    #[test]
+    fn test_poll_rotates_start() {
+        let (mut array, log) = array(&[false, false, false]);
+        array.poll();
+        array.poll();
+        assert_eq!(*log.borrow(), vec![0, 1, 2, 1, 2, 0]);
+    }
+
+    #[test]
+    fn test_add_replace_remove() {
+        let (mut array, _log) = array(&[false, false]);
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let replaced = array.add(
+            "rc1",
+            MockCounter {
+                id: 7,
+                fail: false,
+                log,
+            },
+        );
+        assert_eq!(replaced.unwrap().id, 1);
+        assert_eq!(array.get("rc1").unwrap().id, 7);
+        assert_eq!(array.remove("rc0").unwrap().id, 0);
+        assert_eq!(array.names(), vec!["rc1"]);
+    }
+}
@@ -0,0 +1,44 @@
+use crate::objects::RCHk;
+use crate::{CounterError, CounterResult};
+use i2c_rs::Command;
+use std::time::Duration;
+
+// `get_radiation_count` is generated from the ICD
+include!(concat!(env!("OUT_DIR"), "/commands/count.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        assert_eq!(
+            get_radiation_count::command(),
+            (
+                Command {
+                    cmd: 0x01,
+                    data: vec![],
+                },
+                6,
+                Duration::from_millis(3),
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let counts =
+            get_radiation_count::parse(&[0x00, 0x01, 0x01, 0x00, 0xFF, 0xFF]).unwrap();
+        assert_eq!(counts.rc1_reading, 1);
+        assert_eq!(counts.rc2_reading, 256);
+        assert_eq!(counts.rc3_reading, -1);
+    }
+
+    #[test]
+    fn test_parse_bad_data_len() {
+        assert_eq!(
+            CounterError::parsing_failure("Radiation Count"),
+            get_radiation_count::parse(&[0x00, 0x01]).err().unwrap()
+        );
+    }
+}
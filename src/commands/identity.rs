@@ -0,0 +1,44 @@
+use crate::objects::BoardInfo;
+use crate::{CounterError, CounterResult};
+use i2c_rs::Command;
+use std::time::Duration;
+
+// `get_board_info` is generated from the ICD
+include!(concat!(env!("OUT_DIR"), "/commands/identity.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let info =
+            get_board_info::parse(&[0x00, 0x02, 0x01, 0x04, 0x09, 0x00, 0x00, 0x01, 0x2C]).unwrap();
+        assert_eq!(
+            info,
+            BoardInfo {
+                board_revision: 2,
+                firmware_major: 1,
+                firmware_minor: 4,
+                firmware_patch: 9,
+                serial: 300,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_error_value() {
+        // Legacy firmware replies with 0xFFFF, and the bus reads 0xFF for
+        // the remaining bytes
+        assert_eq!(
+            CounterError::parsing_failure("Board Info"),
+            get_board_info::parse(&[0xFF; 9]).err().unwrap()
+        );
+        assert_eq!(
+            CounterError::parsing_failure("Board Info"),
+            get_board_info::parse(&[0xFF, 0xFF, 0x01, 0x04, 0x09, 0x00, 0x00, 0x01, 0x2C])
+                .err()
+                .unwrap()
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{CounterError, CounterResult};
 // use rust_i2c::Command;
 use i2c_rs::Command;
+use std::time::Duration;
 // use failure::{Fail};
 use serde::*;
 
@@ -38,23 +39,8 @@ impl ErrorCode {
     }
 }
 
-pub fn parse(data: &[u8]) -> CounterResult<ErrorCode> {
-    if data.len() == 2 {
-        Ok(ErrorCode::from_u8(data[1]))
-    } else {
-        Err(CounterError::parsing_failure("Last Error"))
-    }
-}
-
-pub fn command() -> (Command, usize) {
-    (
-        Command {
-            cmd: 0x03,
-            data: vec![0x00],
-        },
-        4,
-    )
-}
+// `parse` and `command` are generated from the ICD
+include!(concat!(env!("OUT_DIR"), "/commands/last_error.rs"));
 
 #[cfg(test)]
 mod tests {
@@ -1,8 +1,17 @@
+mod count;
+mod identity;
 mod reset;
 mod watchdog;
 
 pub mod last_error;
 
+/// Opcodes generated from the Interface Control Document
+pub mod opcodes {
+    include!(concat!(env!("OUT_DIR"), "/opcodes.rs"));
+}
+
+pub use crate::commands::count::*;
+pub use crate::commands::identity::*;
 pub use crate::commands::last_error::*;
 pub use crate::commands::reset::*;
 pub use crate::commands::watchdog::*;
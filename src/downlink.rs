@@ -0,0 +1,620 @@
+//! Downlink Compression
+//!
+//! Compact packets for sequences of [`RCHk`] readings. The counters are free
+//! running, so consecutive readings differ by the few counts registered in
+//! between. Each packet stores its first reading in full and every following
+//! reading as the wrapping difference to the previous one. The differences
+//! are zig-zag mapped to unsigned values and Rice coded. The differences are
+//! coded in blocks of [`BLOCK_SIZE`] readings, each starting with a 4-bit
+//! Rice parameter per tube chosen to minimise the size of the block, so the
+//! code adapts quickly to the rate changes of an SAA passage.
+//!
+//! Packet layout, big-endian:
+//!
+//! | Size | Field                                                |
+//! |------|------------------------------------------------------|
+//! | 1    | Format version                                       |
+//! | 4    | Packet length, all fields included (bytes)           |
+//! | 2    | Number of readings `n`                               |
+//! | 4    | CRC-32 of the three previous fields                  |
+//! | 6    | First reading, if `n > 0`                            |
+//! | ...  | Blocks of coded differences, padded to a full byte   |
+//! | 4    | CRC-32 of all previous fields                        |
+//!
+//! A quotient of [`ESCAPE_QUOTIENT`] or more, e.g. after a counter reset, is
+//! written as that many one bits followed by the raw 16-bit value.
+//!
+//! The decoder only returns the readings of packets whose CRC matches. After
+//! a corrupted or lost byte it discards data up to the next valid packet, so
+//! a damaged packet does not take the rest of the stream with it. The length
+//! is only trusted once the header CRC matches, so a corrupted length does not
+//! hold back the packets behind it.
+
+use crate::objects::RCHk;
+use crate::{CounterError, CounterResult};
+
+/// Format version of the packets written by this version
+pub const PACKET_VERSION: u8 = 3;
+
+/// Unary quotient length from which values are written raw
+pub const ESCAPE_QUOTIENT: u32 = 24;
+
+/// Number of differences sharing a set of Rice parameters
+pub const BLOCK_SIZE: usize = 16;
+
+// Version, length and count, covered by the header CRC
+const HEADER_FIELDS_SIZE: usize = 7;
+const HEADER_SIZE: usize = HEADER_FIELDS_SIZE + CRC_SIZE;
+const FIRST_SIZE: usize = 6;
+const CRC_SIZE: usize = 4;
+const MAX_RICE_PARAMETER: u8 = 15;
+const PARAMETER_BITS: u32 = 4;
+
+// Size of a reading serialized with bincode
+const RAW_SIZE: usize = 6;
+
+fn zigzag(delta: i16) -> u16 {
+    ((delta << 1) ^ (delta >> 15)) as u16
+}
+
+fn unzigzag(value: u16) -> i16 {
+    ((value >> 1) as i16) ^ -((value & 1) as i16)
+}
+
+fn channels(reading: &RCHk) -> [i16; 3] {
+    [
+        reading.rc1_reading,
+        reading.rc2_reading,
+        reading.rc3_reading,
+    ]
+}
+
+// Bounds of the size of a packet of `count` readings (bytes)
+fn packet_size_range(count: u16) -> (usize, usize) {
+    if count == 0 {
+        return (HEADER_SIZE + CRC_SIZE, HEADER_SIZE + CRC_SIZE);
+    }
+    let differences = usize::from(count) - 1;
+    let blocks = differences.div_ceil(BLOCK_SIZE);
+    let max_bits =
+        blocks * 3 * PARAMETER_BITS as usize + differences * 3 * (ESCAPE_QUOTIENT as usize + 16);
+    let min_size = HEADER_SIZE + FIRST_SIZE + CRC_SIZE;
+    (min_size, min_size + max_bits.div_ceil(8))
+}
+
+fn rice_bits(value: u16, k: u8) -> u32 {
+    let quotient = u32::from(value >> k);
+    if quotient >= ESCAPE_QUOTIENT {
+        ESCAPE_QUOTIENT + 16
+    } else {
+        quotient + 1 + u32::from(k)
+    }
+}
+
+/// Compression statistics
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CompressionStats {
+    /// Number of readings encoded
+    pub samples: u64,
+    /// Size of the readings serialized with bincode (bytes)
+    pub raw_bytes: u64,
+    /// Size of the packets (bytes)
+    pub encoded_bytes: u64,
+}
+
+impl CompressionStats {
+    /// Compression ratio, raw size over packet size
+    pub fn ratio(&self) -> f64 {
+        if self.encoded_bytes == 0 {
+            0.0
+        } else {
+            self.raw_bytes as f64 / self.encoded_bytes as f64
+        }
+    }
+
+    /// Mean packet size per reading (bits)
+    pub fn bits_per_sample(&self) -> f64 {
+        if self.samples == 0 {
+            0.0
+        } else {
+            self.encoded_bytes as f64 * 8.0 / self.samples as f64
+        }
+    }
+}
+
+struct BitWriter {
+    data: Vec<u8>,
+    used: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        for bit in (0..bits).rev() {
+            if self.used == 0 {
+                self.data.push(0);
+            }
+            if (value >> bit) & 1 == 1 {
+                if let Some(last) = self.data.last_mut() {
+                    *last |= 0x80 >> self.used;
+                }
+            }
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    fn write_rice(&mut self, value: u16, k: u8) {
+        let quotient = u32::from(value >> k);
+        if quotient >= ESCAPE_QUOTIENT {
+            self.write(u32::MAX, ESCAPE_QUOTIENT);
+            self.write(u32::from(value), 16);
+        } else {
+            self.write(u32::MAX, quotient);
+            self.write(0, 1);
+            self.write(u32::from(value), u32::from(k));
+        }
+    }
+}
+
+/// Encodes readings into downlink packets
+#[derive(Default)]
+pub struct CountEncoder {
+    stats: CompressionStats,
+}
+
+impl CountEncoder {
+    /// Constructor
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Statistics of all packets encoded so far
+    pub fn stats(&self) -> CompressionStats {
+        self.stats
+    }
+
+    /// Encode
+    ///
+    /// Encodes readings into one packet. At most 65535 readings fit into a
+    /// packet, any further readings are left out.
+    ///
+    /// # Arguments
+    /// `readings` - Consecutive readings, oldest first
+    pub fn encode(&mut self, readings: &[RCHk]) -> Vec<u8> {
+        let readings = &readings[..readings.len().min(usize::from(u16::MAX))];
+        let mut data = vec![PACKET_VERSION, 0, 0, 0, 0];
+        data.extend_from_slice(&(readings.len() as u16).to_be_bytes());
+        data.extend_from_slice(&[0; CRC_SIZE]);
+
+        if let Some(first) = readings.first() {
+            for value in channels(first).iter() {
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+
+            let values: Vec<[u16; 3]> = readings
+                .windows(2)
+                .map(|pair| {
+                    let (previous, current) = (channels(&pair[0]), channels(&pair[1]));
+                    let mut values = [0u16; 3];
+                    for (value, (current, previous)) in
+                        values.iter_mut().zip(current.iter().zip(previous.iter()))
+                    {
+                        *value = zigzag(current.wrapping_sub(*previous));
+                    }
+                    values
+                })
+                .collect();
+
+            let mut writer = BitWriter { data, used: 0 };
+            for block in values.chunks(BLOCK_SIZE) {
+                let mut parameters = [0u8; 3];
+                for (tube, k) in parameters.iter_mut().enumerate() {
+                    *k = (0..=MAX_RICE_PARAMETER)
+                        .min_by_key(|k| {
+                            block
+                                .iter()
+                                .map(|values| rice_bits(values[tube], *k))
+                                .sum::<u32>()
+                        })
+                        .unwrap_or(0);
+                    writer.write(u32::from(*k), PARAMETER_BITS);
+                }
+                for values in block.iter() {
+                    for (value, k) in values.iter().zip(parameters.iter()) {
+                        writer.write_rice(*value, *k);
+                    }
+                }
+            }
+            data = writer.data;
+        }
+
+        let size = (data.len() + CRC_SIZE) as u32;
+        data[1..5].copy_from_slice(&size.to_be_bytes());
+        let header_crc = crc32fast::hash(&data[..HEADER_FIELDS_SIZE]);
+        data[HEADER_FIELDS_SIZE..HEADER_SIZE].copy_from_slice(&header_crc.to_be_bytes());
+        let crc = crc32fast::hash(&data);
+        data.extend_from_slice(&crc.to_be_bytes());
+
+        self.stats.samples += readings.len() as u64;
+        self.stats.raw_bytes += (readings.len() * RAW_SIZE) as u64;
+        self.stats.encoded_bytes += data.len() as u64;
+        data
+    }
+}
+
+// Packet being decoded
+struct PacketState {
+    decoded: usize,
+    parameters: [u8; 3],
+    previous: [i16; 3],
+}
+
+/// Streaming decoder for downlink packets
+///
+/// Accepts a stream of concatenated packets in arbitrary chunks and returns
+/// the readings of each packet once it is complete and its CRC matches.
+/// Bytes which do not belong to a valid packet are discarded.
+#[derive(Default)]
+pub struct CountDecoder {
+    buffer: Vec<u8>,
+    discarded: u64,
+}
+
+impl CountDecoder {
+    /// Constructor
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the decoder holds an incomplete packet
+    pub fn is_idle(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Number of bytes discarded while searching for a valid packet
+    pub fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    /// Push
+    ///
+    /// Adds received bytes and returns the readings of the packets completed
+    /// by them.
+    ///
+    /// # Arguments
+    /// `data` - Received bytes
+    pub fn push(&mut self, data: &[u8]) -> Vec<RCHk> {
+        self.buffer.extend_from_slice(data);
+        let mut readings = Vec::new();
+
+        while self.buffer.len() >= HEADER_SIZE {
+            let size = match packet_size(&self.buffer) {
+                Some(size) if self.buffer.len() < size => break,
+                Some(size) => size,
+                None => {
+                    self.resync();
+                    continue;
+                }
+            };
+            match decode_packet(&self.buffer[..size]) {
+                Some(packet) => {
+                    readings.extend(packet);
+                    self.buffer.drain(..size);
+                }
+                None => self.resync(),
+            }
+        }
+        readings
+    }
+
+    // Drops the first byte and anything up to the next possible packet start
+    fn resync(&mut self) {
+        let skip = self.buffer[1..]
+            .iter()
+            .position(|byte| *byte == PACKET_VERSION)
+            .map_or(self.buffer.len(), |position| position + 1);
+        self.buffer.drain(..skip);
+        self.discarded += skip as u64;
+    }
+
+    /// Decodes a single complete packet
+    ///
+    /// # Arguments
+    /// `packet` - Packet as returned by [`CountEncoder::encode`]
+    pub fn decode(packet: &[u8]) -> CounterResult<Vec<RCHk>> {
+        let mut decoder = CountDecoder::new();
+        let readings = decoder.push(packet);
+        if decoder.is_idle() && decoder.discarded() == 0 {
+            Ok(readings)
+        } else {
+            Err(CounterError::parsing_failure("Count Packet"))
+        }
+    }
+}
+
+// Size of the packet starting a buffer of at least `HEADER_SIZE` bytes, or
+// `None` if the header is not valid
+fn packet_size(data: &[u8]) -> Option<usize> {
+    let (fields, crc) = data[..HEADER_SIZE].split_at(HEADER_FIELDS_SIZE);
+    if data[0] != PACKET_VERSION
+        || u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) != crc32fast::hash(fields)
+    {
+        return None;
+    }
+    let size = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+    let (min_size, max_size) = packet_size_range(u16::from_be_bytes([data[5], data[6]]));
+    if size < min_size || size > max_size {
+        return None;
+    }
+    Some(size)
+}
+
+// Decodes a complete packet, or returns `None` if it is corrupted
+fn decode_packet(packet: &[u8]) -> Option<Vec<RCHk>> {
+    let (data, crc) = packet.split_at(packet.len() - CRC_SIZE);
+    if u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) != crc32fast::hash(data) {
+        return None;
+    }
+    let count = u16::from_be_bytes([data[5], data[6]]);
+    if count == 0 {
+        return Some(Vec::new());
+    }
+
+    let first = &data[HEADER_SIZE..HEADER_SIZE + FIRST_SIZE];
+    let first = [
+        i16::from_be_bytes([first[0], first[1]]),
+        i16::from_be_bytes([first[2], first[3]]),
+        i16::from_be_bytes([first[4], first[5]]),
+    ];
+    let mut state = PacketState {
+        decoded: 0,
+        parameters: [0; 3],
+        previous: first,
+    };
+    let mut readings = Vec::with_capacity(usize::from(count));
+    readings.push(first);
+
+    let body = &data[HEADER_SIZE + FIRST_SIZE..];
+    let mut bit = 0;
+    for _ in 1..count {
+        let current = read_reading(body, &mut bit, &mut state)?;
+        readings.push(current);
+        state.previous = current;
+        state.decoded += 1;
+    }
+    // Packets end on a full byte
+    if bit.div_ceil(8) != body.len() {
+        return None;
+    }
+
+    Some(
+        readings
+            .iter()
+            .map(|reading| RCHk {
+                rc1_reading: reading[0],
+                rc2_reading: reading[1],
+                rc3_reading: reading[2],
+            })
+            .collect(),
+    )
+}
+
+fn read_bits(data: &[u8], bit: &mut usize, bits: u32) -> Option<u32> {
+    if *bit + bits as usize > data.len() * 8 {
+        return None;
+    }
+    let mut value = 0;
+    for _ in 0..bits {
+        let set = data[*bit / 8] & (0x80 >> (*bit % 8)) != 0;
+        value = (value << 1) | u32::from(set);
+        *bit += 1;
+    }
+    Some(value)
+}
+
+// Reads the next reading of a packet, along with the Rice parameters of a
+// new block. Returns `None` if the data ends first.
+fn read_reading(data: &[u8], bit: &mut usize, packet: &mut PacketState) -> Option<[i16; 3]> {
+    let mut parameters = packet.parameters;
+    if packet.decoded % BLOCK_SIZE == 0 {
+        for k in parameters.iter_mut() {
+            *k = read_bits(data, bit, PARAMETER_BITS)? as u8;
+        }
+    }
+    let mut current = packet.previous;
+    for (value, k) in current.iter_mut().zip(parameters.iter()) {
+        *value = value.wrapping_add(unzigzag(read_rice(data, bit, *k)?));
+    }
+    packet.parameters = parameters;
+    Some(current)
+}
+
+fn read_rice(data: &[u8], bit: &mut usize, k: u8) -> Option<u16> {
+    let mut quotient = 0;
+    while quotient < ESCAPE_QUOTIENT {
+        if read_bits(data, bit, 1)? == 0 {
+            let remainder = read_bits(data, bit, u32::from(k))?;
+            return Some(((quotient << k) | remainder) as u16);
+        }
+        quotient += 1;
+    }
+    read_bits(data, bit, 16).map(|value| value as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Free running counters of a quiet orbit with an SAA passage, counting
+    // from near the top of the 16-bit range so they wrap
+    fn series(samples: usize) -> Vec<RCHk> {
+        let mut state: u32 = 12345;
+        let mut random = move |limit: u32| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) % limit
+        };
+        let mut counters = [32_000i16, 1_000, -5_000];
+        (0..samples)
+            .map(|index| {
+                let mean = if index % 300 > 250 { 400 } else { 12 };
+                for counter in counters.iter_mut() {
+                    let counts = mean / 2 + random(mean + 1);
+                    *counter = counter.wrapping_add(counts as i16);
+                }
+                RCHk {
+                    rc1_reading: counters[0],
+                    rc2_reading: counters[1],
+                    rc3_reading: counters[2],
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_zigzag() {
+        for delta in &[0i16, 1, -1, 2, -2, i16::MAX, i16::MIN] {
+            assert_eq!(unzigzag(zigzag(*delta)), *delta);
+        }
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let readings = series(1000);
+        let mut encoder = CountEncoder::new();
+        let packet = encoder.encode(&readings);
+        assert_eq!(CountDecoder::decode(&packet).unwrap(), readings);
+
+        let stats = encoder.stats();
+        assert_eq!(stats.samples, 1000);
+        assert_eq!(stats.raw_bytes, 6000);
+        assert!(stats.ratio() > 2.0, "ratio {}", stats.ratio());
+
+        // Counter resets and the error value need the escape
+        let jumps = vec![
+            RCHk {
+                rc1_reading: 30_000,
+                rc2_reading: 0,
+                rc3_reading: 5,
+            },
+            RCHk {
+                rc1_reading: -1,
+                rc2_reading: -1,
+                rc3_reading: -1,
+            },
+            RCHk {
+                rc1_reading: 3,
+                rc2_reading: i16::MIN,
+                rc3_reading: 7,
+            },
+        ];
+        assert_eq!(
+            CountDecoder::decode(&encoder.encode(&jumps)).unwrap(),
+            jumps
+        );
+        assert_eq!(CountDecoder::decode(&encoder.encode(&[])).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_streaming() {
+        let readings = series(500);
+        let mut encoder = CountEncoder::new();
+        let mut stream = Vec::new();
+        for chunk in readings.chunks(60) {
+            stream.extend(encoder.encode(chunk));
+        }
+
+        let mut decoder = CountDecoder::new();
+        let mut decoded = Vec::new();
+        for byte in stream.iter() {
+            decoded.extend(decoder.push(&[*byte]));
+        }
+        assert!(decoder.is_idle());
+        assert_eq!(decoder.discarded(), 0);
+        assert_eq!(decoded, readings);
+    }
+
+    #[test]
+    fn test_resync() {
+        let readings = series(300);
+        let mut encoder = CountEncoder::new();
+        let packets: Vec<Vec<u8>> = readings
+            .chunks(60)
+            .map(|chunk| encoder.encode(chunk))
+            .collect();
+
+        // Noise ahead of the stream, a flipped bit in the second packet and
+        // a byte lost from the fourth
+        let mut stream = vec![PACKET_VERSION, 0x00, 0x13];
+        stream.extend(&packets[0]);
+        let mut corrupted = packets[1].clone();
+        corrupted[20] ^= 0x10;
+        stream.extend(corrupted);
+        stream.extend(&packets[2]);
+        let mut truncated = packets[3].clone();
+        truncated.remove(30);
+        stream.extend(truncated);
+        stream.extend(&packets[4]);
+
+        let mut decoder = CountDecoder::new();
+        let mut decoded = Vec::new();
+        for chunk in stream.chunks(7) {
+            decoded.extend(decoder.push(chunk));
+        }
+        assert!(decoder.is_idle());
+        assert_eq!(
+            decoder.discarded(),
+            (3 + packets[1].len() + packets[3].len() - 1) as u64
+        );
+        let mut expected = readings[..60].to_vec();
+        expected.extend_from_slice(&readings[120..180]);
+        expected.extend_from_slice(&readings[240..]);
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn test_corrupted_length() {
+        let readings = series(120);
+        let mut encoder = CountEncoder::new();
+        let packets: Vec<Vec<u8>> = readings
+            .chunks(60)
+            .map(|chunk| encoder.encode(chunk))
+            .collect();
+
+        // A length still within the bounds of the count, but far beyond the
+        // end of the stream
+        let mut corrupted = packets[0].clone();
+        corrupted[3] ^= 0x01;
+        let size = u32::from_be_bytes([corrupted[1], corrupted[2], corrupted[3], corrupted[4]]);
+        assert!(size as usize <= packet_size_range(60).1);
+        let mut stream = corrupted.clone();
+        stream.extend(&packets[1]);
+        assert!(stream.len() < size as usize);
+
+        let mut decoder = CountDecoder::new();
+        assert_eq!(decoder.push(&stream), readings[60..].to_vec());
+        assert!(decoder.is_idle());
+        assert_eq!(decoder.discarded(), corrupted.len() as u64);
+    }
+
+    #[test]
+    fn test_invalid_packet() {
+        let mut packet = CountEncoder::new().encode(&series(10));
+        assert_eq!(
+            CountDecoder::decode(&packet[..packet.len() - 1]),
+            Err(CounterError::parsing_failure("Count Packet"))
+        );
+        let last = packet.len() - 1;
+        packet[last] ^= 0x01;
+        assert_eq!(
+            CountDecoder::decode(&packet),
+            Err(CounterError::parsing_failure("Count Packet"))
+        );
+        packet[last] ^= 0x01;
+        packet[0] = 0xff;
+        assert_eq!(
+            CountDecoder::decode(&packet),
+            Err(CounterError::parsing_failure("Count Packet"))
+        );
+    }
+}
@@ -0,0 +1,11 @@
+//! Interface Control Document
+//!
+//! The command set of the radiation counter is described in `icd/commands.toml`.
+//! The command definitions in this crate and the Markdown document below are
+//! both generated from that file at build time.
+#![doc = include_str!(concat!(env!("OUT_DIR"), "/ICD.md"))]
+
+pub use crate::commands::opcodes::*;
+
+/// Markdown rendering of the Interface Control Document
+pub const MARKDOWN: &str = include_str!(concat!(env!("OUT_DIR"), "/ICD.md"));
@@ -1,10 +1,22 @@
 // #![deny(missing_docs)]
 // #![deny(warnings)]
 
+mod analysis;
+mod array;
 mod commands;
+mod downlink;
+pub mod icd;
 mod objects;
+#[cfg(feature = "orbit")]
+pub mod orbit;
+mod probe;
+mod profile;
 mod radiation_counter;
+mod sample;
+mod storage;
 mod telemetry;
+#[cfg(test)]
+mod test_util;
 
 /// High level Radiation Counter API functions
 use cubeos_service::{Error};
@@ -12,7 +24,9 @@ use failure::Fail;
 
 use std::convert::From;
 
+pub use crate::analysis::*;
 pub use crate::objects::*;
+pub use crate::storage::*;
 
 /// CounterError
 ///
@@ -41,6 +55,20 @@ pub enum CounterError {
         /// Command which failed
         command: String,
     },
+    /// Error resulting from reading or writing a file
+    #[fail(display = "File error on {}: {:?}", path, kind)]
+    FileError {
+        /// Path of the file
+        path: String,
+        /// Kind of the underlying Io error
+        kind: std::io::ErrorKind,
+    },
+    /// Error resulting from propagating an orbit
+    #[fail(display = "Orbit propagation failed: {}", reason)]
+    PropagationFailure {
+        /// Reason the propagation failed
+        reason: String,
+    },
 }
 
 impl CounterError {
@@ -53,6 +81,18 @@ impl CounterError {
             source: String::from(source),
         }
     }
+
+    /// Convience function for creating an CounterError::FileError
+    ///
+    /// # Arguments
+    /// - path - Path of the file
+    /// - error - Io error raised while accessing the file
+    pub fn file_error<P: AsRef<std::path::Path>>(path: P, error: &std::io::Error) -> CounterError {
+        CounterError::FileError {
+            path: path.as_ref().display().to_string(),
+            kind: error.kind(),
+        }
+    }
 }
 
 impl From<CounterError> for Error {
@@ -63,6 +103,12 @@ impl From<CounterError> for Error {
             CounterError::I2CError(io) => Error::from(io),
             CounterError::ParsingFailure { source } => Error::Failure(source),
             CounterError::CommandFailure { command } => Error::Failure(command),
+            CounterError::FileError { path, kind } => {
+                Error::Failure(format!("File error on {}: {:?}", path, kind))
+            }
+            CounterError::PropagationFailure { reason } => {
+                Error::Failure(format!("Orbit propagation failed: {}", reason))
+            }
         }
     }
 }
@@ -88,6 +134,18 @@ impl From<Error> for CounterError {
 pub type CounterResult<T> = core::result::Result<T, CounterError>;
 
 /// Low level interface for interacting with the radiation counter
+pub use crate::array::{ArraySnapshot, BoardHealth, BoardSnapshot, BoardStatus, CounterArray};
 pub use crate::commands::last_error::ErrorCode;
+pub use crate::downlink::{
+    CompressionStats, CountDecoder, CountEncoder, BLOCK_SIZE, ESCAPE_QUOTIENT, PACKET_VERSION,
+};
+pub use crate::probe::{probe, ProbeResult};
+pub use crate::profile::CompatibilityProfile;
 pub use crate::radiation_counter::{CuavaRadiationCounter, RadiationCounter};
+pub use crate::sample::CountSampler;
+pub use crate::telemetry::housekeeping as HousekeepingTelemetry;
 pub use crate::telemetry::reset as ResetTelemetry;
+
+// Referenced by `make_telemetry!` expansions, which may live outside this crate
+#[doc(hidden)]
+pub use crate::telemetry::lib::get_adc_result;
@@ -1,9 +1,156 @@
+use crate::{CounterError, CounterResult};
 use serde::*;
 
 // #[derive(Default)]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RCHk {
     pub rc1_reading: i16,
     pub rc2_reading: i16,
     pub rc3_reading: i16,
 }
+
+/// Board housekeeping telemetry in engineering units
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BoardHk {
+    /// Supply voltage (V)
+    pub supply_voltage: f64,
+    /// Supply current (mA)
+    pub supply_current: f64,
+    /// MCU temperature (degC)
+    pub mcu_temperature: f64,
+    /// Tube high voltage monitor (V)
+    pub tube_high_voltage: f64,
+}
+
+/// Board identity reported by the Get Board Identity command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BoardInfo {
+    /// Hardware revision of the counter PCB
+    pub board_revision: u8,
+    /// Firmware major version
+    pub firmware_major: u8,
+    /// Firmware minor version
+    pub firmware_minor: u8,
+    /// Firmware patch version
+    pub firmware_patch: u8,
+    /// Board serial number
+    pub serial: u32,
+}
+
+/// Quality flags attached to a [`CountSample`]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SampleQuality(u8);
+
+impl SampleQuality {
+    /// No quality issues
+    pub const NOMINAL: SampleQuality = SampleQuality(0x00);
+    /// The reading only succeeded after retrying the request, or follows a
+    /// failed read. The counts are still valid and span the time since the
+    /// last good reading.
+    pub const RETRIED: SampleQuality = SampleQuality(0x01);
+    /// At least one counter rolled over since the previous sample. The counts
+    /// are still valid.
+    pub const WRAPPED: SampleQuality = SampleQuality(0x02);
+    /// The reading is doubtful, e.g. it holds the error value or a counter
+    /// went backwards. The sample carries no counts.
+    pub const SUSPECT: SampleQuality = SampleQuality(0x04);
+
+    /// Raw flag bits
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Whether all flags of `other` are set
+    pub fn contains(self, other: SampleQuality) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Sets all flags of `other`
+    pub fn insert(&mut self, other: SampleQuality) {
+        self.0 |= other.0;
+    }
+
+    /// Whether no flags are set
+    pub fn is_nominal(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether the counts of the sample can be used, i.e. it is not suspect
+    pub fn is_usable(self) -> bool {
+        !self.contains(SampleQuality::SUSPECT)
+    }
+}
+
+/// Timestamped radiation counter reading
+///
+/// Wraps the raw [`RCHk`] readings with timing, sequencing and quality
+/// information. `counts` holds the number of counts registered by each tube
+/// during `interval_ms`, the time since the previous sample.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CountSample {
+    /// Sequence number, incremented for every sample
+    pub sequence: u32,
+    /// Monotonic time since the sampler was started (ms)
+    pub monotonic_ms: u64,
+    /// UTC time since the Unix epoch (ms)
+    pub utc_ms: u64,
+    /// Integration interval since the previous sample (ms)
+    pub interval_ms: u32,
+    /// Raw counter readings
+    pub reading: RCHk,
+    /// Counts per tube during the integration interval
+    pub counts: [u16; 3],
+    /// Quality flags
+    pub quality: SampleQuality,
+}
+
+impl CountSample {
+    /// Integration interval in seconds
+    pub fn interval_secs(&self) -> f64 {
+        f64::from(self.interval_ms) / 1000.0
+    }
+
+    /// Sum of the counts of all tubes
+    pub fn total_counts(&self) -> u32 {
+        self.counts.iter().map(|&count| u32::from(count)).sum()
+    }
+
+    /// Serializes the sample with bincode
+    pub fn to_bytes(&self) -> CounterResult<Vec<u8>> {
+        bincode::serialize(self).map_err(|_| CounterError::parsing_failure("Count Sample"))
+    }
+
+    /// Deserializes a sample serialized with [`CountSample::to_bytes`]
+    ///
+    /// # Arguments
+    /// `data` - Serialized sample
+    pub fn from_bytes(data: &[u8]) -> CounterResult<CountSample> {
+        bincode::deserialize(data).map_err(|_| CounterError::parsing_failure("Count Sample"))
+    }
+}
+
+/// Geodetic position of the spacecraft
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GeoPosition {
+    /// Geodetic latitude, positive north (deg)
+    pub latitude: f64,
+    /// Longitude, positive east, between -180 and 180 (deg)
+    pub longitude: f64,
+    /// Altitude above the reference ellipsoid (km)
+    pub altitude: f64,
+}
+
+/// Reset counters of the board
+///
+/// All counters roll over at 255 to 0.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ResetCounters {
+    /// Brown-out resets
+    pub brown_out: u8,
+    /// Automatic software resets
+    pub automatic_software: u8,
+    /// Manual resets
+    pub manual: u8,
+    /// Communications watchdog resets
+    pub watchdog: u8,
+}
@@ -0,0 +1,238 @@
+//! Orbital Position
+//!
+//! Tags samples with the spacecraft position, propagated onboard from a
+//! two-line element set with SGP4. Everything runs locally without network
+//! access or external data files.
+//!
+//! The TEME position is rotated into the earth-fixed frame with the Greenwich
+//! mean sidereal time, ignoring polar motion and nutation, which is well
+//! within the accuracy of SGP4 itself. The McIlwain L-shell is approximated
+//! with a tilted centred dipole, which does not capture the South Atlantic
+//! Anomaly offset but orders the radiation belts adequately for mapping.
+
+mod sgp4;
+mod tle;
+
+pub use crate::orbit::sgp4::{Sgp4, StateVector};
+pub use crate::orbit::tle::Tle;
+
+use crate::objects::{CountSample, GeoPosition};
+use crate::CounterResult;
+use std::f64::consts::PI;
+
+// WGS-84 ellipsoid
+const WGS84_A: f64 = 6378.137;
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+// Reference radius of the geomagnetic field (km)
+const GEOMAGNETIC_RADIUS: f64 = 6371.2;
+
+// Geomagnetic north pole of the IGRF-13 dipole, epoch 2020 (deg)
+const DIPOLE_POLE_LATITUDE: f64 = 80.65;
+const DIPOLE_POLE_LONGITUDE: f64 = -72.68;
+
+const UNIX_EPOCH_JD: f64 = 2_440_587.5;
+
+/// Position of the spacecraft at the time of a sample
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrbitPosition {
+    /// UTC time (ms)
+    pub utc_ms: u64,
+    /// Geodetic position
+    pub position: GeoPosition,
+    /// Approximate McIlwain L-shell (earth radii)
+    pub l_shell: f64,
+}
+
+/// Greenwich mean sidereal time (rad)
+///
+/// # Arguments
+/// `jd` - Julian date (UT1)
+pub fn gmst(jd: f64) -> f64 {
+    let tut1 = (jd - 2_451_545.0) / 36_525.0;
+    let seconds = -6.2e-6 * tut1 * tut1 * tut1
+        + 0.093_104 * tut1 * tut1
+        + (876_600.0 * 3600.0 + 8_640_184.812_866) * tut1
+        + 67_310.548_41;
+    // 240 seconds of time per degree
+    (seconds * PI / 180.0 / 240.0).rem_euclid(2.0 * PI)
+}
+
+/// Converts a TEME position to earth-fixed coordinates (km)
+///
+/// # Arguments
+/// `teme` - TEME position (km)
+/// `jd` - Julian date (UT1)
+pub fn teme_to_ecef(teme: [f64; 3], jd: f64) -> [f64; 3] {
+    let (sin, cos) = gmst(jd).sin_cos();
+    [
+        cos * teme[0] + sin * teme[1],
+        -sin * teme[0] + cos * teme[1],
+        teme[2],
+    ]
+}
+
+/// Converts earth-fixed coordinates to a geodetic position
+///
+/// # Arguments
+/// `ecef` - Earth-fixed position (km)
+pub fn ecef_to_geodetic(ecef: [f64; 3]) -> GeoPosition {
+    let e2 = WGS84_F * (2.0 - WGS84_F);
+    let [x, y, z] = ecef;
+    let p = x.hypot(y);
+    let longitude = y.atan2(x);
+
+    let mut latitude = z.atan2(p * (1.0 - e2));
+    let mut altitude = 0.0;
+    for _ in 0..6 {
+        let sin = latitude.sin();
+        let n = WGS84_A / (1.0 - e2 * sin * sin).sqrt();
+        altitude = if latitude.cos().abs() > 1e-10 {
+            p / latitude.cos() - n
+        } else {
+            z.abs() - n * (1.0 - e2)
+        };
+        latitude = z.atan2(p * (1.0 - e2 * n / (n + altitude)));
+    }
+
+    GeoPosition {
+        latitude: latitude.to_degrees(),
+        longitude: longitude.to_degrees(),
+        altitude,
+    }
+}
+
+/// Approximate McIlwain L-shell of an earth-fixed position
+///
+/// Uses the field line equation of a tilted centred dipole,
+/// `L = r / cos^2(magnetic latitude)`.
+///
+/// # Arguments
+/// `ecef` - Earth-fixed position (km)
+pub fn l_shell(ecef: [f64; 3]) -> f64 {
+    let r = (ecef[0] * ecef[0] + ecef[1] * ecef[1] + ecef[2] * ecef[2]).sqrt();
+    let (pole_lat, pole_lon) = (
+        DIPOLE_POLE_LATITUDE.to_radians(),
+        DIPOLE_POLE_LONGITUDE.to_radians(),
+    );
+    let pole = [
+        pole_lat.cos() * pole_lon.cos(),
+        pole_lat.cos() * pole_lon.sin(),
+        pole_lat.sin(),
+    ];
+    let sin_magnetic_latitude = (ecef[0] * pole[0] + ecef[1] * pole[1] + ecef[2] * pole[2]) / r;
+    let cos2 = (1.0 - sin_magnetic_latitude * sin_magnetic_latitude).max(1e-12);
+    r / GEOMAGNETIC_RADIUS / cos2
+}
+
+/// Tags samples with the position propagated from a TLE
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrbitTagger {
+    tle: Tle,
+    sgp4: Sgp4,
+}
+
+impl OrbitTagger {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// `tle` - Element set of the spacecraft
+    pub fn new(tle: Tle) -> CounterResult<Self> {
+        let sgp4 = Sgp4::new(&tle)?;
+        Ok(OrbitTagger { tle, sgp4 })
+    }
+
+    /// Element set in use
+    pub fn tle(&self) -> &Tle {
+        &self.tle
+    }
+
+    /// Set TLE
+    ///
+    /// Replaces the element set, e.g. after an upload of fresh elements.
+    ///
+    /// # Arguments
+    /// `tle` - New element set
+    pub fn set_tle(&mut self, tle: Tle) -> CounterResult<()> {
+        self.sgp4 = Sgp4::new(&tle)?;
+        self.tle = tle;
+        Ok(())
+    }
+
+    /// Position at a UTC time
+    ///
+    /// # Arguments
+    /// `utc_ms` - UTC time since the Unix epoch (ms)
+    pub fn position_at(&self, utc_ms: u64) -> CounterResult<OrbitPosition> {
+        let state = self.sgp4.propagate(self.tle.minutes_since_epoch(utc_ms))?;
+        let jd = UNIX_EPOCH_JD + utc_ms as f64 / 86_400_000.0;
+        let ecef = teme_to_ecef(state.position, jd);
+        Ok(OrbitPosition {
+            utc_ms,
+            position: ecef_to_geodetic(ecef),
+            l_shell: l_shell(ecef),
+        })
+    }
+
+    /// Position at the time of a sample
+    ///
+    /// # Arguments
+    /// `sample` - Sample to tag
+    pub fn tag(&self, sample: &CountSample) -> CounterResult<OrbitPosition> {
+        self.position_at(sample.utc_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ISS: &str = "ISS (ZARYA)
+1 25544U 98067A   08264.51782528 -.00002182  00000-0 -11606-4 0  2927
+2 25544  51.6416 247.4627 0006703 130.5360 325.0288 15.72125391563537";
+
+    #[test]
+    fn test_gmst() {
+        // 1992-08-20 12:14 UT1, Vallado example 3-5: 152.578788 deg
+        let jd = 2_448_855.009_722;
+        assert!((gmst(jd).to_degrees() - 152.578_788).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_geodetic_round_trip() {
+        let position = ecef_to_geodetic([WGS84_A + 500.0, 0.0, 0.0]);
+        assert!(position.latitude.abs() < 1e-9);
+        assert!((position.altitude - 500.0).abs() < 1e-9);
+
+        let pole = ecef_to_geodetic([0.0, 0.0, 6356.752 + 100.0]);
+        assert!((pole.latitude - 90.0).abs() < 1e-6);
+        assert!((pole.altitude - 100.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_l_shell() {
+        // On the magnetic equator L is the radial distance in earth radii
+        let pole_lon = DIPOLE_POLE_LONGITUDE.to_radians() + PI;
+        let pole_lat = (90.0 - DIPOLE_POLE_LATITUDE).to_radians();
+        let r = 2.0 * GEOMAGNETIC_RADIUS;
+        let equator = [
+            r * pole_lat.cos() * pole_lon.cos(),
+            r * pole_lat.cos() * pole_lon.sin(),
+            r * pole_lat.sin(),
+        ];
+        assert!((l_shell(equator) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_tag() {
+        let tagger = OrbitTagger::new(Tle::parse(ISS).unwrap()).unwrap();
+        let epoch = tagger.tle().epoch_utc_ms() as u64;
+        for minutes in 0..200 {
+            let orbit = tagger.position_at(epoch + minutes * 60_000).unwrap();
+            let position = orbit.position;
+            assert!(position.latitude.abs() <= 52.0);
+            assert!(position.altitude > 300.0 && position.altitude < 400.0);
+            assert!(orbit.l_shell >= 1.0 && orbit.l_shell < 6.0);
+        }
+    }
+}
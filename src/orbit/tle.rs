@@ -0,0 +1,222 @@
+//! Two-Line Element Sets
+//!
+//! Parses NORAD two-line element sets, with or without the leading name line.
+//! Both lines are checked for being ASCII, their length, line number,
+//! modulo-10 checksum and matching satellite number.
+
+use crate::{CounterError, CounterResult};
+
+// Julian date of the Unix epoch
+const UNIX_EPOCH_JD: f64 = 2_440_587.5;
+const MS_PER_DAY: f64 = 86_400_000.0;
+
+/// Two-line element set
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tle {
+    /// Satellite name, if a name line was given
+    pub name: Option<String>,
+    /// NORAD catalogue number
+    pub satellite_number: u32,
+    /// Epoch as a Julian date (UTC)
+    pub epoch_jd: f64,
+    /// First derivative of the mean motion divided by two (rev / day^2)
+    pub mean_motion_dot: f64,
+    /// Second derivative of the mean motion divided by six (rev / day^3)
+    pub mean_motion_ddot: f64,
+    /// Drag term (1 / earth radii)
+    pub bstar: f64,
+    /// Inclination (deg)
+    pub inclination: f64,
+    /// Right ascension of the ascending node (deg)
+    pub raan: f64,
+    /// Eccentricity
+    pub eccentricity: f64,
+    /// Argument of perigee (deg)
+    pub arg_perigee: f64,
+    /// Mean anomaly (deg)
+    pub mean_anomaly: f64,
+    /// Mean motion (rev / day)
+    pub mean_motion: f64,
+    /// Revolution number at epoch
+    pub revolution: u32,
+}
+
+fn failure() -> CounterError {
+    CounterError::parsing_failure("TLE")
+}
+
+fn checksum(line: &str) -> CounterResult<()> {
+    let bytes = line.as_bytes();
+    let sum: u32 = bytes[..68]
+        .iter()
+        .map(|byte| match byte {
+            b'0'..=b'9' => u32::from(byte - b'0'),
+            b'-' => 1,
+            _ => 0,
+        })
+        .sum();
+    let expected = (bytes[68] as char).to_digit(10).ok_or_else(failure)?;
+    if sum % 10 == expected {
+        Ok(())
+    } else {
+        Err(failure())
+    }
+}
+
+fn field<T: std::str::FromStr>(line: &str, start: usize, end: usize) -> CounterResult<T> {
+    line.get(start..end)
+        .ok_or_else(failure)?
+        .trim()
+        .parse()
+        .map_err(|_| failure())
+}
+
+// Number with an implied leading decimal point and a power of ten exponent,
+// e.g. " 28098-4" for 0.28098e-4
+fn implied(line: &str, start: usize, end: usize) -> CounterResult<f64> {
+    let text = line.get(start..end).ok_or_else(failure)?.trim();
+    if text.len() < 2 {
+        return Err(failure());
+    }
+    let (mantissa, exponent) = text.split_at(text.len() - 2);
+    let exponent: i32 = exponent.parse().map_err(|_| failure())?;
+    let (sign, digits) = match mantissa.chars().next() {
+        Some('-') => (-1.0, &mantissa[1..]),
+        Some('+') => (1.0, &mantissa[1..]),
+        _ => (1.0, mantissa),
+    };
+    let mantissa: f64 = format!("0.{}", digits.trim())
+        .parse()
+        .map_err(|_| failure())?;
+    Ok(sign * mantissa * 10f64.powi(exponent))
+}
+
+// Julian date of 00:00 UTC on January 1st of a year
+fn year_start_jd(year: i32) -> f64 {
+    let y = f64::from(year - 1);
+    let days = 365.0 * y + (y / 4.0).floor() - (y / 100.0).floor() + (y / 400.0).floor();
+    // Days from 0001-01-01 to 1970-01-01
+    UNIX_EPOCH_JD + days - 719_162.0
+}
+
+impl Tle {
+    /// Parses a two-line element set
+    ///
+    /// # Arguments
+    /// `text` - Two element lines, optionally preceded by a name line
+    pub fn parse(text: &str) -> CounterResult<Self> {
+        let lines: Vec<&str> = text
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty())
+            .collect();
+        match lines.as_slice() {
+            [line1, line2] => Self::from_lines(None, line1, line2),
+            [name, line1, line2] => Self::from_lines(Some(name), line1, line2),
+            _ => Err(failure()),
+        }
+    }
+
+    /// Parses the two element lines
+    ///
+    /// # Arguments
+    /// `name` - Satellite name, e.g. from a name line
+    /// `line1` - First element line
+    /// `line2` - Second element line
+    pub fn from_lines(name: Option<&str>, line1: &str, line2: &str) -> CounterResult<Self> {
+        // The fields are at fixed byte columns
+        if !line1.is_ascii() || !line2.is_ascii() || line1.len() < 69 || line2.len() < 69 {
+            return Err(failure());
+        }
+        if !line1.starts_with("1 ") || !line2.starts_with("2 ") {
+            return Err(failure());
+        }
+        checksum(line1)?;
+        checksum(line2)?;
+
+        let satellite_number: u32 = field(line1, 2, 7)?;
+        if field::<u32>(line2, 2, 7)? != satellite_number {
+            return Err(failure());
+        }
+
+        let year: i32 = field(line1, 18, 20)?;
+        let year = if year < 57 { 2000 + year } else { 1900 + year };
+        let day: f64 = field(line1, 20, 32)?;
+
+        Ok(Tle {
+            name: name.map(|name| name.trim_start_matches("0 ").trim().to_string()),
+            satellite_number,
+            epoch_jd: year_start_jd(year) + day - 1.0,
+            mean_motion_dot: field(line1, 33, 43)?,
+            mean_motion_ddot: implied(line1, 44, 52)?,
+            bstar: implied(line1, 53, 61)?,
+            inclination: field(line2, 8, 16)?,
+            raan: field(line2, 17, 25)?,
+            eccentricity: format!("0.{}", line2.get(26..33).ok_or_else(failure)?)
+                .parse()
+                .map_err(|_| failure())?,
+            arg_perigee: field(line2, 34, 42)?,
+            mean_anomaly: field(line2, 43, 51)?,
+            mean_motion: field(line2, 52, 63)?,
+            revolution: field(line2, 63, 68)?,
+        })
+    }
+
+    /// Epoch as UTC time since the Unix epoch (ms)
+    pub fn epoch_utc_ms(&self) -> f64 {
+        (self.epoch_jd - UNIX_EPOCH_JD) * MS_PER_DAY
+    }
+
+    /// Minutes from the epoch to a UTC time
+    ///
+    /// # Arguments
+    /// `utc_ms` - UTC time since the Unix epoch (ms)
+    pub fn minutes_since_epoch(&self, utc_ms: u64) -> f64 {
+        (utc_ms as f64 - self.epoch_utc_ms()) / 60_000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE1: &str = "1 00005U 58002B   00179.78495062  .00000023  00000-0  28098-4 0  4753";
+    const LINE2: &str = "2 00005  34.2682 348.7242 1859667 331.7664  19.3264 10.82419157413667";
+
+    #[test]
+    fn test_parse() {
+        let tle = Tle::parse(&format!("VANGUARD 1\n{}\n{}\n", LINE1, LINE2)).unwrap();
+        assert_eq!(tle.name.as_deref(), Some("VANGUARD 1"));
+        assert_eq!(tle.satellite_number, 5);
+        assert_eq!(tle.bstar, 0.28098e-4);
+        assert_eq!(tle.eccentricity, 0.1859667);
+        assert_eq!(tle.mean_motion, 10.82419157);
+        assert_eq!(tle.revolution, 41366);
+        // 2000-06-27 18:50:19.733568 UTC
+        assert!((tle.epoch_jd - 2_451_723.284_950_62).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_parse_bad_checksum() {
+        let line2 = LINE2.replace("10.82419157413667", "10.82419157413668");
+        assert_eq!(
+            Tle::from_lines(None, LINE1, &line2),
+            Err(CounterError::parsing_failure("TLE"))
+        );
+    }
+
+    #[test]
+    fn test_parse_non_ascii() {
+        // A two byte character inside the eccentricity column
+        let line2 = LINE2.replacen("1859667", "185966\u{e9}", 1);
+        assert_eq!(
+            Tle::from_lines(None, LINE1, &line2),
+            Err(CounterError::parsing_failure("TLE"))
+        );
+        let line1 = format!("{}\u{e9}", &LINE1[..68]);
+        assert_eq!(
+            Tle::from_lines(None, &line1, LINE2),
+            Err(CounterError::parsing_failure("TLE"))
+        );
+    }
+}
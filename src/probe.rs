@@ -0,0 +1,115 @@
+//! Device Discovery
+//!
+//! Finds radiation counters on an I2C bus when it is not known which of the
+//! possible addresses is strapped. Every candidate address is sent the Get
+//! Communications Watchdog Period command, and addresses answering with a
+//! valid watchdog period are asked for their identity.
+//!
+//! The command is harmless to a radiation counter, but to any other device it
+//! is an arbitrary register write. Only the addresses a counter can be
+//! strapped to on the bus at hand are probed, never the whole address range.
+//! Boards without the identity command record an Unknown Command as their
+//! last error when probed.
+
+use crate::objects::BoardInfo;
+use crate::profile::CompatibilityProfile;
+use crate::radiation_counter::{CuavaRadiationCounter, RadiationCounter};
+use i2c_rs::Connection;
+
+// Watchdog periods accepted by the radiation counter, in minutes
+const MIN_WATCHDOG_PERIOD: u8 = 1;
+const MAX_WATCHDOG_PERIOD: u8 = 90;
+
+/// Radiation counter found on the bus
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeResult {
+    /// I2C address of the board
+    pub address: u16,
+    /// Communications watchdog period reported by the board, in minutes
+    pub watchdog_period: u8,
+    /// Board identity, if the firmware supports the identity command
+    pub board_info: Option<BoardInfo>,
+}
+
+/// Probe Addresses
+///
+/// Tries each candidate address on the bus and returns every address which
+/// responds like a CUAVA radiation counter. Each probed address takes at
+/// least one inter-command delay.
+///
+/// # Arguments
+/// `bus` - Path of the I2C bus, e.g. `/dev/i2c-1`
+/// `addresses` - Addresses a radiation counter may be strapped to. No other
+/// device may be present at these addresses.
+pub fn probe<I>(bus: &str, addresses: I) -> Vec<ProbeResult>
+where
+    I: IntoIterator<Item = u16>,
+{
+    addresses
+        .into_iter()
+        .filter_map(|address| probe_address(bus, address))
+        .collect()
+}
+
+fn probe_address(bus: &str, address: u16) -> Option<ProbeResult> {
+    let counter = RadiationCounter::with_profile(
+        Connection::from_path(bus, address),
+        CompatibilityProfile::legacy(),
+    );
+    probe_counter(address, &counter)
+}
+
+fn probe_counter(address: u16, counter: &RadiationCounter) -> Option<ProbeResult> {
+    let watchdog_period = counter.get_comms_watchdog_period().ok()?;
+    if !is_valid_watchdog_period(watchdog_period) {
+        return None;
+    }
+    Some(ProbeResult {
+        address,
+        watchdog_period,
+        board_info: counter.get_board_info().ok(),
+    })
+}
+
+fn is_valid_watchdog_period(period: u8) -> bool {
+    (MIN_WATCHDOG_PERIOD..=MAX_WATCHDOG_PERIOD).contains(&period)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::mock_counter;
+
+    #[test]
+    fn test_valid_watchdog_period() {
+        assert!(is_valid_watchdog_period(4));
+        assert!(is_valid_watchdog_period(90));
+        assert!(!is_valid_watchdog_period(0));
+        assert!(!is_valid_watchdog_period(0xFF));
+    }
+
+    #[test]
+    fn test_probe_counter() {
+        let counter = mock_counter(|opcode| match opcode {
+            0x02 => vec![0x00, 0x02, 0x01, 0x04, 0x09, 0x00, 0x00, 0x01, 0x2C],
+            _ => vec![0x00, 0x04],
+        });
+        let result = probe_counter(0x40, &counter).unwrap();
+        assert_eq!(result.watchdog_period, 4);
+        assert_eq!(result.board_info.unwrap().serial, 300);
+
+        // Legacy firmware answers the identity command with the error value
+        let counter = mock_counter(|opcode| match opcode {
+            0x02 => vec![0xFF, 0xFF],
+            _ => vec![0x00, 0x04],
+        });
+        assert_eq!(probe_counter(0x40, &counter).unwrap().board_info, None);
+
+        // Other devices
+        assert_eq!(probe_counter(0x40, &mock_counter(|_| vec![])), None);
+        assert_eq!(
+            probe_counter(0x40, &mock_counter(|_| vec![0x12, 0x00])),
+            None
+        );
+    }
+}
@@ -1,14 +1,13 @@
 use crate::commands::*;
-use crate::objects::RCHk;
-use crate::CounterResult;
+use crate::objects::{BoardHk, BoardInfo, RCHk, ResetCounters};
+use crate::profile::CompatibilityProfile;
+use crate::telemetry::housekeeping::{self, HousekeepingOpcodes, Telemetry};
+use crate::telemetry::reset;
+use crate::{CounterError, CounterResult};
 use i2c_rs::{Command, Connection};
+use std::cell::Cell;
 use std::thread;
-use std::io::Error;
-use std::time::Duration;
-
-// Observed (but undocumented) inter-command delay required is 59ms
-// Rounding up to an even 60
-const INTER_COMMAND_DELAY: Duration = Duration::from_millis(60);
+use std::time::{Duration, Instant};
 
 // Number of radiation counters
 //const NUM_COUNTERS: i32 = 3;
@@ -57,6 +56,23 @@ pub trait CuavaRadiationCounter {
     ///
     /// This command uses i2c to get the value from the Radiation Counter
     fn get_radiation_count(&mut self) -> CounterResult<RCHk>;
+
+    /// Get Board Housekeeping
+    ///
+    /// Reads all board housekeeping channels (supply voltage and current,
+    /// MCU temperature and tube high voltage) in engineering units.
+    fn get_board_housekeeping(&self) -> CounterResult<BoardHk>;
+
+    /// Get Board Identity
+    ///
+    /// Requests the board revision, firmware version and serial number.
+    fn get_board_info(&self) -> CounterResult<BoardInfo>;
+
+    /// Get Reset Counters
+    ///
+    /// Reads the brown-out, automatic software, manual and communications
+    /// watchdog reset counters.
+    fn get_reset_counters(&self) -> CounterResult<ResetCounters>;
 }
 
 /// Radiation Counter structure containing low level connection and functionality
@@ -66,6 +82,9 @@ pub struct RadiationCounter {
     rc1_reading: i16,
     rc2_reading: i16,
     rc3_reading: i16,
+    profile: CompatibilityProfile,
+    board_info: Option<BoardInfo>,
+    last_command: Cell<Option<Instant>>,
 }
 
 impl RadiationCounter {
@@ -73,17 +92,179 @@ impl RadiationCounter {
     ///
     /// Creates new instance of Radiation Counter structure.
     ///
+    /// The board is driven with the legacy profile until [`identify`] is called.
+    /// Nothing is sent to the board.
+    ///
     /// # Arguments
     /// `connection` - A [`Connection`] used as low-level connection to Radiation Counter hardware
     ///
     /// [`Connection`]: ../rust_i2c/struct.Connection.html
+    /// [`identify`]: #method.identify
     pub fn new(connection: Connection) -> Self {
+        RadiationCounter::with_profile(connection, CompatibilityProfile::legacy())
+    }
+
+    /// Constructor identifying the board
+    ///
+    /// Creates new instance of Radiation Counter structure and identifies the
+    /// board to select its compatibility profile. Boards which do not answer
+    /// the identity command are driven with the legacy profile.
+    ///
+    /// # Arguments
+    /// `connection` - A [`Connection`] used as low-level connection to Radiation Counter hardware
+    ///
+    /// [`Connection`]: ../rust_i2c/struct.Connection.html
+    pub fn identified(connection: Connection) -> Self {
+        let mut counter = RadiationCounter::new(connection);
+        let _ = counter.identify();
+        counter
+    }
+
+    /// Constructor with a fixed compatibility profile
+    ///
+    /// Creates new instance of Radiation Counter structure without
+    /// identifying the board.
+    ///
+    /// # Arguments
+    /// `connection` - A [`Connection`] used as low-level connection to Radiation Counter hardware
+    /// `profile` - Compatibility profile to drive the board with
+    ///
+    /// [`Connection`]: ../rust_i2c/struct.Connection.html
+    pub fn with_profile(connection: Connection, profile: CompatibilityProfile) -> Self {
         RadiationCounter {
-            connection: connection,
+            connection,
             rc1_reading: 0,
             rc2_reading: 0,
             rc3_reading: 0,
+            profile,
+            board_info: None,
+            last_command: Cell::new(None),
+        }
+    }
+
+    /// Identify Board
+    ///
+    /// Requests the board identity and selects the matching compatibility
+    /// profile. The current profile is kept if the board does not answer.
+    /// The identity command is sent whatever the current profile supports.
+    ///
+    /// Firmware without the identity command records an Unknown Command as
+    /// its last error, overwriting what [`get_last_error`] reports. Read the
+    /// last error first if it matters.
+    ///
+    /// [`get_last_error`]: trait.CuavaRadiationCounter.html#tymethod.get_last_error
+    pub fn identify(&mut self) -> CounterResult<BoardInfo> {
+        let info = self.get_board_info()?;
+        self.profile = CompatibilityProfile::for_board(&info);
+        self.board_info = Some(info.clone());
+        Ok(info)
+    }
+
+    /// Board identity, if the board answered the identity command
+    pub fn board_info(&self) -> Option<&BoardInfo> {
+        self.board_info.as_ref()
+    }
+
+    /// Compatibility profile currently in use
+    pub fn profile(&self) -> &CompatibilityProfile {
+        &self.profile
+    }
+
+    /// Set Compatibility Profile
+    ///
+    /// Overrides the profile selected from the board identity.
+    ///
+    /// # Arguments
+    /// `profile` - Compatibility profile to use from now on
+    pub fn set_profile(&mut self, profile: CompatibilityProfile) {
+        self.profile = profile;
+    }
+
+    /// Housekeeping opcodes currently in use
+    pub fn housekeeping_opcodes(&self) -> &HousekeepingOpcodes {
+        &self.profile.housekeeping_opcodes
+    }
+
+    /// Set Housekeeping Opcodes
+    ///
+    /// Changes the command byte and channel selectors used to request board
+    /// housekeeping telemetry, to match the firmware revision of the board.
+    /// The command byte is marked as supported in the current profile.
+    ///
+    /// # Arguments
+    /// `opcodes` - Housekeeping opcodes to use from now on
+    pub fn set_housekeeping_opcodes(&mut self, opcodes: HousekeepingOpcodes) {
+        if !self.profile.supports(opcodes.command) {
+            self.profile.supported_opcodes.push(opcodes.command);
+        }
+        self.profile.housekeeping_opcodes = opcodes;
+    }
+
+    /// Fails if the firmware does not support a command
+    fn check_supported(&self, opcode: u8) -> CounterResult<()> {
+        if self.profile.supports(opcode) {
+            Ok(())
+        } else {
+            Err(CounterError::CommandFailure {
+                command: format!("Command 0x{:02X} not supported by firmware", opcode),
+            })
+        }
+    }
+
+    /// Time until the inter-command delay since the last command has passed
+    pub fn ready_in(&self) -> Duration {
+        match self.last_command.get() {
+            Some(sent) => self
+                .profile
+                .inter_command_delay
+                .checked_sub(sent.elapsed())
+                .unwrap_or_default(),
+            None => Duration::default(),
+        }
+    }
+
+    /// Waits for whatever remains of the inter-command delay and marks the
+    /// start of a new command
+    fn wait_inter_command_delay(&self) {
+        let remaining = self.ready_in();
+        if remaining > Duration::default() {
+            thread::sleep(remaining);
         }
+        self.last_command.set(Some(Instant::now()));
+    }
+
+    /// Sends a command after the inter-command delay and reads back its reply
+    ///
+    /// Commands the firmware does not support are not sent.
+    pub(crate) fn transfer(
+        &self,
+        command: Command,
+        rx_len: usize,
+        delay: Duration,
+    ) -> CounterResult<Vec<u8>> {
+        self.check_supported(command.cmd)?;
+        self.transfer_unchecked(command, rx_len, delay)
+    }
+
+    /// Sends a command whether or not the firmware supports it
+    fn transfer_unchecked(
+        &self,
+        command: Command,
+        rx_len: usize,
+        delay: Duration,
+    ) -> CounterResult<Vec<u8>> {
+        self.wait_inter_command_delay();
+        Ok(self.connection.transfer(command, rx_len, delay)?)
+    }
+
+    /// Sends a write-only command after the inter-command delay
+    ///
+    /// Commands the firmware does not support are not sent.
+    fn write(&self, command: Command) -> CounterResult<()> {
+        self.check_supported(command.cmd)?;
+        self.wait_inter_command_delay();
+        self.connection.write(command)?;
+        Ok(())
     }
 }
 
@@ -95,13 +276,8 @@ impl CuavaRadiationCounter for RadiationCounter {
     /// If an error has been generated after attempting to execute a user's command,
     /// this command can be used to retrieve details about the error.
     fn get_last_error(&self) -> CounterResult<last_error::ErrorCode> {
-        thread::sleep(INTER_COMMAND_DELAY);
-        let (command, rx_len) = last_error::command();
-        last_error::parse(
-            &self
-                .connection
-                .transfer(command, rx_len, Duration::from_millis(3))?,
-        )
+        let (command, rx_len, delay) = last_error::command();
+        last_error::parse(&self.transfer(command, rx_len, delay)?)
     }
 
     /// Manual Reset
@@ -109,9 +285,7 @@ impl CuavaRadiationCounter for RadiationCounter {
     /// If required the user can reset the radiation counter.
     /// This will increment the Manual Reset Counter.
     fn manual_reset(&self) -> CounterResult<()> {
-        thread::sleep(INTER_COMMAND_DELAY);
-        self.connection.write(manual_reset::command())?;
-        Ok(())
+        self.write(manual_reset::command())
     }
 
     /// Reset Communications Watchdog
@@ -120,9 +294,7 @@ impl CuavaRadiationCounter for RadiationCounter {
     /// does not require any telemetry from the board, this command can be sent
     /// to reset the communications watchdog.
     fn reset_comms_watchdog(&self) -> CounterResult<()> {
-        thread::sleep(INTER_COMMAND_DELAY);
-        self.connection.write(reset_comms_watchdog::command())?;
-        Ok(())
+        self.write(reset_comms_watchdog::command())
     }
 
     /// Set Communications Watchdog Period
@@ -137,10 +309,7 @@ impl CuavaRadiationCounter for RadiationCounter {
     /// # Arguments
     /// `period` - Watchdog period to set in minutes
     fn set_comms_watchdog_period(&self, period: u8) -> CounterResult<()> {
-        thread::sleep(INTER_COMMAND_DELAY);
-        self.connection
-            .write(set_comms_watchdog_period::command(period))?;
-        Ok(())
+        self.write(set_comms_watchdog_period::command(period))
     }
 
     /// Get Communications Watchdog Period
@@ -148,49 +317,63 @@ impl CuavaRadiationCounter for RadiationCounter {
     /// This command provides the user with the current communications watchdog
     /// timeout that has been set. The returned value is indicated in minutes.
     fn get_comms_watchdog_period(&self) -> CounterResult<u8> {
-        thread::sleep(INTER_COMMAND_DELAY);
-        let (command, rx_len) = get_comms_watchdog_period::command();
-        get_comms_watchdog_period::parse(&self.connection.transfer(
-            command,
-            rx_len,
-            Duration::from_millis(2),
-        )?)
+        let (command, rx_len, delay) = get_comms_watchdog_period::command();
+        get_comms_watchdog_period::parse(&self.transfer(command, rx_len, delay)?)
     }
 
     /// Get Radiation Counter Value
     ///
     /// This command uses i2c to get the counter values from the Radiation Counter
     fn get_radiation_count(&mut self) -> CounterResult<RCHk> {
-        let count_request = Command {
-            cmd: 0x01,
-            data: vec![],
-        };
+        let (command, rx_len, delay) = get_radiation_count::command();
+        let count = get_radiation_count::parse(&self.transfer(command, rx_len, delay)?)?;
+        self.rc1_reading = count.rc1_reading;
+        self.rc2_reading = count.rc2_reading;
+        self.rc3_reading = count.rc3_reading;
+        //self.cur_sum += reading1 as i32 + reading2 as i32 + reading3 as i32;
+        // self.cur_sum += self.rc1_reading+ self.rc2_reading + self.rc3_reading;
+        Ok(count)
+    }
 
-        let count_result: Result<Vec<u8>, Error> =
-            self.connection
-                .transfer(count_request, 6, Duration::from_millis(3));
-        match count_result {
-            Ok(count) => {
-                let reading1 = (count[0] as i16) << 8 | (count[1] as i16);
-                let reading2 = (count[2] as i16) << 8 | (count[3] as i16);
-                let reading3 = (count[4] as i16) << 8 | (count[5] as i16);
-                // let reading1 = count[0] as u16;
-                // let reading2 = count[1] as u16;
-                // let reading3 = count[2] as u16;
-                self.rc1_reading = reading1;
-                self.rc2_reading = reading2;
-                self.rc3_reading = reading3;
-                //self.cur_sum += reading1 as i32 + reading2 as i32 + reading3 as i32;
-                // self.cur_sum += self.rc1_reading+ self.rc2_reading + self.rc3_reading;
-                let data = RCHk {
-                    rc1_reading: self.rc1_reading,
-                    rc2_reading: self.rc2_reading,
-                    rc3_reading: self.rc3_reading,
-                };
-                Ok(data)
-            }
-            Err(e) => Err(e.into()),
-        }
+    /// Get Board Housekeeping
+    ///
+    /// Reads all board housekeeping channels (supply voltage and current,
+    /// MCU temperature and tube high voltage) in engineering units.
+    fn get_board_housekeeping(&self) -> CounterResult<BoardHk> {
+        Ok(BoardHk {
+            supply_voltage: self.get_telemetry(housekeeping::Type::SupplyVoltage)?,
+            supply_current: self.get_telemetry(housekeeping::Type::SupplyCurrent)?,
+            mcu_temperature: self.get_telemetry(housekeeping::Type::McuTemperature)?,
+            tube_high_voltage: self.get_telemetry(housekeeping::Type::TubeHighVoltage)?,
+        })
+    }
+
+    /// Get Board Identity
+    ///
+    /// Requests the board revision, firmware version and serial number.
+    ///
+    /// Sent whatever the current profile supports, as the reply is what tells
+    /// whether the firmware knows the command.
+    fn get_board_info(&self) -> CounterResult<BoardInfo> {
+        let (command, rx_len, delay) = get_board_info::command();
+        get_board_info::parse(&self.transfer_unchecked(command, rx_len, delay)?)
+    }
+
+    /// Get Reset Counters
+    ///
+    /// Reads the brown-out, automatic software, manual and communications
+    /// watchdog reset counters.
+    fn get_reset_counters(&self) -> CounterResult<ResetCounters> {
+        let read = |reset_type| -> CounterResult<u8> {
+            let (command, rx_len, delay) = reset::command(reset_type);
+            reset::parse(&self.transfer(command, rx_len, delay)?)
+        };
+        Ok(ResetCounters {
+            brown_out: read(reset::Type::BrownOut)?,
+            automatic_software: read(reset::Type::AutomaticSoftware)?,
+            manual: read(reset::Type::Manual)?,
+            watchdog: read(reset::Type::Watchdog)?,
+        })
     }
 
     // fn swap_30s_block(&mut self, new_timestamp: i32) {
@@ -212,3 +395,88 @@ impl CuavaRadiationCounter for RadiationCounter {
     //     Ok(data)
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::opcodes;
+    use crate::test_util::{mock_counter, MockStream};
+    use crate::CounterError;
+
+    #[test]
+    fn test_new_without_io() {
+        let counter = RadiationCounter::new(Connection::new(Box::new(MockStream {
+            reply: |_| unreachable!("no command expected"),
+        })));
+        assert_eq!(counter.profile(), &CompatibilityProfile::legacy());
+        assert!(counter.board_info().is_none());
+    }
+
+    #[test]
+    fn test_identified() {
+        let counter = RadiationCounter::identified(Connection::new(Box::new(MockStream {
+            reply: |_| vec![0x00, 0x02, 0x01, 0x04, 0x09, 0x00, 0x00, 0x01, 0x2C],
+        })));
+        assert_eq!(counter.profile().name, "fw1");
+        assert_eq!(counter.board_info().unwrap().serial, 300);
+
+        let counter = RadiationCounter::identified(Connection::new(Box::new(MockStream {
+            reply: |_| vec![0xFF, 0xFF],
+        })));
+        assert_eq!(counter.profile(), &CompatibilityProfile::legacy());
+    }
+
+    #[test]
+    fn test_identify() {
+        let mut counter =
+            mock_counter(|_| vec![0x00, 0x02, 0x01, 0x04, 0x09, 0x00, 0x00, 0x01, 0x2C]);
+        assert_eq!(counter.identify().unwrap().firmware_major, 1);
+        assert!(counter.profile().supports(opcodes::GET_BOARD_INFO));
+
+        // Legacy firmware answers with the error value
+        let mut counter = mock_counter(|_| vec![0xFF, 0xFF]);
+        assert_eq!(
+            counter.identify(),
+            Err(CounterError::parsing_failure("Board Info"))
+        );
+        assert!(!counter.profile().supports(opcodes::GET_BOARD_INFO));
+        assert!(counter.board_info().is_none());
+    }
+
+    #[test]
+    fn test_get_board_housekeeping() {
+        let mut counter = mock_counter(|opcode| match opcode {
+            0x45 => vec![0x00, 0x0A],
+            _ => unreachable!("unsupported command sent"),
+        });
+        assert_eq!(
+            counter.get_board_housekeeping(),
+            Err(CounterError::CommandFailure {
+                command: "Command 0x40 not supported by firmware".to_string(),
+            })
+        );
+
+        counter.set_housekeeping_opcodes(HousekeepingOpcodes {
+            command: 0x45,
+            ..Default::default()
+        });
+        assert_eq!(
+            counter.get_board_housekeeping().unwrap().supply_current,
+            5.0
+        );
+    }
+
+    #[test]
+    fn test_get_reset_counters() {
+        let counter = mock_counter(|opcode| vec![0x00, opcode - opcodes::RESET_BROWN_OUT + 1]);
+        assert_eq!(
+            counter.get_reset_counters(),
+            Ok(ResetCounters {
+                brown_out: 1,
+                automatic_software: 2,
+                manual: 3,
+                watchdog: 4,
+            })
+        );
+    }
+}
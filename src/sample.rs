@@ -0,0 +1,300 @@
+//! Count Sampling
+//!
+//! Turns raw counter readings into timestamped [`CountSample`]s. The counters
+//! on the board are free running 16-bit registers, so the counts during an
+//! integration interval are the difference to the previous reading.
+//!
+//! The board answers a failed command with the 0xFFFF error value, and an I2C
+//! read past the end of a reply returns 0xFF, so a failed read returns 0xFFFF
+//! for all three counters. A single counter at 0xFFFF is a valid value of a
+//! free running register, but all three at once are taken as the error reply,
+//! at the cost of one interval should the counters really read that.
+//!
+//! A failed read leaves the previous reading as the baseline, so the next
+//! sample counts over the longer interval since the last good reading and is
+//! marked retried.
+//!
+//! A counter stepping forward by half its range or more within one interval is
+//! taken as going backwards, e.g. after a board reset. The sample then carries
+//! no counts and the following samples count from the new reading.
+
+use crate::objects::{CountSample, RCHk, SampleQuality};
+use crate::radiation_counter::CuavaRadiationCounter;
+use crate::CounterResult;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Value returned by the board for a failed command
+const ERROR_VALUE: i16 = -1;
+
+// Smallest difference between two readings taken as the counter going backwards
+const BACKWARDS_STEP: u16 = 0x8000;
+
+// Polling period used unless another one is set
+const DEFAULT_PERIOD: Duration = Duration::from_secs(10);
+
+/// Builds timestamped samples from consecutive counter readings
+pub struct CountSampler {
+    start: Instant,
+    sequence: u32,
+    previous: Option<(RCHk, u64)>,
+    gap: bool,
+    period: Duration,
+}
+
+impl Default for CountSampler {
+    fn default() -> Self {
+        CountSampler {
+            start: Instant::now(),
+            sequence: 0,
+            previous: None,
+            gap: false,
+            period: DEFAULT_PERIOD,
+        }
+    }
+}
+
+impl CountSampler {
+    /// Constructor
+    ///
+    /// The monotonic timestamps of the samples count from this moment.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Polling period
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Set Polling Period
+    ///
+    /// # Arguments
+    /// `period` - Time between two reads
+    pub fn set_period(&mut self, period: Duration) {
+        self.period = period;
+    }
+
+    /// Time left until the next read is due
+    pub fn next_read_in(&self) -> Duration {
+        match &self.previous {
+            Some((_, previous_ms)) => {
+                let due = Duration::from_millis(*previous_ms) + self.period;
+                due.saturating_sub(self.start.elapsed())
+            }
+            None => Duration::from_secs(0),
+        }
+    }
+
+    /// Read Sample
+    ///
+    /// Reads the counters, retrying once on failure, and returns the sample.
+    /// A failed read marks the next sample as retried.
+    ///
+    /// # Arguments
+    /// `counter` - Radiation counter to read
+    pub fn read<C: CuavaRadiationCounter>(
+        &mut self,
+        counter: &mut C,
+    ) -> CounterResult<CountSample> {
+        let (reading, retried) = match counter.get_radiation_count() {
+            Ok(reading) => (reading, false),
+            Err(_) => match counter.get_radiation_count() {
+                Ok(reading) => (reading, true),
+                Err(error) => {
+                    self.gap = true;
+                    return Err(error);
+                }
+            },
+        };
+        let utc_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis() as u64)
+            .unwrap_or_default();
+        Ok(self.record(reading, self.start.elapsed(), utc_ms, retried))
+    }
+
+    /// Record Reading
+    ///
+    /// Builds the sample for a reading taken at the given time. Error readings
+    /// and counters going backwards give suspect samples without counts. The
+    /// sample after a failed read or an error reading counts from the last
+    /// good reading.
+    ///
+    /// # Arguments
+    /// `reading` - Raw counter readings
+    /// `monotonic` - Time since the sampler was started
+    /// `utc_ms` - UTC time since the Unix epoch (ms)
+    /// `retried` - Whether the reading needed a retry
+    pub fn record(
+        &mut self,
+        reading: RCHk,
+        monotonic: Duration,
+        utc_ms: u64,
+        retried: bool,
+    ) -> CountSample {
+        let monotonic_ms = monotonic.as_millis() as u64;
+        let mut quality = SampleQuality::NOMINAL;
+        if retried {
+            quality.insert(SampleQuality::RETRIED);
+        }
+        let raw = [
+            reading.rc1_reading,
+            reading.rc2_reading,
+            reading.rc3_reading,
+        ];
+        let error = raw.iter().all(|value| *value == ERROR_VALUE);
+
+        let (mut counts, mut interval_ms) = ([0u16; 3], 0);
+        if error {
+            quality.insert(SampleQuality::SUSPECT);
+        } else if let Some((previous, previous_ms)) = &self.previous {
+            // Counting from the last good reading after a failed read
+            if self.gap {
+                quality.insert(SampleQuality::RETRIED);
+            }
+            let previous = [
+                previous.rc1_reading,
+                previous.rc2_reading,
+                previous.rc3_reading,
+            ];
+            for (count, (now, before)) in counts.iter_mut().zip(raw.iter().zip(&previous)) {
+                let (now, before) = (*now as u16, *before as u16);
+                *count = now.wrapping_sub(before);
+                if *count >= BACKWARDS_STEP {
+                    quality.insert(SampleQuality::SUSPECT);
+                } else if now < before {
+                    quality.insert(SampleQuality::WRAPPED);
+                }
+            }
+            // Suspect samples carry no counts
+            if quality.contains(SampleQuality::SUSPECT) {
+                counts = [0; 3];
+            } else {
+                interval_ms = monotonic_ms.saturating_sub(*previous_ms) as u32;
+            }
+        }
+
+        let sample = CountSample {
+            sequence: self.sequence,
+            monotonic_ms,
+            utc_ms,
+            interval_ms,
+            reading: reading.clone(),
+            counts,
+            quality,
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+        // Error readings leave the baseline for the next sample untouched
+        if error {
+            self.gap = true;
+        } else {
+            self.previous = Some((reading, monotonic_ms));
+            self.gap = false;
+        }
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(rc1: i16, rc2: i16, rc3: i16) -> RCHk {
+        RCHk {
+            rc1_reading: rc1,
+            rc2_reading: rc2,
+            rc3_reading: rc3,
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let mut sampler = CountSampler::new();
+        let first = sampler.record(reading(10, 20, 30), Duration::from_secs(1), 1000, false);
+        assert_eq!(first.sequence, 0);
+        assert_eq!(first.counts, [0, 0, 0]);
+        assert_eq!(first.interval_ms, 0);
+
+        let second = sampler.record(reading(15, 20, 42), Duration::from_secs(3), 3000, true);
+        assert_eq!(second.sequence, 1);
+        assert_eq!(second.counts, [5, 0, 12]);
+        assert_eq!(second.interval_ms, 2000);
+        assert_eq!(second.total_counts(), 17);
+        assert!(second.quality.contains(SampleQuality::RETRIED));
+        assert!(!second.quality.contains(SampleQuality::WRAPPED));
+    }
+
+    #[test]
+    fn test_record_wrapped() {
+        let mut sampler = CountSampler::new();
+        sampler.record(reading(-3, 0, 0), Duration::from_secs(1), 1000, false);
+        let sample = sampler.record(reading(2, 0, 0), Duration::from_secs(2), 2000, false);
+        assert_eq!(sample.counts, [5, 0, 0]);
+        assert!(sample.quality.contains(SampleQuality::WRAPPED));
+    }
+
+    #[test]
+    fn test_record_error_value() {
+        let mut sampler = CountSampler::new();
+        sampler.record(reading(10, 20, 30), Duration::from_secs(1), 1000, false);
+        let sample = sampler.record(reading(-1, -1, -1), Duration::from_secs(2), 2000, false);
+        assert!(sample.quality.contains(SampleQuality::SUSPECT));
+        assert_eq!(sample.counts, [0, 0, 0]);
+        assert_eq!(sample.interval_ms, 0);
+
+        // The error reading does not replace the baseline
+        let sample = sampler.record(reading(15, 21, 32), Duration::from_secs(3), 3000, false);
+        assert_eq!(sample.quality, SampleQuality::RETRIED);
+        assert_eq!(sample.counts, [5, 1, 2]);
+        assert_eq!(sample.interval_ms, 2000);
+        let sample = sampler.record(reading(16, 21, 32), Duration::from_secs(4), 4000, false);
+        assert!(sample.quality.is_nominal());
+
+        // A single counter at 0xFFFF is a valid reading
+        let mut sampler = CountSampler::new();
+        sampler.record(reading(-6, 0, 0), Duration::from_secs(1), 1000, false);
+        let sample = sampler.record(reading(-1, 4, 0), Duration::from_secs(2), 2000, false);
+        assert!(sample.quality.is_nominal());
+        assert_eq!(sample.counts, [5, 4, 0]);
+    }
+
+    #[test]
+    fn test_record_after_failed_read() {
+        let mut sampler = CountSampler::new();
+        sampler.record(reading(10, 20, 30), Duration::from_secs(1), 1000, false);
+        // Both attempts of the read at 2 s failed
+        sampler.gap = true;
+        let sample = sampler.record(reading(25, 24, 30), Duration::from_secs(3), 3000, false);
+        assert_eq!(sample.quality, SampleQuality::RETRIED);
+        assert!(sample.quality.is_usable());
+        assert_eq!(sample.counts, [15, 4, 0]);
+        assert_eq!(sample.interval_ms, 2000);
+    }
+
+    #[test]
+    fn test_record_backwards() {
+        let mut sampler = CountSampler::new();
+        sampler.record(reading(30_000, 20, 30), Duration::from_secs(1), 1000, false);
+        // Board reset
+        let sample = sampler.record(reading(3, 1, 0), Duration::from_secs(2), 2000, false);
+        assert!(sample.quality.contains(SampleQuality::SUSPECT));
+        assert!(!sample.quality.contains(SampleQuality::WRAPPED));
+        assert_eq!(sample.counts, [0, 0, 0]);
+        assert_eq!(sample.interval_ms, 0);
+
+        // Counting restarts from the reading after the reset
+        let sample = sampler.record(reading(8, 3, 1), Duration::from_secs(3), 3000, false);
+        assert!(sample.quality.is_nominal());
+        assert_eq!(sample.counts, [5, 2, 1]);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut sampler = CountSampler::new();
+        sampler.record(reading(1, 2, 3), Duration::from_secs(1), 1000, false);
+        let sample = sampler.record(reading(4, 5, 6), Duration::from_secs(2), 2000, false);
+        let bytes = sample.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 37);
+        assert_eq!(CountSample::from_bytes(&bytes), Ok(sample));
+    }
+}
@@ -0,0 +1,497 @@
+//! Sample Archive
+//!
+//! Keeps the radiation history at decreasing resolution. Samples go into the
+//! [`SampleLog`], and are aggregated into levels of increasing window length,
+//! by default 1 minute, 10 minutes and 1 hour. Each level aggregates the
+//! completed windows of the level below and is stored in a ring file of its
+//! own size, so old data survives at coarse resolution after the finer
+//! levels have overwritten it.
+//!
+//! The window currently being aggregated on each level is only held in
+//! memory. On start-up it is rebuilt from the level below, so a restart does
+//! not lose any data.
+//!
+//! Suspect samples carry no counts and are only logged. A sample whose window
+//! was already stored, e.g. after the clock was set backwards, is logged but
+//! cannot be aggregated any more. Such samples are counted, see
+//! [`SampleArchive::dropped`].
+
+use crate::objects::CountSample;
+use crate::storage::ring::RingFile;
+use crate::storage::sample_log::{SampleLog, SampleLogConfig};
+use crate::{CounterError, CounterResult};
+use serde::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Schema version of the archive records written by this version
+pub const ARCHIVE_SCHEMA_VERSION: u16 = 1;
+
+// Archive records need far less than the default slot of the sample log
+const ARCHIVE_SLOT_SIZE: u32 = 96;
+
+/// Aggregated samples of one window
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveRecord {
+    /// Start of the window, UTC (ms)
+    pub start_ms: u64,
+    /// Number of samples
+    pub samples: u32,
+    /// Total integration time (ms)
+    pub live_ms: u64,
+    /// Counts per tube
+    pub counts: [u64; 3],
+    /// Lowest sample count rate per tube (cps)
+    pub min: [f32; 3],
+    /// Highest sample count rate per tube (cps)
+    pub max: [f32; 3],
+}
+
+impl ArchiveRecord {
+    /// Record of a single sample
+    ///
+    /// # Arguments
+    /// `sample` - Sample to aggregate
+    pub fn from_sample(sample: &CountSample) -> Self {
+        let mut rates = [0.0; 3];
+        if sample.interval_ms > 0 {
+            for (rate, counts) in rates.iter_mut().zip(sample.counts.iter()) {
+                *rate = (f64::from(*counts) / sample.interval_secs()) as f32;
+            }
+        }
+        ArchiveRecord {
+            start_ms: sample.utc_ms,
+            samples: 1,
+            live_ms: u64::from(sample.interval_ms),
+            counts: [
+                u64::from(sample.counts[0]),
+                u64::from(sample.counts[1]),
+                u64::from(sample.counts[2]),
+            ],
+            min: rates,
+            max: rates,
+        }
+    }
+
+    /// Mean count rate of a tube (cps)
+    ///
+    /// # Arguments
+    /// `tube` - Tube index
+    pub fn mean(&self, tube: usize) -> f64 {
+        if self.live_ms == 0 {
+            0.0
+        } else {
+            self.counts[tube] as f64 * 1000.0 / self.live_ms as f64
+        }
+    }
+
+    fn merge(&mut self, other: &ArchiveRecord) {
+        self.samples += other.samples;
+        self.live_ms += other.live_ms;
+        for tube in 0..3 {
+            self.counts[tube] += other.counts[tube];
+            self.min[tube] = self.min[tube].min(other.min[tube]);
+            self.max[tube] = self.max[tube].max(other.max[tube]);
+        }
+    }
+}
+
+/// Configuration of one archive level
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveLevelConfig {
+    /// Window length (ms)
+    pub resolution_ms: u64,
+    /// Maximum size of the level file (bytes)
+    pub max_size: u64,
+}
+
+/// Archive configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveConfig {
+    /// Directory holding the archive files
+    pub directory: PathBuf,
+    /// Maximum size of the sample log (bytes)
+    pub log_size: u64,
+    /// Levels, finest first. Each window length must be a multiple of the
+    /// one below.
+    pub levels: Vec<ArchiveLevelConfig>,
+}
+
+impl ArchiveConfig {
+    /// Constructor
+    ///
+    /// Uses 1 minute, 10 minute and 1 hour levels. With 4 MiB for the
+    /// samples and 1 MiB per level this keeps about 9 hours of 1 s samples,
+    /// a week of minutes, 75 days of 10 minutes and 15 months of hours.
+    ///
+    /// # Arguments
+    /// `directory` - Directory holding the archive files
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        let level = |resolution_ms| ArchiveLevelConfig {
+            resolution_ms,
+            max_size: 1024 * 1024,
+        };
+        ArchiveConfig {
+            directory: directory.as_ref().to_path_buf(),
+            log_size: 4 * 1024 * 1024,
+            levels: vec![level(60_000), level(600_000), level(3_600_000)],
+        }
+    }
+}
+
+struct Level {
+    resolution_ms: u64,
+    ring: RingFile,
+    pending: Option<ArchiveRecord>,
+}
+
+impl Level {
+    fn read(&mut self, slot: usize) -> CounterResult<Option<ArchiveRecord>> {
+        Ok(self.ring.read(slot)?.and_then(|record| {
+            if record.schema_version != ARCHIVE_SCHEMA_VERSION {
+                return None;
+            }
+            bincode::deserialize(&record.payload).ok()
+        }))
+    }
+
+    fn records(&mut self, start_ms: u64, end_ms: u64) -> CounterResult<Vec<ArchiveRecord>> {
+        let mut records = Vec::new();
+        for slot in self.ring.range(start_ms, end_ms) {
+            if let Some(record) = self.read(slot)? {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Sample log with automatically maintained aggregate levels
+pub struct SampleArchive {
+    log: SampleLog,
+    levels: Vec<Level>,
+    dropped: u64,
+}
+
+impl SampleArchive {
+    /// Open
+    ///
+    /// Opens or creates the archive files and rebuilds the windows being
+    /// aggregated from the levels below.
+    ///
+    /// # Arguments
+    /// `config` - Archive configuration
+    pub fn open(config: &ArchiveConfig) -> CounterResult<Self> {
+        let mut previous = 1;
+        for level in config.levels.iter() {
+            if level.resolution_ms <= previous || level.resolution_ms % previous != 0 {
+                return Err(CounterError::GenericError);
+            }
+            previous = level.resolution_ms;
+        }
+        fs::create_dir_all(&config.directory)
+            .map_err(|error| CounterError::file_error(&config.directory, &error))?;
+
+        let log = SampleLog::open(&SampleLogConfig::new(
+            config.directory.join("samples.log"),
+            config.log_size,
+        ))?;
+        let mut levels = Vec::new();
+        for level in config.levels.iter() {
+            let path = config
+                .directory
+                .join(format!("level-{}ms.log", level.resolution_ms));
+            levels.push(Level {
+                resolution_ms: level.resolution_ms,
+                ring: RingFile::open("Archive Level", &path, level.max_size, ARCHIVE_SLOT_SIZE)?,
+                pending: None,
+            });
+        }
+
+        let mut archive = SampleArchive {
+            log,
+            levels,
+            dropped: 0,
+        };
+        for level in 0..archive.levels.len() {
+            archive.recover(level)?;
+        }
+        Ok(archive)
+    }
+
+    // Aggregates what the level below holds after the newest window stored
+    // on a level. Completed windows are stored, but not passed on, as the
+    // level above recovers from them in turn.
+    fn recover(&mut self, level: usize) -> CounterResult<()> {
+        let resolution_ms = self.levels[level].resolution_ms;
+        let next_ms = match self.levels[level].ring.index().back() {
+            Some((_, entry)) => entry.utc_ms + resolution_ms,
+            None => 0,
+        };
+
+        let records = if level == 0 {
+            let mut records = Vec::new();
+            for slot in self.log.range(next_ms, u64::MAX) {
+                match self.log.read_slot(slot)? {
+                    Some(record) if record.sample.quality.is_usable() => {
+                        records.push(ArchiveRecord::from_sample(&record.sample))
+                    }
+                    _ => (),
+                }
+            }
+            records
+        } else {
+            self.levels[level - 1].records(next_ms, u64::MAX)?
+        };
+        for record in records.iter() {
+            if !self.add(level, record, false)? {
+                self.dropped += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // Adds a record to the window of a level, storing the window once a
+    // record of a later window arrives. Returns false for a record of a
+    // window which was already stored.
+    fn add(&mut self, level: usize, record: &ArchiveRecord, cascade: bool) -> CounterResult<bool> {
+        let resolution_ms = self.levels[level].resolution_ms;
+        let window_ms = record.start_ms - record.start_ms % resolution_ms;
+
+        let completed = match self.levels[level].pending.as_mut() {
+            Some(pending) if pending.start_ms == window_ms => {
+                pending.merge(record);
+                return Ok(true);
+            }
+            Some(pending) if window_ms < pending.start_ms => return Ok(false),
+            _ => self.levels[level].pending.replace(ArchiveRecord {
+                start_ms: window_ms,
+                ..record.clone()
+            }),
+        };
+
+        if let Some(completed) = completed {
+            let payload = bincode::serialize(&completed)
+                .map_err(|_| CounterError::parsing_failure("Archive Record"))?;
+            self.levels[level]
+                .ring
+                .append(completed.start_ms, ARCHIVE_SCHEMA_VERSION, &payload)?;
+            if cascade && level + 1 < self.levels.len() {
+                self.add(level + 1, &completed, true)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Append
+    ///
+    /// Logs a sample and adds it to the aggregate levels unless it is
+    /// suspect. Returns the sequence number of the sample in the log.
+    ///
+    /// # Arguments
+    /// `sample` - Sample to archive
+    pub fn append(&mut self, sample: &CountSample) -> CounterResult<u64> {
+        let sequence = self.log.append(sample)?;
+        if !self.levels.is_empty()
+            && sample.quality.is_usable()
+            && !self.add(0, &ArchiveRecord::from_sample(sample), true)?
+        {
+            self.dropped += 1;
+        }
+        Ok(sequence)
+    }
+
+    /// Number of samples logged since the archive was opened which are
+    /// missing from the aggregate levels, as their window was already stored
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Sample log holding the full resolution samples
+    pub fn log(&mut self) -> &mut SampleLog {
+        &mut self.log
+    }
+
+    /// Window lengths of the levels, finest first (ms)
+    pub fn resolutions(&self) -> Vec<u64> {
+        self.levels
+            .iter()
+            .map(|level| level.resolution_ms)
+            .collect()
+    }
+
+    /// Records
+    ///
+    /// Returns the windows of a level starting from `start_ms` up to, but
+    /// excluding, `end_ms`, including the window still being aggregated.
+    ///
+    /// # Arguments
+    /// `level` - Level index, finest first
+    /// `start_ms` - Start of the range, UTC (ms)
+    /// `end_ms` - End of the range, UTC (ms)
+    pub fn records(
+        &mut self,
+        level: usize,
+        start_ms: u64,
+        end_ms: u64,
+    ) -> CounterResult<Vec<ArchiveRecord>> {
+        let level = self
+            .levels
+            .get_mut(level)
+            .ok_or(CounterError::GenericError)?;
+        let mut records = level.records(start_ms, end_ms)?;
+        if let Some(pending) = &level.pending {
+            if pending.start_ms >= start_ms && pending.start_ms < end_ms {
+                records.push(pending.clone());
+            }
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SampleQuality;
+    use crate::test_util::{count_sample, temp_path};
+
+    fn sample(second: u64) -> CountSample {
+        let counts = [(second % 60) as u16, 1, 0];
+        count_sample(second as u32, second * 1000, second * 1000, 1000, counts)
+    }
+
+    fn config(name: &str, level_slots: u64) -> ArchiveConfig {
+        let directory = temp_path(&format!("archive-{}", name));
+        let level = |resolution_ms| ArchiveLevelConfig {
+            resolution_ms,
+            max_size: 20 + level_slots * u64::from(ARCHIVE_SLOT_SIZE),
+        };
+        ArchiveConfig {
+            directory,
+            log_size: 20 + 4000 * 128,
+            levels: vec![level(60_000), level(600_000)],
+        }
+    }
+
+    #[test]
+    fn test_levels() {
+        let config = config("levels", 100);
+        let mut archive = SampleArchive::open(&config).unwrap();
+        assert_eq!(archive.resolutions(), vec![60_000, 600_000]);
+        for second in 0..1500 {
+            archive.append(&sample(second)).unwrap();
+        }
+
+        let minutes = archive.records(0, 0, u64::MAX).unwrap();
+        assert_eq!(minutes.len(), 25);
+        assert_eq!(minutes[1].start_ms, 60_000);
+        assert_eq!(minutes[1].samples, 60);
+        assert_eq!(minutes[1].counts, [1770, 60, 0]);
+        assert_eq!(minutes[1].min[0], 0.0);
+        assert_eq!(minutes[1].max[0], 59.0);
+        assert_eq!(minutes[1].mean(0), 29.5);
+
+        let tens = archive.records(1, 0, u64::MAX).unwrap();
+        assert_eq!(tens.len(), 3);
+        assert_eq!(tens[0].samples, 600);
+        assert_eq!(tens[0].counts[0], 17_700);
+        assert_eq!(tens[2].start_ms, 1_200_000);
+        assert_eq!(tens[2].samples, 240);
+
+        assert_eq!(archive.records(1, 600_000, 1_200_000).unwrap().len(), 1);
+        assert!(archive.records(2, 0, u64::MAX).is_err());
+        let _ = fs::remove_dir_all(&config.directory);
+    }
+
+    #[test]
+    fn test_retention() {
+        let config = config("retention", 4);
+        let mut archive = SampleArchive::open(&config).unwrap();
+        for second in 0..3600 {
+            archive.append(&sample(second)).unwrap();
+        }
+
+        // Minutes are overwritten, the ten minute windows are kept
+        let minutes = archive.records(0, 0, u64::MAX).unwrap();
+        assert_eq!(minutes.len(), 5);
+        assert_eq!(minutes[0].start_ms, 55 * 60_000);
+        let tens = archive.records(1, 0, u64::MAX).unwrap();
+        assert_eq!(tens.len(), 5);
+        assert_eq!(tens[0].start_ms, 600_000);
+        let _ = fs::remove_dir_all(&config.directory);
+    }
+
+    #[test]
+    fn test_recover_pending_windows() {
+        let config = config("recover", 100);
+        let mut archive = SampleArchive::open(&config).unwrap();
+        for second in 0..1530 {
+            archive.append(&sample(second)).unwrap();
+        }
+        let minutes = archive.records(0, 0, u64::MAX).unwrap();
+        let tens = archive.records(1, 0, u64::MAX).unwrap();
+        drop(archive);
+
+        let mut archive = SampleArchive::open(&config).unwrap();
+        assert_eq!(archive.records(0, 0, u64::MAX).unwrap(), minutes);
+        assert_eq!(archive.records(1, 0, u64::MAX).unwrap(), tens);
+
+        // The first sample of minute 30 completes minute 29
+        for second in 1530..1801 {
+            archive.append(&sample(second)).unwrap();
+        }
+        let tens = archive.records(1, 0, u64::MAX).unwrap();
+        assert_eq!(tens.len(), 3);
+        assert_eq!(tens[2].samples, 600);
+        let _ = fs::remove_dir_all(&config.directory);
+    }
+
+    #[test]
+    fn test_clock_step_back() {
+        let config = config("step-back", 100);
+        let mut archive = SampleArchive::open(&config).unwrap();
+        for second in 0..180 {
+            archive.append(&sample(second)).unwrap();
+        }
+        // Clock set back into the first minute, which was already stored
+        for second in 30..40 {
+            archive.append(&sample(second)).unwrap();
+        }
+        assert_eq!(archive.dropped(), 10);
+        assert_eq!(archive.log().len(), 190);
+        // Back in the window being aggregated
+        archive.append(&sample(150)).unwrap();
+        assert_eq!(archive.dropped(), 10);
+
+        let minutes = archive.records(0, 0, u64::MAX).unwrap();
+        assert_eq!(minutes[0].samples, 60);
+        assert_eq!(minutes[2].samples, 61);
+
+        // Recovery does not aggregate the samples again
+        drop(archive);
+        let mut archive = SampleArchive::open(&config).unwrap();
+        assert_eq!(archive.records(0, 0, u64::MAX).unwrap(), minutes);
+        let _ = fs::remove_dir_all(&config.directory);
+    }
+
+    #[test]
+    fn test_suspect_samples() {
+        let config = config("suspect", 100);
+        let mut archive = SampleArchive::open(&config).unwrap();
+        for second in 1..60 {
+            let mut sample = sample(second);
+            if second == 30 {
+                sample.quality.insert(SampleQuality::SUSPECT);
+                sample.counts = [0; 3];
+                sample.interval_ms = 0;
+            }
+            archive.append(&sample).unwrap();
+        }
+        assert_eq!(archive.log().len(), 59);
+        assert_eq!(archive.dropped(), 0);
+        let minutes = archive.records(0, 0, u64::MAX).unwrap();
+        assert_eq!(minutes[0].samples, 58);
+        assert_eq!(minutes[0].min[0], 1.0);
+        let _ = fs::remove_dir_all(&config.directory);
+    }
+}
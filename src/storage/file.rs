@@ -0,0 +1,67 @@
+//! Atomic File Replacement
+//!
+//! Files holding state across restarts are never written in place. The new
+//! contents go to a temporary file next to the target, which is synced and
+//! renamed over the target. The directory is synced after the rename, so the
+//! new file is in place once the write returns, and an interrupted write
+//! leaves the previous file intact.
+
+use crate::{CounterError, CounterResult};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Replaces the file at `path` with `data`
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> CounterResult<()> {
+    replace(path, |file| file.write_all(data))
+}
+
+/// Replaces the file at `path` with a new file filled by `fill`
+pub(crate) fn replace<F>(path: &Path, fill: F) -> CounterResult<()>
+where
+    F: FnOnce(&mut File) -> io::Result<()>,
+{
+    let temp = path.with_extension("tmp");
+    let write = || -> io::Result<()> {
+        let mut file = File::create(&temp)?;
+        fill(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)?;
+        sync_directory(path)
+    };
+    write().map_err(|error| CounterError::file_error(path, &error))
+}
+
+// Persists the directory entry of `path`
+fn sync_directory(path: &Path) -> io::Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(directory)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn test_write_atomic() {
+        let path = temp_path("atomic.bin");
+        write_atomic(&path, &[1, 2, 3]).unwrap();
+        write_atomic(&path, &[4, 5]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![4, 5]);
+        assert!(!path.with_extension("tmp").exists());
+        let _ = fs::remove_file(&path);
+
+        let missing = temp_path("atomic").join("missing.bin");
+        assert_eq!(
+            write_atomic(&missing, &[1]),
+            Err(CounterError::file_error(
+                &missing,
+                &io::Error::from(io::ErrorKind::NotFound)
+            ))
+        );
+    }
+}
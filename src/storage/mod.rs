@@ -0,0 +1,10 @@
+mod archive;
+mod file;
+mod query;
+mod ring;
+mod sample_log;
+
+pub use crate::storage::archive::*;
+pub(crate) use crate::storage::file::write_atomic;
+pub use crate::storage::query::*;
+pub use crate::storage::sample_log::*;
@@ -0,0 +1,287 @@
+//! Sample Queries
+//!
+//! Time-range queries over the [`SampleLog`]. The log keeps an in-memory
+//! index of the UTC time of every record in sequence order, so a query
+//! locates the first record of its range with a binary search and reads only
+//! the records inside the range from disk. The search relies on UTC times
+//! increasing with the sequence number. The log detects records appended with
+//! an earlier UTC time than the previous one, e.g. after the clock was set,
+//! and queries then scan the whole index instead, see
+//! [`SampleLog::is_time_ordered`]. Records are returned in sequence order.
+
+use crate::storage::sample_log::{LogRecord, SampleLog};
+use crate::{CounterError, CounterResult};
+use std::collections::BTreeMap;
+
+/// Aggregate of the samples in one window
+#[derive(Clone, Debug, PartialEq)]
+pub struct AggregateWindow {
+    /// Start of the window, UTC (ms)
+    pub start_ms: u64,
+    /// End of the window, exclusive, UTC (ms)
+    pub end_ms: u64,
+    /// Number of samples in the window
+    pub samples: usize,
+    /// Total integration time of the samples (ms)
+    pub live_ms: u64,
+    /// Total counts
+    pub sum: u64,
+    /// Mean count rate (cps)
+    pub mean: f64,
+    /// Highest count rate of a single sample (cps)
+    pub max: f64,
+}
+
+impl SampleLog {
+    /// Samples
+    ///
+    /// Returns the records from `start_ms` up to, but excluding, `end_ms`,
+    /// keeping every `decimation`-th record of the range.
+    ///
+    /// # Arguments
+    /// `start_ms` - Start of the range, UTC (ms)
+    /// `end_ms` - End of the range, UTC (ms)
+    /// `decimation` - Keep one record in this many, 1 keeps all
+    pub fn samples(
+        &mut self,
+        start_ms: u64,
+        end_ms: u64,
+        decimation: usize,
+    ) -> CounterResult<Vec<LogRecord>> {
+        let mut records = Vec::new();
+        for slot in self
+            .range(start_ms, end_ms)
+            .into_iter()
+            .step_by(decimation.max(1))
+        {
+            if let Some(record) = self.read_slot(slot)? {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Aggregate
+    ///
+    /// Aggregates the counts of a tube, or of all three tubes, into windows
+    /// of fixed length starting at `start_ms`, in time order. Suspect samples,
+    /// which carry no counts, and windows without samples are left out. Fails
+    /// for a tube which does not exist.
+    ///
+    /// # Arguments
+    /// `start_ms` - Start of the range, UTC (ms)
+    /// `end_ms` - End of the range, UTC (ms)
+    /// `tube` - Tube index, or `None` for the sum of the three tubes
+    /// `window_ms` - Length of a window (ms)
+    pub fn aggregate(
+        &mut self,
+        start_ms: u64,
+        end_ms: u64,
+        tube: Option<usize>,
+        window_ms: u64,
+    ) -> CounterResult<Vec<AggregateWindow>> {
+        if matches!(tube, Some(tube) if tube >= 3) {
+            return Err(CounterError::GenericError);
+        }
+        let window_ms = window_ms.max(1);
+        let mut windows: BTreeMap<u64, AggregateWindow> = BTreeMap::new();
+        for slot in self.range(start_ms, end_ms) {
+            let sample = match self.read_slot(slot)? {
+                Some(record) if record.sample.quality.is_usable() => record.sample,
+                _ => continue,
+            };
+            let counts = match tube {
+                Some(tube) => u64::from(sample.counts[tube]),
+                None => u64::from(sample.total_counts()),
+            };
+            let window_start = start_ms + (sample.utc_ms - start_ms) / window_ms * window_ms;
+
+            let window = windows
+                .entry(window_start)
+                .or_insert_with(|| AggregateWindow {
+                    start_ms: window_start,
+                    end_ms: (window_start + window_ms).min(end_ms),
+                    samples: 0,
+                    live_ms: 0,
+                    sum: 0,
+                    mean: 0.0,
+                    max: 0.0,
+                });
+            window.samples += 1;
+            window.live_ms += u64::from(sample.interval_ms);
+            window.sum += counts;
+            if sample.interval_ms > 0 {
+                window.max = window.max.max(counts as f64 / sample.interval_secs());
+            }
+        }
+
+        let mut windows: Vec<AggregateWindow> = windows.into_values().collect();
+        for window in windows.iter_mut() {
+            if window.live_ms > 0 {
+                window.mean = window.sum as f64 * 1000.0 / window.live_ms as f64;
+            }
+        }
+        Ok(windows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::objects::{CountSample, SampleQuality};
+    use crate::storage::sample_log::{SampleLog, SampleLogConfig};
+    use crate::test_util::{count_sample, temp_path};
+    use crate::CounterError;
+    use std::fs;
+
+    const START_MS: u64 = 1_600_000_000_000;
+
+    fn sample(sequence: u32) -> CountSample {
+        let counts = (sequence % 6) as u16 * 10;
+        let monotonic_ms = u64::from(sequence) * 10_000;
+        count_sample(
+            sequence,
+            monotonic_ms,
+            START_MS + monotonic_ms,
+            10_000,
+            [counts, 1, 2],
+        )
+    }
+
+    fn log(name: &str) -> (SampleLog, SampleLogConfig) {
+        let path = temp_path(&format!("query-{}.bin", name));
+        let config = SampleLogConfig::new(path, 20 + 2500 * 128);
+        let mut log = SampleLog::open(&config).unwrap();
+        // Over eight hours at ten second intervals, wrapping the log
+        for sequence in 0..3000 {
+            log.append(&sample(sequence)).unwrap();
+        }
+        (log, config)
+    }
+
+    #[test]
+    fn test_samples() {
+        let (mut log, config) = log("samples");
+        let records = log
+            .samples(START_MS + 29_900_000, START_MS + 30_000_000, 1)
+            .unwrap();
+        assert_eq!(records.len(), 10);
+        assert_eq!(records[0].sample, sample(2990));
+
+        let records = log
+            .samples(START_MS + 29_900_000, START_MS + 30_000_000, 4)
+            .unwrap();
+        let sequences: Vec<u32> = records
+            .iter()
+            .map(|record| record.sample.sequence)
+            .collect();
+        assert_eq!(sequences, vec![2990, 2994, 2998]);
+
+        // Records overwritten by the ring are gone
+        assert!(log
+            .samples(START_MS, START_MS + 60_000, 1)
+            .unwrap()
+            .is_empty());
+        let _ = fs::remove_file(&config.path);
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let (mut log, config) = log("aggregate");
+        let end_ms = START_MS + 30_000_000;
+        let start_ms = end_ms - 6 * 3_600_000;
+        let windows = log.aggregate(start_ms, end_ms, Some(0), 60_000).unwrap();
+        assert_eq!(windows.len(), 360);
+
+        // Six samples per minute with 0, 10, ... 50 counts
+        let window = &windows[0];
+        assert_eq!(window.start_ms, start_ms);
+        assert_eq!(window.end_ms, start_ms + 60_000);
+        assert_eq!(window.samples, 6);
+        assert_eq!(window.sum, 150);
+        assert_eq!(window.mean, 2.5);
+        assert_eq!(window.max, 5.0);
+
+        let windows = log.aggregate(start_ms, end_ms, None, 3_600_000).unwrap();
+        assert_eq!(windows.len(), 6);
+        assert_eq!(windows[5].sum, 360 * (25 + 3));
+        let _ = fs::remove_file(&config.path);
+    }
+
+    #[test]
+    fn test_aggregate_unknown_tube() {
+        let (mut log, config) = log("tube");
+        assert_eq!(
+            log.aggregate(START_MS, START_MS + 60_000, Some(3), 60_000),
+            Err(CounterError::GenericError)
+        );
+        let _ = fs::remove_file(&config.path);
+    }
+
+    #[test]
+    fn test_clock_step_back() {
+        let path = temp_path("query-step-back.bin");
+        let config = SampleLogConfig::new(path, 20 + 100 * 128);
+        let mut log = SampleLog::open(&config).unwrap();
+        for sequence in 0..10 {
+            log.append(&sample(sequence)).unwrap();
+        }
+        // Clock set back by a minute
+        for sequence in 10..20 {
+            let mut sample = sample(sequence);
+            sample.utc_ms -= 60_000;
+            log.append(&sample).unwrap();
+        }
+        assert!(!log.is_time_ordered());
+
+        // Both samples taken at START_MS + 50 s are found
+        let records = log
+            .samples(START_MS + 50_000, START_MS + 60_000, 1)
+            .unwrap();
+        let sequences: Vec<u32> = records
+            .iter()
+            .map(|record| record.sample.sequence)
+            .collect();
+        assert_eq!(sequences, vec![5, 11]);
+
+        let windows = log
+            .aggregate(START_MS, START_MS + 120_000, None, 60_000)
+            .unwrap();
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[0].samples, 6 + 2);
+        assert_eq!(windows[1].samples, 4 + 6);
+
+        // The index is ordered again once the ring has overwritten the step
+        let mut sequence = 20;
+        while !log.is_time_ordered() {
+            let mut sample = sample(sequence);
+            sample.utc_ms -= 60_000;
+            log.append(&sample).unwrap();
+            sequence += 1;
+        }
+        assert_eq!(sequence, 110);
+        let _ = fs::remove_file(&config.path);
+    }
+
+    #[test]
+    fn test_aggregate_skips_suspect_samples() {
+        let path = temp_path("query-suspect.bin");
+        let config = SampleLogConfig::new(path, 20 + 100 * 128);
+        let mut log = SampleLog::open(&config).unwrap();
+        for sequence in 1..6 {
+            let mut sample = sample(sequence);
+            if sequence == 3 {
+                sample.quality.insert(SampleQuality::SUSPECT);
+                sample.counts = [0; 3];
+                sample.interval_ms = 0;
+            }
+            log.append(&sample).unwrap();
+        }
+        let windows = log
+            .aggregate(START_MS, START_MS + 60_000, Some(0), 60_000)
+            .unwrap();
+        assert_eq!(windows[0].samples, 4);
+        assert_eq!(windows[0].sum, 10 + 20 + 40 + 50);
+        assert_eq!(windows[0].mean, 3.0);
+        let _ = fs::remove_file(&config.path);
+    }
+}
@@ -0,0 +1,358 @@
+//! Ring File
+//!
+//! Crash-safe ring buffer of records in a single file of fixed size, shared
+//! by the sample log and the archive levels. The file starts with a header
+//! describing the slot geometry, followed by equally sized slots each holding
+//! one record:
+//!
+//! | Offset | Size | Field                                   |
+//! |--------|------|-----------------------------------------|
+//! | 0      | 4    | CRC-32 of the rest of the record        |
+//! | 4      | 2    | Schema version of the payload           |
+//! | 6      | 2    | Payload length                          |
+//! | 8      | 8    | Sequence number                         |
+//! | 16     | 8    | UTC time of the record (ms)             |
+//! | 24     | n    | Payload                                 |
+//!
+//! All fields are big-endian. Once the file is full, the oldest record is
+//! overwritten. A record torn by a power loss fails its checksum and is
+//! treated as an empty slot, so on start-up the ring resumes after the valid
+//! record with the highest sequence number.
+//!
+//! UTC range lookups use a binary search while the UTC times of the records
+//! increase with their sequence number. Once the clock has stepped backwards
+//! they scan the whole index, until the ring has overwritten the records out
+//! of order.
+
+use crate::storage::file;
+use crate::{CounterError, CounterResult};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"RCSL";
+const FORMAT_VERSION: u16 = 1;
+pub(crate) const HEADER_SIZE: u64 = 20;
+const RECORD_HEADER_SIZE: usize = 24;
+// Payload lengths are stored as 16-bit values
+const MAX_SLOT_SIZE: u32 = RECORD_HEADER_SIZE as u32 + u16::MAX as u32;
+
+/// Location of a valid record
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct SlotEntry {
+    pub(crate) sequence: u64,
+    pub(crate) utc_ms: u64,
+}
+
+/// Payload of a valid record
+pub(crate) struct SlotRecord {
+    pub(crate) entry: SlotEntry,
+    pub(crate) schema_version: u16,
+    pub(crate) payload: Vec<u8>,
+}
+
+/// Fixed-size ring buffer of checksummed records on disk
+pub(crate) struct RingFile {
+    path: PathBuf,
+    file: File,
+    slot_size: u32,
+    slots: Vec<Option<SlotEntry>>,
+    index: VecDeque<(usize, SlotEntry)>,
+    // Number of consecutive records in the index with decreasing UTC times
+    steps_back: usize,
+    head: usize,
+    next_sequence: u64,
+}
+
+impl RingFile {
+    /// Open
+    ///
+    /// Opens the ring file, or creates it at its full size if it does not
+    /// exist, and recovers the position of the newest record.
+    ///
+    /// # Arguments
+    /// `name` - Name of the ring used in errors
+    /// `path` - Path of the file
+    /// `max_size` - Maximum size of the file (bytes)
+    /// `slot_size` - Size of a record slot (bytes), payload lengths are limited
+    /// to 65535 bytes
+    pub(crate) fn open(
+        name: &'static str,
+        path: &Path,
+        max_size: u64,
+        slot_size: u32,
+    ) -> CounterResult<Self> {
+        let slot_count = max_size.saturating_sub(HEADER_SIZE) / u64::from(slot_size.max(1));
+        if slot_count < 2
+            || slot_count > u64::from(u32::MAX)
+            || (slot_size as usize) <= RECORD_HEADER_SIZE
+            || slot_size > MAX_SLOT_SIZE
+        {
+            return Err(CounterError::GenericError);
+        }
+        if !path.exists() {
+            create(path, slot_size, slot_count as u32)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|error| CounterError::file_error(path, &error))?;
+        let mut header = [0u8; HEADER_SIZE as usize];
+        file.read_exact(&mut header)
+            .map_err(|error| CounterError::file_error(path, &error))?;
+        if &header[..4] != MAGIC
+            || u16::from_be_bytes([header[4], header[5]]) != FORMAT_VERSION
+            || be_u32(&header[8..12]) != slot_size
+            || u64::from(be_u32(&header[12..16])) != slot_count
+            || be_u32(&header[16..20]) != crc32fast::hash(&header[..16])
+        {
+            return Err(CounterError::parsing_failure(&format!("{} Header", name)));
+        }
+
+        let mut ring = RingFile {
+            path: path.to_path_buf(),
+            file,
+            slot_size,
+            slots: vec![None; slot_count as usize],
+            index: VecDeque::new(),
+            steps_back: 0,
+            head: 0,
+            next_sequence: 0,
+        };
+        ring.recover()?;
+        Ok(ring)
+    }
+
+    // Indexes the valid records and resumes after the newest one
+    fn recover(&mut self) -> CounterResult<()> {
+        let mut newest: Option<(usize, u64)> = None;
+        for slot in 0..self.slots.len() {
+            let data = self.read_slot_data(slot)?;
+            self.slots[slot] = validate(&data).map(|(entry, _, _)| entry);
+            if let Some(entry) = self.slots[slot] {
+                let newer = match newest {
+                    Some((_, sequence)) => entry.sequence > sequence,
+                    None => true,
+                };
+                if newer {
+                    newest = Some((slot, entry.sequence));
+                }
+            }
+        }
+        if let Some((slot, sequence)) = newest {
+            self.head = (slot + 1) % self.slots.len();
+            self.next_sequence = sequence + 1;
+        }
+
+        let mut index: Vec<(usize, SlotEntry)> = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, entry)| entry.map(|entry| (slot, entry)))
+            .collect();
+        index.sort_by_key(|(_, entry)| entry.sequence);
+        self.index = index.into();
+        self.count_steps_back();
+        Ok(())
+    }
+
+    fn count_steps_back(&mut self) {
+        self.steps_back = self
+            .index
+            .iter()
+            .zip(self.index.iter().skip(1))
+            .filter(|((_, older), (_, newer))| newer.utc_ms < older.utc_ms)
+            .count();
+    }
+
+    fn slot_offset(&self, slot: usize) -> u64 {
+        HEADER_SIZE + slot as u64 * u64::from(self.slot_size)
+    }
+
+    fn read_slot_data(&mut self, slot: usize) -> CounterResult<Vec<u8>> {
+        let mut data = vec![0u8; self.slot_size as usize];
+        let offset = self.slot_offset(slot);
+        let (file, path) = (&mut self.file, &self.path);
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut data))
+            .map_err(|error| CounterError::file_error(path, &error))?;
+        Ok(data)
+    }
+
+    /// Number of slots
+    pub(crate) fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Sequence number of the next record
+    pub(crate) fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    /// Slots of the valid records, oldest first
+    pub(crate) fn index(&self) -> &VecDeque<(usize, SlotEntry)> {
+        &self.index
+    }
+
+    /// Whether the UTC times of the records increase with the sequence number
+    pub(crate) fn is_time_ordered(&self) -> bool {
+        self.steps_back == 0
+    }
+
+    /// Slots of the records with `start_ms <= utc < end_ms`, oldest first
+    ///
+    /// # Arguments
+    /// `start_ms` - Start of the range, UTC (ms)
+    /// `end_ms` - End of the range, UTC (ms)
+    pub(crate) fn range(&self, start_ms: u64, end_ms: u64) -> Vec<usize> {
+        let in_range = |entry: &SlotEntry| entry.utc_ms >= start_ms && entry.utc_ms < end_ms;
+        if !self.is_time_ordered() {
+            return self
+                .index
+                .iter()
+                .filter(|(_, entry)| in_range(entry))
+                .map(|(slot, _)| *slot)
+                .collect();
+        }
+        let first = self
+            .index
+            .partition_point(|(_, entry)| entry.utc_ms < start_ms);
+        self.index
+            .range(first..)
+            .take_while(|(_, entry)| in_range(entry))
+            .map(|(slot, _)| *slot)
+            .collect()
+    }
+
+    /// Append
+    ///
+    /// Writes a record over the oldest slot and flushes it to disk. Returns
+    /// the sequence number of the record.
+    ///
+    /// # Arguments
+    /// `utc_ms` - UTC time of the record (ms)
+    /// `schema_version` - Schema version of the payload
+    /// `payload` - Encoded record
+    pub(crate) fn append(
+        &mut self,
+        utc_ms: u64,
+        schema_version: u16,
+        payload: &[u8],
+    ) -> CounterResult<u64> {
+        if payload.len() > self.slot_size as usize - RECORD_HEADER_SIZE {
+            return Err(CounterError::GenericError);
+        }
+
+        let sequence = self.next_sequence;
+        let mut data = vec![0u8; self.slot_size as usize];
+        data[4..6].copy_from_slice(&schema_version.to_be_bytes());
+        data[6..8].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+        data[8..16].copy_from_slice(&sequence.to_be_bytes());
+        data[16..24].copy_from_slice(&utc_ms.to_be_bytes());
+        data[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + payload.len()].copy_from_slice(payload);
+        let crc = crc32fast::hash(&data[4..RECORD_HEADER_SIZE + payload.len()]);
+        data[..4].copy_from_slice(&crc.to_be_bytes());
+
+        let slot = self.head;
+        // The slot no longer holds its old record, whatever happens below
+        if self.slots[slot].take().is_some() {
+            if self.index.front().map(|(oldest, _)| *oldest) == Some(slot) {
+                if let Some(((_, oldest), (_, next))) = self.index.front().zip(self.index.get(1)) {
+                    if next.utc_ms < oldest.utc_ms {
+                        self.steps_back -= 1;
+                    }
+                }
+                self.index.pop_front();
+            } else {
+                self.index.retain(|(indexed, _)| *indexed != slot);
+                self.count_steps_back();
+            }
+        }
+        let offset = self.slot_offset(slot);
+        let (file, path) = (&mut self.file, &self.path);
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.write_all(&data))
+            .and_then(|_| file.sync_data())
+            .map_err(|error| CounterError::file_error(path, &error))?;
+
+        let entry = SlotEntry { sequence, utc_ms };
+        if let Some((_, newest)) = self.index.back() {
+            if utc_ms < newest.utc_ms {
+                self.steps_back += 1;
+            }
+        }
+        self.slots[slot] = Some(entry);
+        self.index.push_back((slot, entry));
+        self.head = (slot + 1) % self.slots.len();
+        self.next_sequence += 1;
+        Ok(sequence)
+    }
+
+    /// Read
+    ///
+    /// Returns the record in a slot, or `None` if the slot is empty or torn.
+    ///
+    /// # Arguments
+    /// `slot` - Slot index
+    pub(crate) fn read(&mut self, slot: usize) -> CounterResult<Option<SlotRecord>> {
+        if self.slots[slot].is_none() {
+            return Ok(None);
+        }
+        let data = self.read_slot_data(slot)?;
+        Ok(
+            validate(&data).map(|(entry, schema_version, payload)| SlotRecord {
+                entry,
+                schema_version,
+                payload: payload.to_vec(),
+            }),
+        )
+    }
+}
+
+fn be_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+// Checks the record in a slot and splits it into index entry, schema
+// version and payload
+fn validate(data: &[u8]) -> Option<(SlotEntry, u16, &[u8])> {
+    let length = u16::from_be_bytes([data[6], data[7]]) as usize;
+    if RECORD_HEADER_SIZE + length > data.len()
+        || be_u32(&data[..4]) != crc32fast::hash(&data[4..RECORD_HEADER_SIZE + length])
+    {
+        return None;
+    }
+    let mut sequence = [0u8; 8];
+    sequence.copy_from_slice(&data[8..16]);
+    let mut utc_ms = [0u8; 8];
+    utc_ms.copy_from_slice(&data[16..24]);
+    Some((
+        SlotEntry {
+            sequence: u64::from_be_bytes(sequence),
+            utc_ms: u64::from_be_bytes(utc_ms),
+        },
+        u16::from_be_bytes([data[4], data[5]]),
+        &data[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + length],
+    ))
+}
+
+// Creates an empty ring at its full size. The file is only moved into place
+// once complete, so a power loss cannot leave a ring without a valid header.
+fn create(path: &Path, slot_size: u32, slot_count: u32) -> CounterResult<()> {
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+    header.extend_from_slice(&[0, 0]);
+    header.extend_from_slice(&slot_size.to_be_bytes());
+    header.extend_from_slice(&slot_count.to_be_bytes());
+    let crc = crc32fast::hash(&header);
+    header.extend_from_slice(&crc.to_be_bytes());
+
+    file::replace(path, |file| {
+        file.write_all(&header)?;
+        file.set_len(HEADER_SIZE + u64::from(slot_size) * u64::from(slot_count))
+    })
+}
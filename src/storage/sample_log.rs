@@ -0,0 +1,317 @@
+//! Sample Log
+//!
+//! Persistent ring buffer of [`CountSample`]s in a single file of fixed size,
+//! with one bincode encoded sample per record. Every record carries the
+//! schema version it was written with, a sequence number and a CRC-32, so a
+//! record torn by a power loss is detected and skipped, and the log resumes
+//! after the newest valid record on start-up.
+
+use crate::objects::CountSample;
+use crate::storage::ring::{RingFile, SlotEntry};
+use crate::CounterResult;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+/// Schema version of the records written by this version
+pub const SCHEMA_VERSION: u16 = 1;
+
+/// Sample log configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampleLogConfig {
+    /// Path of the log file
+    pub path: PathBuf,
+    /// Maximum size of the log file (bytes)
+    pub max_size: u64,
+    /// Size of a record slot (bytes), at most 65559
+    pub slot_size: u32,
+}
+
+impl SampleLogConfig {
+    /// Constructor
+    ///
+    /// Uses slots of 128 bytes, which hold a sample with room to spare.
+    ///
+    /// # Arguments
+    /// `path` - Path of the log file
+    /// `max_size` - Maximum size of the log file (bytes)
+    pub fn new<P: AsRef<Path>>(path: P, max_size: u64) -> Self {
+        SampleLogConfig {
+            path: path.as_ref().to_path_buf(),
+            max_size,
+            slot_size: 128,
+        }
+    }
+}
+
+/// Sample read back from the log
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogRecord {
+    /// Sequence number, incremented for every record appended
+    pub sequence: u64,
+    /// Schema version the record was written with
+    pub schema_version: u16,
+    /// Logged sample
+    pub sample: CountSample,
+}
+
+/// Crash-safe ring buffer of samples on disk
+pub struct SampleLog {
+    ring: RingFile,
+}
+
+impl SampleLog {
+    /// Open
+    ///
+    /// Opens the log file, or creates it at its full size if it does not
+    /// exist, and recovers the position of the newest record.
+    ///
+    /// # Arguments
+    /// `config` - Log configuration
+    pub fn open(config: &SampleLogConfig) -> CounterResult<Self> {
+        Ok(SampleLog {
+            ring: RingFile::open(
+                "Sample Log",
+                &config.path,
+                config.max_size,
+                config.slot_size,
+            )?,
+        })
+    }
+
+    /// Number of slots
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+
+    /// Number of valid records
+    pub fn len(&self) -> usize {
+        self.ring.index().len()
+    }
+
+    /// Whether the log holds no valid records
+    pub fn is_empty(&self) -> bool {
+        self.ring.index().is_empty()
+    }
+
+    /// Sequence number of the next record
+    pub fn next_sequence(&self) -> u64 {
+        self.ring.next_sequence()
+    }
+
+    /// Append
+    ///
+    /// Writes a sample over the oldest slot and flushes it to disk. Samples
+    /// are logged as read, suspect ones included, with their quality flags.
+    /// Returns the sequence number of the record.
+    ///
+    /// # Arguments
+    /// `sample` - Sample to log
+    pub fn append(&mut self, sample: &CountSample) -> CounterResult<u64> {
+        self.ring
+            .append(sample.utc_ms, SCHEMA_VERSION, &sample.to_bytes()?)
+    }
+
+    /// Read Slot
+    ///
+    /// Returns the record in a slot, or `None` if the slot is empty, torn or
+    /// holds a schema version this version cannot decode.
+    ///
+    /// # Arguments
+    /// `slot` - Slot index
+    pub(crate) fn read_slot(&mut self, slot: usize) -> CounterResult<Option<LogRecord>> {
+        Ok(self.ring.read(slot)?.and_then(|record| {
+            if record.schema_version != SCHEMA_VERSION {
+                return None;
+            }
+            CountSample::from_bytes(&record.payload)
+                .ok()
+                .map(|sample| LogRecord {
+                    sequence: record.entry.sequence,
+                    schema_version: record.schema_version,
+                    sample,
+                })
+        }))
+    }
+
+    /// Slots of the valid records, oldest first
+    pub(crate) fn index(&self) -> &VecDeque<(usize, SlotEntry)> {
+        self.ring.index()
+    }
+
+    /// Whether the UTC times of the records increase with their sequence
+    /// number, i.e. the clock has not stepped backwards while they were logged
+    ///
+    /// Time-range queries stay correct either way, but have to scan every
+    /// record while this is not the case.
+    pub fn is_time_ordered(&self) -> bool {
+        self.ring.is_time_ordered()
+    }
+
+    /// Slots of the records in a UTC range, oldest first
+    ///
+    /// # Arguments
+    /// `start_ms` - Start of the range, UTC (ms)
+    /// `end_ms` - End of the range, UTC (ms)
+    pub(crate) fn range(&self, start_ms: u64, end_ms: u64) -> Vec<usize> {
+        self.ring.range(start_ms, end_ms)
+    }
+
+    /// Records
+    ///
+    /// Reads all decodable records, oldest first.
+    pub fn records(&mut self) -> CounterResult<Vec<LogRecord>> {
+        let mut records = Vec::new();
+        let slots: Vec<usize> = self.index().iter().map(|(slot, _)| *slot).collect();
+        for slot in slots {
+            if let Some(record) = self.read_slot(slot)? {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SampleQuality;
+    use crate::storage::ring::HEADER_SIZE;
+    use crate::test_util::{count_sample, temp_path};
+    use crate::CounterError;
+    use std::fs::{self, OpenOptions};
+    use std::io::{Seek, SeekFrom, Write};
+
+    fn sample(sequence: u32) -> CountSample {
+        let monotonic_ms = u64::from(sequence) * 10_000;
+        let utc_ms = 1_600_000_000_000 + monotonic_ms;
+        count_sample(sequence, monotonic_ms, utc_ms, 10_000, [1, 2, 3])
+    }
+
+    fn config(name: &str, slots: u64) -> SampleLogConfig {
+        let path = temp_path(&format!("log-{}.bin", name));
+        SampleLogConfig::new(path, HEADER_SIZE + slots * 128)
+    }
+
+    #[test]
+    fn test_append_and_reopen() {
+        let config = config("reopen", 8);
+        let mut log = SampleLog::open(&config).unwrap();
+        assert!(log.is_empty());
+        assert_eq!(log.capacity(), 8);
+        assert_eq!(fs::metadata(&config.path).unwrap().len(), config.max_size);
+
+        for sequence in 0..3 {
+            assert_eq!(log.append(&sample(sequence)).unwrap(), u64::from(sequence));
+        }
+        drop(log);
+
+        let mut log = SampleLog::open(&config).unwrap();
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.next_sequence(), 3);
+        let records = log.records().unwrap();
+        assert_eq!(records[2].sample, sample(2));
+        assert_eq!(records[2].schema_version, SCHEMA_VERSION);
+        let _ = fs::remove_file(&config.path);
+    }
+
+    #[test]
+    fn test_quality_flags() {
+        let config = config("quality", 8);
+        let mut log = SampleLog::open(&config).unwrap();
+        let mut suspect = count_sample(0, 0, 1_600_000_000_000, 0, [0; 3]);
+        suspect.quality.insert(SampleQuality::SUSPECT);
+        let mut wrapped = sample(1);
+        wrapped.quality.insert(SampleQuality::WRAPPED);
+        wrapped.quality.insert(SampleQuality::RETRIED);
+        log.append(&suspect).unwrap();
+        log.append(&wrapped).unwrap();
+        drop(log);
+
+        let records = SampleLog::open(&config).unwrap().records().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].sample, suspect);
+        assert!(!records[0].sample.quality.is_usable());
+        assert_eq!(records[1].sample, wrapped);
+        assert!(records[1].sample.quality.is_usable());
+        let _ = fs::remove_file(&config.path);
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        let config = config("wrap", 4);
+        let mut log = SampleLog::open(&config).unwrap();
+        for sequence in 0..10 {
+            log.append(&sample(sequence)).unwrap();
+        }
+        drop(log);
+
+        let mut log = SampleLog::open(&config).unwrap();
+        let sequences: Vec<u64> = log
+            .records()
+            .unwrap()
+            .iter()
+            .map(|record| record.sequence)
+            .collect();
+        assert_eq!(sequences, vec![6, 7, 8, 9]);
+        assert_eq!(log.append(&sample(10)).unwrap(), 10);
+        let _ = fs::remove_file(&config.path);
+    }
+
+    #[test]
+    fn test_recover_torn_record() {
+        let config = config("torn", 4);
+        let mut log = SampleLog::open(&config).unwrap();
+        for sequence in 0..6 {
+            log.append(&sample(sequence)).unwrap();
+        }
+        drop(log);
+
+        // Power loss while overwriting slot 2, which held record 2
+        let mut file = OpenOptions::new().write(true).open(&config.path).unwrap();
+        file.seek(SeekFrom::Start(HEADER_SIZE + 2 * 128 + 30))
+            .unwrap();
+        file.write_all(&[0xff; 8]).unwrap();
+        drop(file);
+
+        let mut log = SampleLog::open(&config).unwrap();
+        let sequences: Vec<u64> = log
+            .records()
+            .unwrap()
+            .iter()
+            .map(|record| record.sequence)
+            .collect();
+        assert_eq!(sequences, vec![3, 4, 5]);
+        assert_eq!(log.append(&sample(6)).unwrap(), 6);
+        let _ = fs::remove_file(&config.path);
+    }
+
+    #[test]
+    fn test_invalid_configuration() {
+        let config = config("invalid", 4);
+        SampleLog::open(&config).unwrap();
+
+        let mut resized = config.clone();
+        resized.max_size *= 2;
+        assert_eq!(
+            SampleLog::open(&resized).err(),
+            Some(CounterError::parsing_failure("Sample Log Header"))
+        );
+
+        let mut small = config.clone();
+        small.max_size = HEADER_SIZE + 128;
+        assert_eq!(
+            SampleLog::open(&small).err(),
+            Some(CounterError::GenericError)
+        );
+
+        // Payload lengths past 65535 bytes do not fit the record header
+        let mut large = SampleLogConfig::new(temp_path("log-large.bin"), 1 << 20);
+        large.slot_size = 65_560;
+        assert_eq!(
+            SampleLog::open(&large).err(),
+            Some(CounterError::GenericError)
+        );
+        assert!(!large.path.exists());
+        let _ = fs::remove_file(&config.path);
+    }
+}
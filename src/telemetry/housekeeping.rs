@@ -0,0 +1,139 @@
+//! Board Housekeeping Telemetry
+//!
+//! This module provides the enum, commands and parsers necessary for working
+//! with the housekeeping telemetry of the radiation counter PCB.
+//!
+//! All channels are read with the Get Board Housekeeping Telemetry command,
+//! with the data byte selecting the channel. The command byte and channel
+//! selectors differ between firmware revisions and can be changed through
+//! [`HousekeepingOpcodes`].
+
+use crate::commands::opcodes;
+use crate::radiation_counter::RadiationCounter;
+use crate::CounterResult;
+use i2c_rs::Command;
+use serde::*;
+use std::time::Duration;
+
+const TELEM_CMD: u8 = opcodes::GET_BOARD_HOUSEKEEPING;
+
+// ADC reference voltage and full scale reading
+const ADC_VREF: f64 = 3.3;
+const ADC_FULL_SCALE: f64 = 4095.0;
+
+make_telemetry!(
+    /// Board Supply Voltage, measured behind a 1:2 divider
+    SupplyVoltage => {vec![opcodes::HK_SUPPLY_VOLTAGE], |d| d * ADC_VREF / ADC_FULL_SCALE * 2.0, "V"},
+    /// Board Supply Current, 0.5 mA per LSB
+    SupplyCurrent => {vec![opcodes::HK_SUPPLY_CURRENT], |d| d * 0.5, "mA"},
+    /// Microcontroller Temperature, 0.76 V at 25 degC with a 2.5 mV/degC slope
+    McuTemperature => {vec![opcodes::HK_MCU_TEMPERATURE], |d| (d * ADC_VREF / ADC_FULL_SCALE - 0.76) / 0.0025 + 25.0, "degC"},
+    /// Geiger Tube High Voltage Monitor, measured behind a 1:200 divider
+    TubeHighVoltage => {vec![opcodes::HK_TUBE_HIGH_VOLTAGE], |d| d * ADC_VREF / ADC_FULL_SCALE * 200.0, "V"},
+);
+
+/// Housekeeping command byte and channel selectors
+///
+/// Defaults to the values of the Interface Control Document. Boards running
+/// other firmware revisions may use different values.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HousekeepingOpcodes {
+    /// Command byte used to request a housekeeping channel
+    pub command: u8,
+    /// Supply voltage channel selector
+    pub supply_voltage: u8,
+    /// Supply current channel selector
+    pub supply_current: u8,
+    /// MCU temperature channel selector
+    pub mcu_temperature: u8,
+    /// Tube high voltage monitor channel selector
+    pub tube_high_voltage: u8,
+}
+
+impl Default for HousekeepingOpcodes {
+    fn default() -> Self {
+        HousekeepingOpcodes {
+            command: TELEM_CMD,
+            supply_voltage: opcodes::HK_SUPPLY_VOLTAGE,
+            supply_current: opcodes::HK_SUPPLY_CURRENT,
+            mcu_temperature: opcodes::HK_MCU_TEMPERATURE,
+            tube_high_voltage: opcodes::HK_TUBE_HIGH_VOLTAGE,
+        }
+    }
+}
+
+impl HousekeepingOpcodes {
+    /// Helper function storing telemetry command information
+    ///
+    /// # Arguments
+    ///
+    /// `telem_type` - `Type` of telemetry to return command for
+    pub fn command(&self, telem_type: Type) -> (Command, usize) {
+        let channel = match telem_type {
+            Type::SupplyVoltage => self.supply_voltage,
+            Type::SupplyCurrent => self.supply_current,
+            Type::McuTemperature => self.mcu_temperature,
+            Type::TubeHighVoltage => self.tube_high_voltage,
+        };
+        (
+            Command {
+                cmd: self.command,
+                data: vec![channel],
+            },
+            2,
+        )
+    }
+}
+
+impl Telemetry for RadiationCounter {
+    fn get_telemetry(&self, telem_type: Type) -> CounterResult<f64> {
+        let (command, rx_len) = self.housekeeping_opcodes().command(telem_type);
+        parse(
+            &self.transfer(command, rx_len, Duration::from_millis(3))?,
+            telem_type,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_opcodes() {
+        let opcodes = HousekeepingOpcodes::default();
+        for telem_type in &[
+            Type::SupplyVoltage,
+            Type::SupplyCurrent,
+            Type::McuTemperature,
+            Type::TubeHighVoltage,
+        ] {
+            assert_eq!(opcodes.command(*telem_type), command(*telem_type));
+        }
+    }
+
+    #[test]
+    fn test_custom_opcodes() {
+        let opcodes = HousekeepingOpcodes {
+            command: 0x45,
+            tube_high_voltage: 0x07,
+            ..Default::default()
+        };
+        assert_eq!(
+            opcodes.command(Type::TubeHighVoltage),
+            (
+                Command {
+                    cmd: 0x45,
+                    data: vec![0x07],
+                },
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(&[0x00, 0x0A], Type::SupplyCurrent), Ok(5.0));
+        assert_eq!(unit(Type::SupplyCurrent), "mA");
+    }
+}
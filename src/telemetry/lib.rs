@@ -1,14 +1,18 @@
-// use crate::{CounterError, CounterResult};
+use crate::{CounterError, CounterResult};
 // pub use crate::commands::last_error::*;
 
-/// Macro for generating `Type` enum, `parse` and `command` functions
-/// for telemetry items.
+/// Macro for generating `Type` enum, `parse`, `unit` and `command` functions
+/// and the `Telemetry` trait for telemetry items.
+///
+/// Each item is given as `Name => {data, parser, unit}`, where `data` is the
+/// data sent along with `TELEM_CMD`, `parser` converts the raw ADC reading
+/// into engineering units and `unit` names those units.
 #[macro_export]
 macro_rules! make_telemetry {
     (
         $(
             $(#[$meta:meta])+
-            $type: ident => {$data: expr, $parser: expr},
+            $type: ident => {$data: expr, $parser: expr, $unit: expr},
         )+
     ) => {
 
@@ -21,18 +25,29 @@ macro_rules! make_telemetry {
             )+
         }
 
-        // /// Telemetry parsing function
-        // ///
-        // /// # Arguments
-        // ///
-        // /// `data` - Raw telemetry data from eps
-        // /// `telem_type` - `Type` of telemetry to parse
-        // pub fn parse(data: &[u8], telem_type: Type) -> CounterResult<f64> {
-        //     let adc_data = get_adc_result(data)?;
-        //     Ok(match telem_type {
-        //         $(Type::$type => $parser(adc_data),)+
-        //     })
-        // }
+        /// Telemetry parsing function
+        ///
+        /// # Arguments
+        ///
+        /// `data` - Raw telemetry data from radiation counter
+        /// `telem_type` - `Type` of telemetry to parse
+        pub fn parse(data: &[u8], telem_type: Type) -> $crate::CounterResult<f64> {
+            let adc_data = $crate::get_adc_result(data)?;
+            Ok(match telem_type {
+                $(Type::$type => $parser(adc_data),)+
+            })
+        }
+
+        /// Engineering units of a telemetry item
+        ///
+        /// # Arguments
+        ///
+        /// `telem_type` - `Type` of telemetry to return units for
+        pub fn unit(telem_type: Type) -> &'static str {
+            match telem_type {
+                $(Type::$type => $unit,)+
+            }
+        }
 
         /// Helper function storing telemetry command information
         ///
@@ -50,40 +65,62 @@ macro_rules! make_telemetry {
                 2
             )
         }
+
+        /// Retrieval of the telemetry items in this set
+        pub trait Telemetry {
+            /// Get Telemetry
+            ///
+            /// Requests a single telemetry item and returns it in engineering units.
+            ///
+            /// # Arguments
+            ///
+            /// `telem_type` - `Type` of telemetry to request
+            fn get_telemetry(&self, telem_type: Type) -> $crate::CounterResult<f64>;
+        }
     }
 }
 
-// pub fn get_adc_result(data: &[u8]) -> CounterResult<f64> {
-//     if data.len() < 2 {
-//         Err(CounterError::parsing_failure("ADC Result"))
-//     } else {
-//         let be_val = u16::from(data[0]) | u16::from(data[1]) << 8;
-//         let native_val = u16::from_be(be_val);
-//         Ok(f64::from(native_val))
-//     }
-// }
+/// Extracts the raw ADC reading from a telemetry reply
+///
+/// # Arguments
+///
+/// `data` - Raw telemetry data from radiation counter, transmitted big-endian
+pub fn get_adc_result(data: &[u8]) -> CounterResult<f64> {
+    if data.len() < 2 {
+        Err(CounterError::parsing_failure("ADC Result"))
+    } else {
+        Ok(f64::from(u16::from_be_bytes([data[0], data[1]])))
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    // #[test]
-    // fn test_adcs_result() {
-    //     let raw = vec![0x01, 0x23];
-    //     let adc = get_adc_result(&raw).unwrap();
+    #[test]
+    fn test_adcs_result() {
+        let raw = vec![0x01, 0x23];
+        let adc = get_adc_result(&raw).unwrap();
+
+        assert_eq!(adc, 291.0);
+    }
 
-    //     // Test assumes native endianess is little endian
-    //     assert_eq!(adc, 291.0);
-    // }
+    #[test]
+    fn test_adcs_result_bad_data_len() {
+        assert_eq!(
+            get_adc_result(&[0x01]),
+            Err(CounterError::parsing_failure("ADC Result"))
+        );
+    }
 
     #[test]
     fn test_make_telemetry() {
-        use rust_i2c::Command;
+        use i2c_rs::Command;
         const TELEM_CMD: u8 = 0x00;
 
         make_telemetry!(
             /// TestValue1
-            TestVal1 => {vec![0xE1], |d| (10.0 * d) - 10.0},
+            TestVal1 => {vec![0xE1], |d| (10.0 * d) - 10.0, "mV"},
         );
 
         assert_eq!(
@@ -96,6 +133,21 @@ mod tests {
                 2
             )
         );
-        // assert_eq!(parse(&vec![0x01, 0x23], Type::TestVal1), Ok(2900.0));
+        assert_eq!(parse(&[0x01, 0x23], Type::TestVal1), Ok(2900.0));
+        assert_eq!(unit(Type::TestVal1), "mV");
+
+        struct Fixed(Vec<u8>);
+
+        impl Telemetry for Fixed {
+            fn get_telemetry(&self, telem_type: Type) -> CounterResult<f64> {
+                let (_command, rx_len) = command(telem_type);
+                parse(&self.0[..rx_len], telem_type)
+            }
+        }
+
+        assert_eq!(
+            Fixed(vec![0x00, 0x02]).get_telemetry(Type::TestVal1),
+            Ok(10.0)
+        );
     }
 }
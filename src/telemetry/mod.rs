@@ -1,5 +1,6 @@
 #[macro_use]
 pub mod lib;
 
+pub mod housekeeping;
 pub mod reset;
 //pub mod counter;
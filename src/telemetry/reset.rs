@@ -4,17 +4,22 @@
 //! with reset telemetry from the radiation counter.
 //!
 //! The macro `make_reset_telemetry!` is responsibly for generating the enum `Type`,
-//! and the `command` function.
+//! and the `command` and `parse` functions. The items themselves, the reply
+//! length and the delay are listed in the Interface Control Document
+//! `icd/commands.toml`.
 
-use crate::{CounterError, CounterResult};
 use i2c_rs::Command;
 // use rust_i2c::Command;
 
-/// Macro for generating `ResetType` enum and `command` function
+/// Macro for generating `ResetType` enum and `command` and `parse` functions
 /// for reset telemetry items.
+///
+/// The items are preceded by the reply length and the delay before reading
+/// back the reply, in milliseconds.
 #[macro_export]
 macro_rules! make_reset_telemetry {
     (
+        $rx_len: expr, $delay_ms: expr;
         $(
             $(#[$meta:meta])+
                 $type: ident => $cmd: expr,
@@ -38,7 +43,7 @@ macro_rules! make_reset_telemetry {
         /// # Arguments
         ///
         /// `telem_type` - `Type` of telemetry to return command for
-        pub fn command(reset_type: Type) -> (Command, usize) {
+        pub fn command(reset_type: Type) -> (Command, usize, std::time::Duration) {
             (
                 Command {
                     cmd: match reset_type {
@@ -46,50 +51,39 @@ macro_rules! make_reset_telemetry {
                     },
                     data: vec![0x00],
                 },
-                4,
+                $rx_len,
+                std::time::Duration::from_millis($delay_ms),
             )
         }
-    }
-}
 
-make_reset_telemetry!(
-    /// Get Number of Brown-out Resets
-    BrownOut => 0x31,
-    /// Get Number of Automatic Software Resets
-    /// If the on-board microcontroller has experienced a malfunction, such as being stuck
-    /// in a loop, it will reset itself into a pre-defined initial state.
-    AutomaticSoftware => 0x32,
-    /// Get Number of Manual Resets
-    /// This is the count of the number of times the device has been manually reset using
-    /// the Reset command.
-    Manual => 0x33,
-    /// Get Number of Communications Watchdog Resets
-    /// The device will reset itself if it does not receive any
-    /// data via i2c for a predefined length of time. The communications node keeps a count
-    /// of the number of times such an event has taken place.
-    Watchdog => 0x34,
-);
-
-/// Parses ResetTelemetry message
-///
-/// # Arguments
-///
-/// `data` - Data received from Radiation Counter
-pub fn parse(data: &[u8]) -> CounterResult<u8> {
-    if data.len() == 2 {
-        Ok(data[1])
-    } else {
-        Err(CounterError::parsing_failure("Reset Telemetry"))
+        /// Parses ResetTelemetry message
+        ///
+        /// # Arguments
+        ///
+        /// `data` - Data received from Radiation Counter
+        pub fn parse(data: &[u8]) -> $crate::CounterResult<u8> {
+            if data.len() == $rx_len {
+                Ok(data[1])
+            } else {
+                Err($crate::CounterError::parsing_failure("Reset Telemetry"))
+            }
+        }
     }
 }
 
+// The reset telemetry items are generated from the ICD
+include!(concat!(env!("OUT_DIR"), "/reset_telemetry.rs"));
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::CounterError;
+    use std::time::Duration;
 
     #[test]
     fn test_make_reset_telemetry() {
         make_reset_telemetry!(
+            2, 5;
             /// TestValue1
             TestVal1 => 0x30,
         );
@@ -101,9 +95,11 @@ mod tests {
                     cmd: 0x30,
                     data: vec![0x00],
                 },
-                4,
+                2,
+                Duration::from_millis(5),
             )
         );
+        assert_eq!(parse(&[0x00, 0x07]), Ok(7));
     }
 
     #[test]
@@ -0,0 +1,101 @@
+//! Test Utilities
+//!
+//! Mock hardware, sample builders and temporary files shared by the unit tests.
+
+use crate::analysis::{CalibratedSample, Measurement, TubeRate};
+use crate::objects::{CountSample, RCHk, SampleQuality};
+use crate::profile::CompatibilityProfile;
+use crate::radiation_counter::RadiationCounter;
+use i2c_rs::{Command, Connection, Stream};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Board answering through a bus which, like I2C, always returns the
+/// requested number of bytes, reading 0xFF past the end of the reply
+pub(crate) struct MockStream {
+    /// Reply of the board to a command byte
+    pub(crate) reply: fn(u8) -> Vec<u8>,
+}
+
+impl Stream for MockStream {
+    fn path(&self) -> &str {
+        "/dev/i2c-mock"
+    }
+    fn addr(&self) -> u16 {
+        0x40
+    }
+    fn write(&self, _command: Command) -> io::Result<()> {
+        Ok(())
+    }
+    fn read(&self, command: Command, rx_len: usize) -> io::Result<Vec<u8>> {
+        self.transfer(command, rx_len, Duration::default())
+    }
+    fn transfer(&self, command: Command, rx_len: usize, _delay: Duration) -> io::Result<Vec<u8>> {
+        let mut data = (self.reply)(command.cmd);
+        data.resize(rx_len, 0xFF);
+        Ok(data)
+    }
+}
+
+/// Driver of a mock board, with the legacy profile but no inter-command delay
+pub(crate) fn mock_counter(reply: fn(u8) -> Vec<u8>) -> RadiationCounter {
+    let mut profile = CompatibilityProfile::legacy();
+    profile.inter_command_delay = Duration::default();
+    RadiationCounter::with_profile(Connection::new(Box::new(MockStream { reply })), profile)
+}
+
+/// Nominal sample with all raw readings at zero
+pub(crate) fn count_sample(
+    sequence: u32,
+    monotonic_ms: u64,
+    utc_ms: u64,
+    interval_ms: u32,
+    counts: [u16; 3],
+) -> CountSample {
+    CountSample {
+        sequence,
+        monotonic_ms,
+        utc_ms,
+        interval_ms,
+        reading: RCHk {
+            rc1_reading: 0,
+            rc2_reading: 0,
+            rc3_reading: 0,
+        },
+        counts,
+        quality: SampleQuality::NOMINAL,
+    }
+}
+
+/// Reliable tube rate without uncertainties or dead-time correction
+pub(crate) fn tube_rate(counts: u16, rate: f64, flux: f64) -> TubeRate {
+    TubeRate {
+        counts,
+        rate: Measurement::new(rate, 0.0),
+        corrected_rate: Measurement::new(rate, 0.0),
+        flux: Measurement::new(flux, 0.0),
+        reliable: true,
+    }
+}
+
+/// Nominal calibrated sample, with the same monotonic and UTC time and the
+/// counts of the tube rates
+pub(crate) fn calibrated(utc_ms: u64, interval_ms: u32, tubes: [TubeRate; 3]) -> CalibratedSample {
+    let counts = [tubes[0].counts, tubes[1].counts, tubes[2].counts];
+    CalibratedSample {
+        sample: count_sample(0, utc_ms, utc_ms, interval_ms, counts),
+        tubes,
+    }
+}
+
+/// Path in the temporary directory unique to this test process, with any file
+/// or directory left at it by an earlier run removed
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("radiation-counter-{}-{}", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_dir_all(&path);
+    path
+}